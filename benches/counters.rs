@@ -18,6 +18,43 @@ fn criterion_benchmark(c: &mut Criterion) {
         let mut ret = [0; 256];
         b.iter(|| count::multi_bucket_count_u8(&book1, &mut ret))
     });
+    c.bench_function("parallel lanes count", |b| {
+        let mut ret = [0; 256];
+        b.iter(|| count::parallel_lanes_count_u8(&book1, &mut ret))
+    });
+    #[cfg(feature = "portable_simd")]
+    c.bench_function("simd count", |b| {
+        let mut ret = [0; 256];
+        b.iter(|| count::simd_count_u8(&book1, &mut ret))
+    });
+    c.bench_function("dispatch count", |b| {
+        let mut ret = [0; 256];
+        b.iter(|| count::count_u8(&book1, &mut ret))
+    });
+    c.bench_function("dispatch count small", |b| {
+        let mut ret = [0; 256];
+        b.iter(|| count::count_u8(&book1[..1024], &mut ret))
+    });
+    let book1_u16 = book1
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect::<Vec<u16>>();
+    c.bench_function("simple count u16", |b| {
+        b.iter(|| count::simple_count_u16(&book1_u16, u16::MAX as usize))
+    });
+    c.bench_function("multi_bucket count u16", |b| {
+        b.iter(|| count::multi_bucket_count_u16(&book1_u16, u16::MAX as usize))
+    });
+    #[cfg(feature = "rayon")]
+    c.bench_function("rayon count u16", |b| {
+        b.iter(|| {
+            count::divide_and_conquer_count_u16(
+                &book1_u16,
+                std::thread::available_parallelism().unwrap().get(),
+                u16::MAX as usize,
+            )
+        })
+    });
     #[cfg(feature = "rayon")]
     c.bench_function("rayon count", |b| {
         b.iter(|| {
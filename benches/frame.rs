@@ -0,0 +1,35 @@
+use std::{fs::File, io::Read};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use final_state_rs::frame::{self, FrameConfig};
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let mut book1 = vec![];
+    File::open("./rsc/calgary_book1")
+        .expect("Cannot find calgary book1 ressource")
+        .read_to_end(&mut book1)
+        .expect("Unexpected fail to read calgary book1 ressource");
+    let config = FrameConfig {
+        block_size: 64 << 10,
+        ..Default::default()
+    };
+
+    c.bench_function("encode frame", |b| {
+        b.iter(|| frame::encode_frame(&book1, &config))
+    });
+    let encoded = frame::encode_frame(&book1, &config);
+    c.bench_function("decode frame", |b| b.iter(|| frame::decode_frame(&encoded)));
+    #[cfg(feature = "rayon")]
+    {
+        c.bench_function("rayon encode frame", |b| {
+            b.iter(|| frame::par_encode_frame(&book1, &config))
+        });
+        let encoded = frame::par_encode_frame(&book1, &config);
+        c.bench_function("rayon decode frame", |b| {
+            b.iter(|| frame::par_decode_frame(&encoded))
+        });
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
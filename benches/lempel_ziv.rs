@@ -1,5 +1,7 @@
 use criterion::{criterion_group, criterion_main, Criterion};
+use final_state_rs::binary_tree::BinaryTreeMatchFinder;
 use final_state_rs::lempel_ziv::*;
+use final_state_rs::lzw::encode_lzw;
 
 fn criterion_benchmark(c: &mut Criterion) {
     use std::fs::File;
@@ -56,6 +58,25 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("lzss with a dict 10k", |b| {
         b.iter(|| encode_lz_with_hashmap_u8(book1_10k))
     });
+
+    c.bench_function("lzss with a binary tree", |b| {
+        b.iter(|| encode_lz_with_binary_tree_u8(book1_extract))
+    });
+
+    c.bench_function("lzss with a binary tree 10k", |b| {
+        b.iter(|| encode_lz_with_binary_tree_u8(book1_10k))
+    });
+
+    c.bench_function("lzw 10k", |b| b.iter(|| encode_lzw(book1_10k, 12)));
+
+    c.bench_function("binary tree all matches 10k", |b| {
+        b.iter(|| {
+            let mut finder = BinaryTreeMatchFinder::new(book1_10k, usize::MAX, 256, 273);
+            (0..book1_10k.len())
+                .map(|index| finder.find_matches(index).len())
+                .sum::<usize>()
+        })
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
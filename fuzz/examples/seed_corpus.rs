@@ -0,0 +1,119 @@
+//! Build the seed corpus of the fuzz targets from the files in `rsc/`.
+//!
+//! ```sh
+//! cargo run --example seed_corpus
+//! ```
+//!
+//! The inputs are written in `fuzz/corpus/<target>/`, where `cargo fuzz run`
+//! looks for them.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use final_state_rs::{
+    bwt::encode_bwt_tans,
+    count::Histogram,
+    frame::{encode_frame, Coder, FrameConfig},
+    lempel_ziv::{encode_lz_with_hashmap_u8, internal_encode_lz_with_hashmap_u8, Faster},
+    lz_stream::{LzStreamEncoder, MAX_WINDOW_SIZE},
+    lzw::encode_lzw,
+    normalization::normalization_with_compensation_binary_heap,
+    r_ans::encode_rans,
+    seekable::encode_seekable,
+    spreads::fse_spread_unsorted,
+    t_ans::encode_tans,
+};
+use final_state_rs_fuzz::{lz_config, write_rans_case, write_tans_case};
+
+const SLICE_SIZES: [usize; 3] = [64, 1000, 20_000];
+const TABLE_LOG: usize = 11;
+
+fn root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).to_path_buf()
+}
+
+fn write_seed(target: &str, name: &str, data: &[u8]) {
+    let dir = root().join("corpus").join(target);
+    fs::create_dir_all(&dir).expect("Cannot create the corpus directory");
+    fs::write(dir.join(name), data).expect("Cannot write a seed");
+}
+
+/// Copy the files of `rsc/<dir>` with the given extension.
+fn copy_seeds(target: &str, dir: &str, extension: &str) {
+    let dir = root().join("../rsc").join(dir);
+    for entry in fs::read_dir(&dir).expect("Cannot read the ressources") {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == extension) {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            write_seed(target, name, &fs::read(&path).unwrap());
+        }
+    }
+}
+
+fn main() {
+    copy_seeds("decode_zstd", "zstd", "zst");
+    copy_seeds("decode_deflate", "deflate", "gz");
+    copy_seeds("decode_lz4", "lz4", "block");
+
+    let book1 =
+        fs::read(root().join("../rsc/calgary_book1")).expect("Cannot find calgary book1 ressource");
+    for size in SLICE_SIZES {
+        let src = &book1[..size];
+        let name = format!("book1_{size}");
+
+        for coder in [Coder::Tans, Coder::Rans] {
+            let config = FrameConfig {
+                coder,
+                ..Default::default()
+            };
+            let name = format!("{name}_{coder:?}").to_lowercase();
+            write_seed("decode_frame", &name, &encode_frame(src, &config));
+        }
+        let seekable = encode_seekable(src, size.div_ceil(4), &FrameConfig::default());
+        write_seed("decode_seekable", &name, &seekable);
+
+        let mut lz = vec![0];
+        lz.extend(encode_lz_with_hashmap_u8(src));
+        write_seed("decode_lz", &name, &lz);
+        let mut lz = vec![3];
+        lz.extend(internal_encode_lz_with_hashmap_u8::<Faster>(
+            src,
+            &[],
+            &lz_config(3),
+        ));
+        write_seed("decode_lz", &format!("{name}_short_rep"), &lz);
+
+        let hist = Histogram::from_source(src);
+        let norm = normalization_with_compensation_binary_heap(
+            hist.counts(),
+            TABLE_LOG,
+            hist.max_symbol(),
+        )
+        .unwrap();
+        let spread = fse_spread_unsorted(&norm, TABLE_LOG);
+        let mut state = 1 << TABLE_LOG;
+        let (stream, state) = encode_tans(src, &norm, &spread, TABLE_LOG, &mut state);
+        let case = write_tans_case(TABLE_LOG, &norm, state, src.len(), &stream);
+        write_seed("decode_tans", &name, &case);
+        let (state, bits, stream) = encode_rans(&norm, TABLE_LOG, src);
+        let case = write_rans_case(TABLE_LOG, &norm, state, src.len(), &bits, &stream);
+        write_seed("decode_rans", &name, &case);
+
+        write_seed("decode_bwt", &name, &encode_bwt_tans(src));
+        let mut lzw = vec![3];
+        lzw.extend(encode_lzw(src, 12));
+        write_seed("decode_lzw", &name, &lzw);
+        let mut encoder = LzStreamEncoder::new(MAX_WINDOW_SIZE);
+        let mut lz_stream = encoder.write(src);
+        lz_stream.append(&mut encoder.finish());
+        write_seed("decode_lz_stream", &name, &lz_stream);
+
+        for target in ["round_trip_ans", "round_trip_lz", "round_trip_frame"] {
+            let mut case = vec![0];
+            case.extend_from_slice(src);
+            write_seed(target, &name, &case);
+        }
+    }
+}
@@ -0,0 +1,11 @@
+#![no_main]
+
+use final_state_rs::bwt::decode_bwt_tans_with_max_size;
+use final_state_rs_fuzz::MAX_SIZE;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(decoded) = decode_bwt_tans_with_max_size(data, MAX_SIZE) {
+        assert!(decoded.len() <= MAX_SIZE);
+    }
+});
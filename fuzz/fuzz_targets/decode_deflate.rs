@@ -0,0 +1,14 @@
+#![no_main]
+
+use final_state_rs::deflate::{decode_deflate_with_max_size, decode_gzip_with_max_size};
+use final_state_rs_fuzz::MAX_SIZE;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(decoded) = decode_deflate_with_max_size(data, MAX_SIZE) {
+        assert!(decoded.len() <= MAX_SIZE);
+    }
+    if let Ok(decoded) = decode_gzip_with_max_size(data, MAX_SIZE) {
+        assert!(decoded.len() <= MAX_SIZE);
+    }
+});
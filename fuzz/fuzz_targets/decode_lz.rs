@@ -0,0 +1,15 @@
+#![no_main]
+
+use final_state_rs::lempel_ziv::try_decode_lz_u8_with_config;
+use final_state_rs_fuzz::{lz_config, MAX_SIZE};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((selector, src)) = data.split_first() else {
+        return;
+    };
+    let config = lz_config(*selector);
+    if let Ok(decoded) = try_decode_lz_u8_with_config(src, &[], &config, MAX_SIZE) {
+        assert!(decoded.len() <= MAX_SIZE);
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use final_state_rs::lz4::decode_lz4_block;
+use final_state_rs_fuzz::MAX_SIZE;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(decoded) = decode_lz4_block(data, MAX_SIZE) {
+        assert!(decoded.len() <= MAX_SIZE);
+    }
+});
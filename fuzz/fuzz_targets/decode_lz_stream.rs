@@ -0,0 +1,22 @@
+#![no_main]
+
+use final_state_rs::lz_stream::{LzStreamDecoder, MAX_WINDOW_SIZE};
+use final_state_rs_fuzz::MAX_SIZE;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Une paire de 4 octets produit jusqu'à 32767 octets, le flux est donné
+    // par morceaux pour borner la sortie.
+    let mut decoder = LzStreamDecoder::new(MAX_WINDOW_SIZE);
+    let mut size = 0;
+    for chunk in data.chunks(64) {
+        match decoder.write(chunk) {
+            Ok(decoded) => size += decoded.len(),
+            Err(_) => return,
+        }
+        if size > MAX_SIZE {
+            return;
+        }
+    }
+    let _ = decoder.finish();
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use final_state_rs::lzw::decode_lzw_with_max_size;
+use final_state_rs_fuzz::MAX_SIZE;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((selector, src)) = data.split_first() else {
+        return;
+    };
+    if let Ok(decoded) = decode_lzw_with_max_size(src, 9 + selector % 8, MAX_SIZE) {
+        assert!(decoded.len() <= MAX_SIZE);
+    }
+});
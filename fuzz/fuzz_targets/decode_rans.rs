@@ -0,0 +1,27 @@
+#![no_main]
+
+use final_state_rs::r_ans::try_decode_rans;
+use final_state_rs_fuzz::Reader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = Reader::new(data);
+    let Some((table_log, norm)) = reader.table() else {
+        return;
+    };
+    let (Some(state), Some(len), Some(nb_flushes)) = (reader.u32(), reader.u16(), reader.u16())
+    else {
+        return;
+    };
+    let Some(bits) = reader.bytes(nb_flushes) else {
+        return;
+    };
+    let _ = try_decode_rans(
+        state,
+        bits.to_vec(),
+        reader.rest().to_vec(),
+        &norm,
+        table_log,
+        len,
+    );
+});
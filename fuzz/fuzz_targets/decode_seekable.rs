@@ -0,0 +1,27 @@
+#![no_main]
+
+use final_state_rs::seekable::SeekableReader;
+use final_state_rs_fuzz::MAX_SIZE;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(reader) = SeekableReader::new(data) else {
+        return;
+    };
+    // Un index forgé peut annoncer des frames de plusieurs gigaoctets, seules
+    // les frames d'au plus MAX_SIZE octets sont décodées.
+    let small = |frame: usize| reader.frame_range(frame).len() <= MAX_SIZE;
+    for frame in (0..reader.nb_frames()).filter(|frame| small(*frame)) {
+        let range = reader.frame_range(frame);
+        if let Ok(decoded) = reader.read_frame(frame) {
+            assert_eq!(decoded.len(), range.len());
+            assert_eq!(reader.read_range(range.start, range.len()), Ok(decoded));
+        }
+        // Une plage à cheval sur deux frames.
+        if frame + 1 < reader.nb_frames() && small(frame + 1) {
+            if let Ok(decoded) = reader.read_range(range.end - 1, 2) {
+                assert_eq!(decoded.len(), 2);
+            }
+        }
+    }
+});
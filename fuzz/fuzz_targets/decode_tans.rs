@@ -0,0 +1,25 @@
+#![no_main]
+
+use final_state_rs::{spreads::fse_spread_unsorted, t_ans::try_decode_tans};
+use final_state_rs_fuzz::Reader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = Reader::new(data);
+    let Some((table_log, norm)) = reader.table() else {
+        return;
+    };
+    let (Some(state), Some(len)) = (reader.u16(), reader.u16()) else {
+        return;
+    };
+    let spread = fse_spread_unsorted(&norm, table_log);
+    let mut decoded = vec![0; len];
+    let _ = try_decode_tans(
+        reader.rest().to_vec(),
+        &norm,
+        &spread,
+        table_log,
+        state,
+        &mut decoded,
+    );
+});
@@ -0,0 +1,31 @@
+#![no_main]
+
+use final_state_rs::{
+    bitstream::BitDstream, r_ans::decode_rans_terminated, spreads::fse_spread_unsorted,
+    t_ans::decode_tans_terminated,
+};
+use final_state_rs_fuzz::{Reader, MAX_SIZE};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = Reader::new(data);
+    let Some((table_log, norm)) = reader.table() else {
+        return;
+    };
+    let spread = fse_spread_unsorted(&norm, table_log);
+    let rest = reader.rest().to_vec();
+
+    // Tous les flux concaténés, jusqu'à la première erreur.
+    if let Ok(mut dstream) = BitDstream::try_from(rest.clone()) {
+        while decode_tans_terminated(&mut dstream, &norm, &spread, table_log, MAX_SIZE)
+            .is_ok_and(|decoded| decoded.len() <= MAX_SIZE)
+            && dstream.next_stream().is_ok()
+        {}
+    }
+    if let Ok(mut dstream) = BitDstream::try_from(rest) {
+        while decode_rans_terminated(&mut dstream, &norm, table_log, MAX_SIZE)
+            .is_ok_and(|decoded| decoded.len() <= MAX_SIZE)
+            && dstream.next_stream().is_ok()
+        {}
+    }
+});
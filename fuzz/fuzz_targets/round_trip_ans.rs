@@ -0,0 +1,34 @@
+#![no_main]
+
+use final_state_rs::{
+    count::Histogram,
+    normalization::normalization_with_compensation_binary_heap,
+    r_ans::{encode_rans, try_decode_rans},
+    spreads::fse_spread_unsorted,
+    t_ans::{encode_tans, try_decode_tans},
+};
+use libfuzzer_sys::fuzz_target;
+
+const TABLE_LOG: usize = 11;
+
+fuzz_target!(|data: &[u8]| {
+    let hist = Histogram::from_source(data);
+    // Une source vide ou d'un seul symbole n'a pas de table, la frame les
+    // écrit autrement.
+    let Ok(norm) =
+        normalization_with_compensation_binary_heap(hist.counts(), TABLE_LOG, hist.max_symbol())
+    else {
+        return;
+    };
+
+    let spread = fse_spread_unsorted(&norm, TABLE_LOG);
+    let mut state = 1 << TABLE_LOG;
+    let (stream, state) = encode_tans(data, &norm, &spread, TABLE_LOG, &mut state);
+    let mut decoded = vec![0; data.len()];
+    try_decode_tans(stream, &norm, &spread, TABLE_LOG, state, &mut decoded).unwrap();
+    assert_eq!(decoded, data);
+
+    let (state, bits, stream) = encode_rans(&norm, TABLE_LOG, data);
+    let decoded = try_decode_rans(state, bits, stream, &norm, TABLE_LOG, data.len()).unwrap();
+    assert_eq!(decoded, data);
+});
@@ -0,0 +1,46 @@
+#![no_main]
+
+use final_state_rs::{
+    bwt::{decode_bwt_tans, encode_bwt_tans},
+    deflate::{decode_deflate, decode_gzip, encode_deflate, encode_gzip},
+    filters::Filter,
+    frame::{decode_frame, encode_frame, Checksum, Coder, FrameConfig},
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((selector, src)) = data.split_first() else {
+        return;
+    };
+    let config = FrameConfig {
+        coder: if selector & 1 == 0 {
+            Coder::Tans
+        } else {
+            Coder::Rans
+        },
+        block_size: 1 << (8 + (selector >> 1) % 8),
+        split: selector & 0x10 != 0,
+        filters: match selector >> 5 {
+            0 => vec![],
+            1 => vec![Filter::Delta(1)],
+            2 => vec![Filter::Shuffle(4)],
+            3 => vec![Filter::ZigZag(2), Filter::Delta(2)],
+            _ => vec![Filter::Delta(4), Filter::Shuffle(4)],
+        },
+        block_checksum: match src.len() % 3 {
+            0 => Checksum::None,
+            1 => Checksum::Crc32c,
+            _ => Checksum::Xxh64,
+        },
+        frame_checksum: match src.len() % 2 {
+            0 => Checksum::None,
+            _ => Checksum::Xxh64,
+        },
+        ..Default::default()
+    };
+    assert_eq!(decode_frame(&encode_frame(src, &config)).unwrap(), src);
+
+    assert_eq!(decode_bwt_tans(&encode_bwt_tans(src)).unwrap(), src);
+    assert_eq!(decode_deflate(&encode_deflate(src)).unwrap(), src);
+    assert_eq!(decode_gzip(&encode_gzip(src)).unwrap(), src);
+});
@@ -0,0 +1,52 @@
+#![no_main]
+
+use final_state_rs::{
+    lempel_ziv::*,
+    lz4::{decode_lz4_block, encode_lz4_block},
+    lz_stream::{LzStreamDecoder, LzStreamEncoder},
+    lzw::{decode_lzw, encode_lzw},
+};
+use final_state_rs_fuzz::lz_config;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((selector, src)) = data.split_first() else {
+        return;
+    };
+
+    assert_eq!(decode_lz_u8(&encode_lz_with_hashmap_u8(src)), src);
+    assert_eq!(decode_lz_u8(&encode_lz_no_windows_u8(src)), src);
+    // La fenêtre doit être plus petite que la source.
+    let window_size = 1 + *selector as usize;
+    if window_size < src.len() {
+        assert_eq!(decode_lz_u8(&encode_lempel_ziv_u8(src, window_size)), src);
+    }
+    let config = lz_config(*selector);
+    // Les index des paires sont limités à `2^index_bits`.
+    if src.len() <= 1 << config.index_bits {
+        let encoded = internal_encode_lz_with_hashmap_u8::<Faster>(src, &[], &config);
+        assert_eq!(decode_lz_u8_with_config(&encoded, &[], &config), src);
+    }
+
+    let window_size = window_size * 64;
+    let mut encoder = LzStreamEncoder::new(window_size);
+    let mut encoded = vec![];
+    for chunk in src.chunks(1 + *selector as usize) {
+        encoded.append(&mut encoder.write(chunk));
+    }
+    encoded.append(&mut encoder.finish());
+    let mut decoder = LzStreamDecoder::new(window_size);
+    let mut decoded = decoder.write(&encoded).unwrap();
+    decoded.append(&mut decoder.finish().unwrap());
+    assert_eq!(decoded, src);
+
+    assert_eq!(
+        decode_lz4_block(&encode_lz4_block(src), src.len()).unwrap(),
+        src
+    );
+    let max_bits = 9 + selector % 8;
+    assert_eq!(
+        decode_lzw(&encode_lzw(src, max_bits), max_bits).unwrap(),
+        src
+    );
+});
@@ -0,0 +1,147 @@
+//! Helpers shared by the fuzz targets and the `seed_corpus` example.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! The decoders of `t_ans` and `r_ans` need a table and a state beside the
+//! stream. Their targets read them at the beginning of the input, integers in
+//! little-endian:
+//! - the table: table_log (u8), max symbol (u8), then the normalized
+//!   histogram until the max symbol (u16 per symbol), like in a frame,
+//! - `decode_tans`: the table, the state (u16), the number of symbols (u16)
+//!   and the stream,
+//! - `decode_rans`: the table, the state (u32), the number of symbols (u16),
+//!   the number of flushes (u16), the number of bits of each flush (u8) and
+//!   the stream.
+//! - `decode_terminated`: the table and concatenated streams, decoded by
+//!   `decode_tans_terminated` and by `decode_rans_terminated`.
+
+/// Maximum size of a decoded input, a forged size shouldn't make the target
+/// allocate more.
+pub const MAX_SIZE: usize = 1 << 22;
+/// Bounds of the table log, `fse_spread_unsorted` needs at least 3 bits.
+pub const MIN_TABLE_LOG: usize = 5;
+pub const MAX_TABLE_LOG: usize = 15;
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    pub fn bytes(&mut self, size: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(size)?)?;
+        self.pos += size;
+        Some(bytes)
+    }
+
+    pub fn u8(&mut self) -> Option<usize> {
+        Some(self.bytes(1)?[0] as usize)
+    }
+
+    pub fn u16(&mut self) -> Option<usize> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()) as usize)
+    }
+
+    pub fn u32(&mut self) -> Option<usize> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()) as usize)
+    }
+
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.pos..];
+        self.pos = self.data.len();
+        rest
+    }
+
+    /// Read a table, return the table log and the normalized histogram. The
+    /// table has to be valid for `fse_spread_unsorted`, the decoders check
+    /// the rest.
+    pub fn table(&mut self) -> Option<(usize, Vec<usize>)> {
+        let table_log = self.u8()?;
+        let max_symbol = self.u8()?;
+        let norm = (0..=max_symbol)
+            .map(|_| self.u16())
+            .collect::<Option<Vec<usize>>>()?;
+        if !(MIN_TABLE_LOG..=MAX_TABLE_LOG).contains(&table_log)
+            || norm.iter().sum::<usize>() != 1 << table_log
+        {
+            return None;
+        }
+        Some((table_log, norm))
+    }
+}
+
+pub fn write_table(table_log: usize, norm: &[usize], ret: &mut Vec<u8>) {
+    ret.push(table_log as u8);
+    ret.push((norm.len() - 1) as u8);
+    for count in norm.iter() {
+        ret.extend_from_slice(&(*count as u16).to_le_bytes());
+    }
+}
+
+/// Input of the target `decode_tans`.
+pub fn write_tans_case(
+    table_log: usize,
+    norm: &[usize],
+    state: usize,
+    len: usize,
+    stream: &[u8],
+) -> Vec<u8> {
+    let mut ret = vec![];
+    write_table(table_log, norm, &mut ret);
+    ret.extend_from_slice(&(state as u16).to_le_bytes());
+    ret.extend_from_slice(&(len as u16).to_le_bytes());
+    ret.extend_from_slice(stream);
+    ret
+}
+
+/// Input of the target `decode_rans`.
+pub fn write_rans_case(
+    table_log: usize,
+    norm: &[usize],
+    state: usize,
+    len: usize,
+    nb_bits: &[u8],
+    stream: &[u8],
+) -> Vec<u8> {
+    let mut ret = vec![];
+    write_table(table_log, norm, &mut ret);
+    ret.extend_from_slice(&(state as u32).to_le_bytes());
+    ret.extend_from_slice(&(len as u16).to_le_bytes());
+    ret.extend_from_slice(&(nb_bits.len() as u16).to_le_bytes());
+    ret.extend_from_slice(nb_bits);
+    ret.extend_from_slice(stream);
+    ret
+}
+
+/// Configurations of `lempel_ziv` tried by the lz targets, chosen by the first
+/// byte of the input.
+pub fn lz_config(selector: u8) -> final_state_rs::lempel_ziv::LzConfig {
+    use final_state_rs::lempel_ziv::LzConfig;
+    let short = LzConfig {
+        min_match: 4,
+        max_match: 127,
+        index_bits: 15,
+        ..Default::default()
+    };
+    match selector % 4 {
+        0 => LzConfig::default(),
+        1 => LzConfig {
+            rep_codes: true,
+            ..Default::default()
+        },
+        2 => short,
+        _ => LzConfig {
+            rep_codes: true,
+            ..short
+        },
+    }
+}
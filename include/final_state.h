@@ -0,0 +1,156 @@
+/* Generated from src/ffi.rs by tests/ffi.rs, don't edit it. */
+
+#ifndef FINAL_STATE_H
+#define FINAL_STATE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/** Smallest table log accepted by `fs_tans_build` and `fs_rans_build`. */
+#define FS_MIN_TABLE_LOG 5
+
+/** Greatest table log accepted by `fs_tans_build` and `fs_rans_build`. */
+#define FS_MAX_TABLE_LOG 15
+
+/** Result of the functions of the interface. */
+typedef enum FsError {
+    /** Success. */
+    FS_OK = 0,
+    /** A pointer is null while its length isn't 0. */
+    FS_NULL_POINTER = 1,
+    /** The table log is out of [FS_MIN_TABLE_LOG, FS_MAX_TABLE_LOG]. */
+    FS_INVALID_TABLE_LOG = 2,
+    /**
+     * The histogram has more than 256 symbols, or less than two symbols
+     * with a count.
+     */
+    FS_INVALID_HISTOGRAM = 3,
+    /** The table hasn't been built. */
+    FS_NOT_BUILT = 4,
+    /** The source contains a symbol without count in the histogram. */
+    FS_INVALID_SYMBOL = 5,
+    /**
+     * The destination is too small, the required size is written in
+     * `dst_len`.
+     */
+    FS_DST_TOO_SMALL = 6,
+    /** The stream ends before the last symbol. */
+    FS_TRUNCATED = 7,
+    /** The state doesn't belong to the table. */
+    FS_INVALID_STATE = 8,
+    /** Unexpected panic of the library. */
+    FS_PANIC = 9,
+} FsError;
+
+/** Opaque tANS table. */
+typedef struct FsTansTable FsTansTable;
+
+/** Opaque rANS table. */
+typedef struct FsRansTable FsRansTable;
+
+/**
+ * Allocate an empty tANS table, to build with `fs_tans_build` and to free
+ * with `fs_tans_free`.
+ */
+FsTansTable *fs_tans_create(void);
+
+/**
+ * Build the table from the counts of `nb_symbols` symbols, a previous table
+ * is replaced.
+ *
+ * # Safety
+ *
+ * `table` comes from `fs_tans_create`, `counts` points to `nb_symbols`
+ * integers.
+ */
+FsError fs_tans_build(FsTansTable *table, const uint32_t *counts, size_t nb_symbols, uint32_t table_log);
+
+/**
+ * Encode `src_len` symbols in `dst`, write the size of the stream in
+ * `dst_len` and the final state, needed by the decoder, in `state`.
+ *
+ * # Safety
+ *
+ * `table` comes from `fs_tans_create`, `src` points to `src_len` bytes,
+ * `dst` to `dst_capacity` bytes, `dst_len` and `state` to an integer.
+ */
+FsError fs_tans_encode(FsTansTable *table, const uint8_t *src, size_t src_len, uint8_t *dst, size_t dst_capacity, size_t *dst_len, uint32_t *state);
+
+/**
+ * Decode `dst_len` symbols of a stream of `fs_tans_encode`, `state` is the
+ * final state of the encoder.
+ *
+ * # Safety
+ *
+ * `table` comes from `fs_tans_create`, `src` points to `src_len` bytes and
+ * `dst` to `dst_len` bytes.
+ */
+FsError fs_tans_decode(FsTansTable *table, const uint8_t *src, size_t src_len, uint32_t state, uint8_t *dst, size_t dst_len);
+
+/**
+ * Free a table of `fs_tans_create`, a null table is ignored.
+ *
+ * # Safety
+ *
+ * `table` comes from `fs_tans_create` and isn't used after.
+ */
+void fs_tans_free(FsTansTable *table);
+
+/**
+ * Allocate an empty rANS table, to build with `fs_rans_build` and to free
+ * with `fs_rans_free`.
+ */
+FsRansTable *fs_rans_create(void);
+
+/**
+ * Build the table from the counts of `nb_symbols` symbols, a previous table
+ * is replaced.
+ *
+ * # Safety
+ *
+ * `table` comes from `fs_rans_create`, `counts` points to `nb_symbols`
+ * integers.
+ */
+FsError fs_rans_build(FsRansTable *table, const uint32_t *counts, size_t nb_symbols, uint32_t table_log);
+
+/**
+ * Encode `src_len` symbols in `dst`, the sizes of the flushes followed by
+ * the stream. Write the size of the output in `dst_len` and the final
+ * state, needed by the decoder, in `state`.
+ *
+ * # Safety
+ *
+ * `table` comes from `fs_rans_create`, `src` points to `src_len` bytes,
+ * `dst` to `dst_capacity` bytes, `dst_len` and `state` to an integer.
+ */
+FsError fs_rans_encode(FsRansTable *table, const uint8_t *src, size_t src_len, uint8_t *dst, size_t dst_capacity, size_t *dst_len, uint32_t *state);
+
+/**
+ * Decode `dst_len` symbols of an output of `fs_rans_encode`, `state` is the
+ * final state of the encoder.
+ *
+ * # Safety
+ *
+ * `table` comes from `fs_rans_create`, `src` points to `src_len` bytes and
+ * `dst` to `dst_len` bytes.
+ */
+FsError fs_rans_decode(FsRansTable *table, const uint8_t *src, size_t src_len, uint32_t state, uint8_t *dst, size_t dst_len);
+
+/**
+ * Free a table of `fs_rans_create`, a null table is ignored.
+ *
+ * # Safety
+ *
+ * `table` comes from `fs_rans_create` and isn't used after.
+ */
+void fs_rans_free(FsRansTable *table);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* FINAL_STATE_H */
@@ -0,0 +1,106 @@
+//! Build target of `final_state_rs` without `std`.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! The crate is `no_std` and depends on `final_state_rs` without its default
+//! features, it doesn't build if the entropy coders need `std`. It builds on
+//! the host, or on a target without `std`:
+//!
+//! ```sh
+//! cargo build --manifest-path no_std/Cargo.toml --target thumbv7em-none-eabihf
+//! ```
+//!
+//! The test runs on the host only.
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use final_state_rs::{
+    count::Histogram,
+    normalization::{
+        normalization_with_compensation_binary_heap, normalization_with_fast_compensation,
+    },
+    r_ans::{encode_rans, try_decode_rans, RansError},
+    spreads::fse_spread_unsorted,
+    t_ans::{encode_tans, try_decode_tans, TansError},
+};
+
+pub const TABLE_LOG: usize = 10;
+
+/// Normalized histogram of a source, `None` if the source has less than two
+/// distinct symbols.
+pub fn normalize(src: &[u8]) -> Option<Vec<usize>> {
+    let hist = Histogram::from_source(src);
+    normalization_with_fast_compensation(hist.counts(), TABLE_LOG).ok()
+}
+
+/// Same as `normalize`, with the more accurate compensation of the binary
+/// heap.
+pub fn normalize_binary_heap(src: &[u8]) -> Option<Vec<usize>> {
+    let hist = Histogram::from_source(src);
+    normalization_with_compensation_binary_heap(hist.counts(), TABLE_LOG, hist.max_symbol()).ok()
+}
+
+/// Encode a source with tANS, return the stream and the final state.
+pub fn tans_encode(src: &[u8], norm: &[usize]) -> (Vec<u8>, usize) {
+    let spread = fse_spread_unsorted(norm, TABLE_LOG);
+    let mut state = 1 << TABLE_LOG;
+    encode_tans(src, norm, &spread, TABLE_LOG, &mut state)
+}
+
+/// Decode a stream of `tans_encode` in `dst`, as a firmware would do with a
+/// buffer of the size of the source.
+pub fn tans_decode(
+    stream: Vec<u8>,
+    norm: &[usize],
+    state: usize,
+    dst: &mut [u8],
+) -> Result<(), TansError> {
+    let spread = fse_spread_unsorted(norm, TABLE_LOG);
+    try_decode_tans(stream, norm, &spread, TABLE_LOG, state, dst)
+}
+
+/// Encode a source with rANS, return the final state, the size of the
+/// flushes and the stream.
+pub fn rans_encode(src: &[u8], norm: &[usize]) -> (usize, Vec<u8>, Vec<u8>) {
+    encode_rans(norm, TABLE_LOG, src)
+}
+
+pub fn rans_decode(
+    state: usize,
+    bits: Vec<u8>,
+    stream: Vec<u8>,
+    norm: &[usize],
+    len: usize,
+) -> Result<Vec<u8>, RansError> {
+    try_decode_rans(state, bits, stream, norm, TABLE_LOG, len)
+}
+
+#[test]
+fn no_std_round_trip() {
+    let src = b"Far from the madding crowd, far from the madding crowd.".repeat(20);
+    let norm = normalize(&src).unwrap();
+
+    let (stream, state) = tans_encode(&src, &norm);
+    let mut decoded = alloc::vec![0; src.len()];
+    tans_decode(stream, &norm, state, &mut decoded).unwrap();
+    assert_eq!(decoded, src);
+
+    let (state, bits, stream) = rans_encode(&src, &norm);
+    assert_eq!(
+        rans_decode(state, bits, stream, &norm, src.len()),
+        Ok(src.clone())
+    );
+
+    let norm = normalize_binary_heap(&src).unwrap();
+    assert_eq!(norm.iter().sum::<usize>(), 1 << TABLE_LOG);
+    let (stream, state) = tans_encode(&src, &norm);
+    tans_decode(stream, &norm, state, &mut decoded).unwrap();
+    assert_eq!(decoded, src);
+}
@@ -0,0 +1,437 @@
+//! Python bindings of the building blocks of `final_state_rs`.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! Each function keeps the name and the order of the arguments of the Rust
+//! function it wraps, the sources and the streams are `bytes` and the
+//! histograms are lists of integers. An invalid input raises a `ValueError`
+//! instead of a panic of the library.
+//!
+//! ```python
+//! import final_state as fs
+//!
+//! hist, max_symbol = fs.count_u8(src)
+//! norm = fs.normalization_with_compensation_binary_heap(hist, 11, max_symbol)
+//! spread = fs.fse_spread_unsorted(norm, 11)
+//! stream, state = fs.encode_tans(src, norm, spread, 11)
+//! assert fs.decode_tans(stream, norm, spread, 11, state, len(src)) == src
+//! ```
+
+use final_state_rs::{count, lempel_ziv, normalization, r_ans, spreads, t_ans};
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyBytes};
+
+/// Greatest table log of the bindings, the spreads allocate `2^table_log`
+/// states.
+const MAX_TABLE_LOG: usize = 20;
+
+/// Greatest number of symbols of `decode_tans`, the output is allocated
+/// before the decoding.
+const MAX_DECODED_LEN: usize = 1 << 30;
+
+fn value_error(err: impl std::fmt::Debug) -> PyErr {
+    PyValueError::new_err(format!("{err:?}"))
+}
+
+fn check_table_log(table_log: usize, min: usize, max: usize) -> PyResult<()> {
+    match (min..=max).contains(&table_log) {
+        true => Ok(()),
+        false => Err(value_error(format!(
+            "table_log {table_log} out of [{min}, {max}]"
+        ))),
+    }
+}
+
+/// A normalized histogram of at most 256 symbols that sums to
+/// `2^table_log`.
+fn check_norm(norm: &[usize], table_log: usize) -> PyResult<()> {
+    if norm.len() > 256 || norm.iter().sum::<usize>() != 1 << table_log {
+        return Err(value_error(format!(
+            "The histogram isn't normalized to 2^{table_log}"
+        )));
+    }
+    Ok(())
+}
+
+fn check_symbols(src: &[u8], norm: &[usize]) -> PyResult<()> {
+    match src
+        .iter()
+        .find(|symbol| norm.get(**symbol as usize).is_none_or(|count| *count == 0))
+    {
+        Some(symbol) => Err(value_error(format!("Symbol {symbol} not in the histogram"))),
+        None => Ok(()),
+    }
+}
+
+fn counted(
+    src: &[u8],
+    count: impl FnOnce(&[u8], &mut [usize; 256]) -> usize,
+) -> (Vec<usize>, usize) {
+    let mut hist = [0; 256];
+    let max_symbol = count(src, &mut hist);
+    (hist.to_vec(), max_symbol)
+}
+
+/// Count the bytes of `src`, returns the histogram of the 256 symbols and
+/// the greatest symbol.
+#[pyfunction]
+fn simple_count_u8(src: &[u8]) -> (Vec<usize>, usize) {
+    counted(src, count::simple_count_u8_inplace)
+}
+
+/// Same as `simple_count_u8`.
+#[pyfunction]
+fn multi_bucket_count_u8(src: &[u8]) -> (Vec<usize>, usize) {
+    counted(src, count::multi_bucket_count_u8)
+}
+
+/// Same as `simple_count_u8`.
+#[pyfunction]
+fn parallel_lanes_count_u8(src: &[u8]) -> (Vec<usize>, usize) {
+    counted(src, count::parallel_lanes_count_u8)
+}
+
+/// Same as `simple_count_u8`, with the counter the library picks for the
+/// size of the source.
+#[pyfunction]
+fn count_u8(src: &[u8]) -> (Vec<usize>, usize) {
+    counted(src, count::count_u8)
+}
+
+/// Same as `simple_count_u8`, `src` is counted in `split` parts in
+/// parallel.
+#[cfg(feature = "rayon")]
+#[pyfunction]
+fn divide_and_conquer_count(src: &[u8], split: usize) -> PyResult<(Vec<usize>, usize)> {
+    if split == 0 || split > src.len() {
+        return Err(value_error("split out of [1, len(src)]"));
+    }
+    let (hist, max_symbol) = count::divide_and_conquer_count(src, split);
+    Ok((hist.to_vec(), max_symbol))
+}
+
+fn normalized(
+    hist: &[usize],
+    table_log: usize,
+    normalize: impl FnOnce() -> Result<Vec<usize>, Box<normalization::NormError>>,
+) -> PyResult<Vec<usize>> {
+    check_table_log(table_log, 1, MAX_TABLE_LOG)?;
+    if hist.iter().all(|count| *count == 0) {
+        return Err(value_error("The histogram is empty"));
+    }
+    let norm = normalize().map_err(value_error)?;
+    // Une table trop petite pour le nombre de symboles donne une
+    // normalisation qui ne somme pas à 2^table_log.
+    if norm.iter().sum::<usize>() != 1 << table_log {
+        return Err(value_error(format!(
+            "The histogram can't be normalized on {table_log} bits"
+        )));
+    }
+    Ok(norm)
+}
+
+/// Normalize `hist` so that it sums to `2^table_log`.
+#[pyfunction]
+fn fast_normalization_1(hist: Vec<usize>, table_log: usize) -> PyResult<Vec<usize>> {
+    normalized(&hist, table_log, || {
+        normalization::fast_normalization_1(&hist, table_log)
+    })
+}
+
+/// Same as `fast_normalization_1`.
+#[pyfunction]
+fn normalization_with_fast_compensation(
+    hist: Vec<usize>,
+    table_log: usize,
+) -> PyResult<Vec<usize>> {
+    normalized(&hist, table_log, || {
+        normalization::normalization_with_fast_compensation(&hist, table_log)
+    })
+}
+
+/// Same as `fast_normalization_1`, `max_symbol` is the greatest symbol of
+/// the histogram.
+#[pyfunction]
+fn normalization_with_compensation_binary_heap(
+    hist: Vec<usize>,
+    table_log: usize,
+    max_symbol: usize,
+) -> PyResult<Vec<usize>> {
+    normalized(&hist, table_log, || {
+        normalization::normalization_with_compensation_binary_heap(&hist, table_log, max_symbol)
+    })
+}
+
+/// Same as `fast_normalization_1`.
+#[pyfunction]
+fn slow_normalization(hist: Vec<usize>, table_log: usize) -> PyResult<Vec<usize>> {
+    normalized(&hist, table_log, || {
+        normalization::slow_normalization(&hist, table_log)
+    })
+}
+
+/// Same as `normalization_with_compensation_binary_heap`, returns a new list
+/// instead of normalizing in place.
+#[pyfunction]
+fn zstd_normalization_1(
+    mut hist: Vec<usize>,
+    table_log: usize,
+    max_symbol: usize,
+) -> PyResult<Vec<usize>> {
+    // La normalisation de zstd travaille sur des tables d'au moins 32 états.
+    check_table_log(table_log, 5, MAX_TABLE_LOG)?;
+    normalized(&hist.clone(), table_log, || {
+        normalization::zstd_normalization_1_inplace(&mut hist, table_log, max_symbol)?;
+        Ok(hist)
+    })
+}
+
+fn spread<'py>(
+    py: Python<'py>,
+    norm: &[usize],
+    table_log: usize,
+    spread: impl FnOnce(&[usize], usize) -> Vec<u8>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    check_table_log(table_log, 3, MAX_TABLE_LOG)?;
+    check_norm(norm, table_log)?;
+    Ok(PyBytes::new(py, &spread(norm, table_log)))
+}
+
+/// Spread the normalized histogram `norm` over the `2^table_log` states of
+/// a tANS table. The histogram has to be sorted by decreasing counts.
+#[pyfunction]
+fn fse_spread<'py>(
+    py: Python<'py>,
+    norm: Vec<usize>,
+    table_log: usize,
+) -> PyResult<Bound<'py, PyBytes>> {
+    spread(py, &norm, table_log, spreads::fse_spread)
+}
+
+/// Same as `fse_spread`.
+#[pyfunction]
+fn fast_spread_2<'py>(
+    py: Python<'py>,
+    norm: Vec<usize>,
+    table_log: usize,
+) -> PyResult<Bound<'py, PyBytes>> {
+    spread(py, &norm, table_log, spreads::fast_spread_2)
+}
+
+/// Same as `fse_spread`.
+#[pyfunction]
+fn bit_reverse_spread<'py>(
+    py: Python<'py>,
+    norm: Vec<usize>,
+    table_log: usize,
+) -> PyResult<Bound<'py, PyBytes>> {
+    spread(py, &norm, table_log, spreads::bit_reverse_spread)
+}
+
+/// Same as `fse_spread`, the histogram doesn't need to be sorted.
+#[pyfunction]
+fn fse_spread_unsorted<'py>(
+    py: Python<'py>,
+    norm: Vec<usize>,
+    table_log: usize,
+) -> PyResult<Bound<'py, PyBytes>> {
+    spread(py, &norm, table_log, spreads::fse_spread_unsorted)
+}
+
+/// Same as `fse_spread_unsorted`, a count of `-1` is a symbol with less
+/// than one state.
+#[pyfunction]
+fn zstd_spread<'py>(
+    py: Python<'py>,
+    norm: Vec<i16>,
+    table_log: usize,
+) -> PyResult<Bound<'py, PyBytes>> {
+    check_table_log(table_log, 5, MAX_TABLE_LOG)?;
+    let states = norm
+        .iter()
+        .map(|count| match *count {
+            -1 => Ok(1),
+            count => usize::try_from(count).map_err(value_error),
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    check_norm(&states, table_log)?;
+    Ok(PyBytes::new(py, &spreads::zstd_spread(&norm, table_log)))
+}
+
+fn check_table(
+    norm: &[usize],
+    spread: &[u8],
+    table_log: usize,
+    max_table_log: usize,
+) -> PyResult<()> {
+    check_table_log(table_log, 1, max_table_log)?;
+    check_norm(norm, table_log)?;
+    if spread.len() != 1 << table_log {
+        return Err(value_error("The spread doesn't have 2^table_log states"));
+    }
+    Ok(())
+}
+
+/// Encode `src` with a tANS table, returns the stream and the final state
+/// to give to `decode_tans`.
+#[pyfunction]
+fn encode_tans<'py>(
+    py: Python<'py>,
+    src: &[u8],
+    norm: Vec<usize>,
+    spread: &[u8],
+    table_log: usize,
+) -> PyResult<(Bound<'py, PyBytes>, usize)> {
+    check_table(&norm, spread, table_log, MAX_TABLE_LOG)?;
+    check_symbols(src, &norm)?;
+    let mut state = 1 << table_log;
+    let (stream, state) = t_ans::encode_tans(src, &norm, spread, table_log, &mut state);
+    Ok((PyBytes::new(py, &stream), state))
+}
+
+/// Decode the `len` symbols of a stream of `encode_tans`, `len` is at most
+/// `2^30`.
+#[pyfunction]
+fn decode_tans<'py>(
+    py: Python<'py>,
+    stream: &[u8],
+    norm: Vec<usize>,
+    spread: &[u8],
+    table_log: usize,
+    state: usize,
+    len: usize,
+) -> PyResult<Bound<'py, PyBytes>> {
+    if len > MAX_DECODED_LEN {
+        return Err(value_error(format!(
+            "len {len} greater than {MAX_DECODED_LEN}"
+        )));
+    }
+    let mut dst = vec![0; len];
+    t_ans::try_decode_tans(stream.to_vec(), &norm, spread, table_log, state, &mut dst)
+        .map_err(value_error)?;
+    Ok(PyBytes::new(py, &dst))
+}
+
+/// Encode `src` with rANS, returns the final state, the number of bits of
+/// each flush of the state and the stream, to give to `decode_rans`.
+#[pyfunction]
+fn encode_rans<'py>(
+    py: Python<'py>,
+    norm: Vec<usize>,
+    table_log: usize,
+    src: &[u8],
+) -> PyResult<(usize, Bound<'py, PyBytes>, Bound<'py, PyBytes>)> {
+    // L'état de l'encodeur reste sous 2^32, voir `try_decode_rans`.
+    check_table_log(table_log, 1, 16)?;
+    check_norm(&norm, table_log)?;
+    check_symbols(src, &norm)?;
+    let (state, bits, stream) = r_ans::encode_rans(&norm, table_log, src);
+    Ok((state, PyBytes::new(py, &bits), PyBytes::new(py, &stream)))
+}
+
+/// Decode the `len` symbols of an output of `encode_rans`.
+#[pyfunction]
+fn decode_rans<'py>(
+    py: Python<'py>,
+    state: usize,
+    bits: &[u8],
+    stream: &[u8],
+    norm: Vec<usize>,
+    table_log: usize,
+    len: usize,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let decoded =
+        r_ans::try_decode_rans(state, bits.to_vec(), stream.to_vec(), &norm, table_log, len)
+            .map_err(value_error)?;
+    Ok(PyBytes::new(py, &decoded))
+}
+
+/// Encode `src` with pairs that reference the whole source.
+#[pyfunction]
+fn encode_lz_no_windows_u8<'py>(py: Python<'py>, src: &[u8]) -> Bound<'py, PyBytes> {
+    PyBytes::new(py, &lempel_ziv::encode_lz_no_windows_u8(src))
+}
+
+/// Encode `src` with pairs that reference the last `windows_size` symbols,
+/// `windows_size` is in `[1, len(src))`.
+#[pyfunction]
+fn encode_lempel_ziv_u8<'py>(
+    py: Python<'py>,
+    src: &[u8],
+    windows_size: usize,
+) -> PyResult<Bound<'py, PyBytes>> {
+    if windows_size == 0 || windows_size >= src.len() {
+        return Err(value_error(format!(
+            "windows_size {windows_size} out of [1, {})",
+            src.len()
+        )));
+    }
+    Ok(PyBytes::new(
+        py,
+        &lempel_ziv::encode_lempel_ziv_u8(src, windows_size),
+    ))
+}
+
+/// Same as `encode_lz_no_windows_u8`, the repetitions are found with a hash
+/// map.
+#[pyfunction]
+fn encode_lz_with_hashmap_u8<'py>(py: Python<'py>, src: &[u8]) -> Bound<'py, PyBytes> {
+    PyBytes::new(py, &lempel_ziv::encode_lz_with_hashmap_u8(src))
+}
+
+/// Same as `encode_lz_no_windows_u8`, the repetitions are found with a
+/// binary tree.
+#[pyfunction]
+fn encode_lz_with_binary_tree_u8<'py>(py: Python<'py>, src: &[u8]) -> Bound<'py, PyBytes> {
+    PyBytes::new(py, &lempel_ziv::encode_lz_with_binary_tree_u8(src))
+}
+
+/// Decode an output of the `encode_lz*` functions.
+#[pyfunction]
+fn decode_lz_u8<'py>(py: Python<'py>, src: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let config = lempel_ziv::LzConfig::default();
+    let decoded = lempel_ziv::try_decode_lz_u8_with_config(src, &[], &config, usize::MAX)
+        .map_err(value_error)?;
+    Ok(PyBytes::new(py, &decoded))
+}
+
+#[pymodule]
+fn final_state(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(simple_count_u8, m)?)?;
+    m.add_function(wrap_pyfunction!(multi_bucket_count_u8, m)?)?;
+    m.add_function(wrap_pyfunction!(parallel_lanes_count_u8, m)?)?;
+    m.add_function(wrap_pyfunction!(count_u8, m)?)?;
+    #[cfg(feature = "rayon")]
+    m.add_function(wrap_pyfunction!(divide_and_conquer_count, m)?)?;
+
+    m.add_function(wrap_pyfunction!(fast_normalization_1, m)?)?;
+    m.add_function(wrap_pyfunction!(normalization_with_fast_compensation, m)?)?;
+    m.add_function(wrap_pyfunction!(
+        normalization_with_compensation_binary_heap,
+        m
+    )?)?;
+    m.add_function(wrap_pyfunction!(slow_normalization, m)?)?;
+    m.add_function(wrap_pyfunction!(zstd_normalization_1, m)?)?;
+
+    m.add_function(wrap_pyfunction!(fse_spread, m)?)?;
+    m.add_function(wrap_pyfunction!(fast_spread_2, m)?)?;
+    m.add_function(wrap_pyfunction!(bit_reverse_spread, m)?)?;
+    m.add_function(wrap_pyfunction!(fse_spread_unsorted, m)?)?;
+    m.add_function(wrap_pyfunction!(zstd_spread, m)?)?;
+
+    m.add_function(wrap_pyfunction!(encode_tans, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_tans, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_rans, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_rans, m)?)?;
+
+    m.add_function(wrap_pyfunction!(encode_lz_no_windows_u8, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_lempel_ziv_u8, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_lz_with_hashmap_u8, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_lz_with_binary_tree_u8, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_lz_u8, m)?)?;
+    Ok(())
+}
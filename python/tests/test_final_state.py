@@ -0,0 +1,119 @@
+"""Tests of the Python bindings, run after `maturin develop`:
+
+    python -m unittest discover -s tests
+"""
+
+import os
+import unittest
+
+import final_state as fs
+
+TABLE_LOG = 11
+BOOK1 = os.path.join(os.path.dirname(__file__), "..", "..", "rsc", "calgary_book1")
+
+NORMALIZATIONS = [
+    lambda hist, max_symbol: fs.fast_normalization_1(hist, TABLE_LOG),
+    lambda hist, max_symbol: fs.normalization_with_fast_compensation(hist, TABLE_LOG),
+    lambda hist, max_symbol: fs.normalization_with_compensation_binary_heap(
+        hist, TABLE_LOG, max_symbol
+    ),
+    lambda hist, max_symbol: fs.zstd_normalization_1(hist, TABLE_LOG, max_symbol),
+]
+
+SPREADS = [fs.fse_spread, fs.fast_spread_2, fs.bit_reverse_spread, fs.fse_spread_unsorted]
+
+
+def sorted_table(src):
+    """Map the symbols of `src` by decreasing counts, for the spreads that
+    need a sorted histogram."""
+    hist, _ = fs.simple_count_u8(src)
+    order = sorted(range(256), key=lambda symbol: -hist[symbol])
+    mapping = bytes(order.index(symbol) for symbol in range(256))
+    return src.translate(mapping)
+
+
+class TestFinalState(unittest.TestCase):
+    def setUp(self):
+        with open(BOOK1, "rb") as file:
+            self.src = file.read(50_000)
+
+    def test_counters(self):
+        hist, max_symbol = fs.simple_count_u8(self.src)
+        self.assertEqual(sum(hist), len(self.src))
+        self.assertEqual(max_symbol, max(self.src))
+        for count in [fs.multi_bucket_count_u8, fs.parallel_lanes_count_u8, fs.count_u8]:
+            self.assertEqual(count(self.src), (hist, max_symbol))
+        if hasattr(fs, "divide_and_conquer_count"):
+            self.assertEqual(fs.divide_and_conquer_count(self.src, 4), (hist, max_symbol))
+
+    def test_normalizations(self):
+        hist, max_symbol = fs.count_u8(self.src)
+        for normalize in NORMALIZATIONS:
+            norm = normalize(hist, max_symbol)
+            self.assertEqual(sum(norm), 1 << TABLE_LOG)
+        with self.assertRaises(ValueError):
+            fs.normalization_with_fast_compensation([0] * 256, TABLE_LOG)
+        with self.assertRaises(ValueError):
+            fs.normalization_with_fast_compensation([0, 10], TABLE_LOG)
+        # 256 symbols don't fit in a table of 2 states.
+        with self.assertRaises(ValueError):
+            fs.fast_normalization_1([1] * 256, 1)
+
+    def test_tans(self):
+        src = sorted_table(self.src)
+        hist, max_symbol = fs.count_u8(src)
+        # fast_normalization_1 ne garde pas les symboles les plus rares.
+        for normalize in NORMALIZATIONS[1:]:
+            norm = normalize(hist, max_symbol)
+            for spread in SPREADS:
+                table = spread(norm, TABLE_LOG)
+                stream, state = fs.encode_tans(src, norm, table, TABLE_LOG)
+                self.assertLess(len(stream), len(src))
+                decoded = fs.decode_tans(stream, norm, table, TABLE_LOG, state, len(src))
+                self.assertEqual(decoded, src)
+        with self.assertRaises(ValueError):
+            fs.decode_tans(stream[:10], norm, table, TABLE_LOG, state, len(src))
+        with self.assertRaises(ValueError):
+            fs.decode_tans(stream, norm, table, TABLE_LOG, state, 1 << 40)
+        with self.assertRaises(ValueError):
+            fs.encode_tans(b"\xff", norm, table, TABLE_LOG)
+        norm = fs.fast_normalization_1(hist, TABLE_LOG)
+        with self.assertRaises(ValueError):
+            fs.encode_tans(src, norm, fs.fse_spread(norm, TABLE_LOG), TABLE_LOG)
+
+    def test_zstd_spread(self):
+        norm = [16, 12, 3, -1]
+        spread = fs.zstd_spread(norm, 5)
+        self.assertEqual(len(spread), 32)
+        self.assertEqual(spread[31], 3)
+        with self.assertRaises(ValueError):
+            fs.zstd_spread([16, 12], 5)
+
+    def test_rans(self):
+        hist, max_symbol = fs.count_u8(self.src)
+        norm = fs.normalization_with_compensation_binary_heap(hist, TABLE_LOG, max_symbol)
+        state, bits, stream = fs.encode_rans(norm, TABLE_LOG, self.src)
+        decoded = fs.decode_rans(state, bits, stream, norm, TABLE_LOG, len(self.src))
+        self.assertEqual(decoded, self.src)
+        with self.assertRaises(ValueError):
+            fs.decode_rans(state, bits, stream[:10], norm, TABLE_LOG, len(self.src))
+
+    def test_lz(self):
+        src = self.src[:10_000]
+        for encoded in [
+            fs.encode_lz_no_windows_u8(src),
+            fs.encode_lempel_ziv_u8(src, 1 << 10),
+            fs.encode_lz_with_hashmap_u8(src),
+            fs.encode_lz_with_binary_tree_u8(src),
+        ]:
+            self.assertLess(len(encoded), len(src))
+            self.assertEqual(fs.decode_lz_u8(encoded), src)
+        with self.assertRaises(ValueError):
+            fs.decode_lz_u8(b"ab\x80\x05\x00\x09")
+        for windows_size in [0, 3, 1024]:
+            with self.assertRaises(ValueError):
+                fs.encode_lempel_ziv_u8(b"abc", windows_size)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,458 @@
+//! `final-state`, a command line tool to try the methods of the library on
+//! files.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! ```text
+//! final-state compress [-m METHOD] [-t TABLE_LOG] [-b BLOCK_SIZE] [-c CHECKSUM] INPUT [OUTPUT]
+//! final-state decompress INPUT [OUTPUT]
+//! final-state analyze INPUT
+//! final-state bench [-n ITERATIONS] INPUT
+//! ```
+//!
+//! The methods are `tans`, `rans`, `lz`, `lz+tans` and `lzw`. A compressed
+//! file starts with the magic `FSCL` and the id of the method, then:
+//! - `tans` and `rans`: a frame of `final_state_rs::frame`,
+//! - `lz`: the tokens of `final_state_rs::lz_stream`,
+//! - `lz+tans`: a tANS frame of the lz tokens,
+//! - `lzw`: a stream of `final_state_rs::lzw` with codes of at most 16 bits.
+
+use std::{fs, process::ExitCode, time::Instant};
+
+use final_state_rs::{
+    count::Histogram,
+    frame::{decode_frame, encode_frame, Checksum, Coder, FrameConfig},
+    lz_stream::{LzStreamDecoder, LzStreamEncoder, MAX_WINDOW_SIZE},
+    lzw::{decode_lzw, encode_lzw},
+};
+
+const MAGIC: [u8; 4] = *b"FSCL";
+/// Width of the largest codes of `Method::Lzw`.
+const LZW_MAX_BITS: u8 = 16;
+
+const USAGE: &str = "Usage:
+    final-state compress [-m METHOD] [-t TABLE_LOG] [-b BLOCK_SIZE] [-c CHECKSUM] INPUT [OUTPUT]
+    final-state decompress INPUT [OUTPUT]
+    final-state analyze INPUT
+    final-state bench [-n ITERATIONS] INPUT
+
+Methods: tans (default), rans, lz, lz+tans, lzw.
+Checksums of the blocks and of the frame: none (default), crc32c, xxh64.
+The output of compress is INPUT.fs by default, the output of decompress is
+INPUT without .fs, or INPUT.out.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Method {
+    Tans,
+    Rans,
+    Lz,
+    LzTans,
+    Lzw,
+}
+
+const METHODS: [Method; 5] = [
+    Method::Tans,
+    Method::Rans,
+    Method::Lz,
+    Method::LzTans,
+    Method::Lzw,
+];
+
+impl Method {
+    fn name(&self) -> &'static str {
+        match self {
+            Method::Tans => "tans",
+            Method::Rans => "rans",
+            Method::Lz => "lz",
+            Method::LzTans => "lz+tans",
+            Method::Lzw => "lzw",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Method> {
+        METHODS.into_iter().find(|method| method.name() == name)
+    }
+
+    /// Id of the method in a compressed file.
+    fn id(&self) -> u8 {
+        *self as u8
+    }
+
+    fn from_id(id: u8) -> Option<Method> {
+        METHODS.into_iter().find(|method| method.id() == id)
+    }
+
+    /// Compress the source, with the magic and the id of the method.
+    fn compress(&self, src: &[u8], config: &FrameConfig) -> Vec<u8> {
+        let mut ret = MAGIC.to_vec();
+        ret.push(self.id());
+        let payload = match self {
+            Method::Tans => encode_frame(src, config),
+            Method::Rans => encode_frame(
+                src,
+                &FrameConfig {
+                    coder: Coder::Rans,
+                    ..config.clone()
+                },
+            ),
+            Method::Lz => encode_lz(src),
+            Method::LzTans => encode_frame(&encode_lz(src), config),
+            Method::Lzw => encode_lzw(src, LZW_MAX_BITS),
+        };
+        ret.extend_from_slice(&payload);
+        ret
+    }
+}
+
+fn encode_lz(src: &[u8]) -> Vec<u8> {
+    let mut encoder = LzStreamEncoder::new(MAX_WINDOW_SIZE);
+    let mut ret = encoder.write(src);
+    ret.append(&mut encoder.finish());
+    ret
+}
+
+fn decode_lz(src: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoder = LzStreamDecoder::new(MAX_WINDOW_SIZE);
+    let mut ret = decoder.write(src).map_err(|err| format!("{err:?}"))?;
+    ret.append(&mut decoder.finish().map_err(|err| format!("{err:?}"))?);
+    Ok(ret)
+}
+
+/// Decompress a file of `Method::compress`.
+fn decompress(src: &[u8]) -> Result<Vec<u8>, String> {
+    if src.len() < 5 || src[..4] != MAGIC {
+        return Err("not a file compressed by final-state".to_string());
+    }
+    let method = Method::from_id(src[4]).ok_or(format!("unknown method {}", src[4]))?;
+    let payload = &src[5..];
+    let frame = |src| decode_frame(src).map_err(|err| format!("{err:?}"));
+    match method {
+        Method::Tans | Method::Rans => frame(payload),
+        Method::Lz => decode_lz(payload),
+        Method::LzTans => decode_lz(&frame(payload)?),
+        Method::Lzw => decode_lzw(payload, LZW_MAX_BITS).map_err(|err| format!("{err:?}")),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Compress {
+        method: Method,
+        config: FrameConfig,
+        input: String,
+        output: String,
+    },
+    Decompress {
+        input: String,
+        output: String,
+    },
+    Analyze {
+        input: String,
+    },
+    Bench {
+        iterations: usize,
+        input: String,
+    },
+}
+
+/// Value of an option, parsed as a number.
+fn number(option: &str, value: Option<String>) -> Result<usize, String> {
+    let value = value.ok_or(format!("missing value of {option}"))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value of {option}: {value}"))
+}
+
+/// Parse the arguments, without the name of the program.
+fn parse_args(args: Vec<String>) -> Result<Command, String> {
+    let mut args = args.into_iter();
+    let command = args.next().ok_or("missing command")?;
+    let mut method = Method::Tans;
+    let mut config = FrameConfig::default();
+    let mut iterations = 3;
+    let mut files = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-m" | "--method" => {
+                let name = args.next().ok_or("missing value of --method")?;
+                method = Method::from_name(&name).ok_or(format!("unknown method {name}"))?;
+            }
+            "-c" | "--checksum" => {
+                let name = args.next().ok_or("missing value of --checksum")?;
+                let checksum = match name.as_str() {
+                    "none" => Checksum::None,
+                    "crc32c" => Checksum::Crc32c,
+                    "xxh64" => Checksum::Xxh64,
+                    _ => return Err(format!("unknown checksum {name}")),
+                };
+                config.block_checksum = checksum;
+                config.frame_checksum = checksum;
+            }
+            "-t" | "--table-log" => config.table_log = number(&arg, args.next())?,
+            "-b" | "--block-size" => config.block_size = number(&arg, args.next())?,
+            "-n" | "--iterations" => iterations = number(&arg, args.next())?,
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("unknown option {arg}"))
+            }
+            _ => files.push(arg),
+        }
+    }
+    if config.block_size == 0 || iterations == 0 {
+        return Err("the block size and the iterations can't be 0".to_string());
+    }
+    let mut files = files.into_iter();
+    let input = files.next().ok_or("missing input file")?;
+    let output = files.next();
+    if files.next().is_some() {
+        return Err("too many files".to_string());
+    }
+    let single = |output: Option<String>| match output {
+        Some(_) => Err(format!("{command} takes a single file")),
+        None => Ok(()),
+    };
+    match command.as_str() {
+        "compress" => Ok(Command::Compress {
+            method,
+            config,
+            output: output.unwrap_or(format!("{input}.fs")),
+            input,
+        }),
+        "decompress" => Ok(Command::Decompress {
+            output: output.unwrap_or(match input.strip_suffix(".fs") {
+                Some(stem) => stem.to_string(),
+                None => format!("{input}.out"),
+            }),
+            input,
+        }),
+        "analyze" => single(output).map(|_| Command::Analyze { input }),
+        "bench" => single(output).map(|_| Command::Bench { iterations, input }),
+        _ => Err(format!("unknown command {command}")),
+    }
+}
+
+/// Entropy of order 0 in bits per byte.
+fn entropy(hist: &Histogram) -> f64 {
+    let total = hist.total() as f64;
+    hist.counts()
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn printable(symbol: u8) -> String {
+    if symbol.is_ascii_graphic() {
+        format!("'{}'", symbol as char)
+    } else {
+        format!("0x{symbol:02x}")
+    }
+}
+
+fn analyze(src: &[u8]) {
+    let hist = Histogram::from_source(src);
+    let distinct = hist.counts().iter().filter(|count| **count > 0).count();
+    let entropy = entropy(&hist);
+    println!("size:        {} bytes", src.len());
+    println!("symbols:     {distinct} (max {})", hist.max_symbol());
+    println!("entropy:     {entropy:.4} bits per byte");
+    println!(
+        "bound:       {} bytes",
+        (entropy * src.len() as f64 / 8.0).ceil() as usize
+    );
+    let config = FrameConfig::default();
+    println!("table_log:   {}", config.block_table_log(distinct));
+
+    let mut symbols = (0..=255u8)
+        .filter(|symbol| hist.count(*symbol) > 0)
+        .collect::<Vec<u8>>();
+    symbols.sort_by_key(|symbol| std::cmp::Reverse(hist.count(*symbol)));
+    println!("\nmost frequent symbols:");
+    for symbol in symbols.iter().take(16) {
+        let count = hist.count(*symbol);
+        println!(
+            "  {:>6} {count:>10} {:>6.2}%",
+            printable(*symbol),
+            100.0 * count as f64 / src.len().max(1) as f64
+        );
+    }
+
+    println!("\nmethods:");
+    for method in METHODS {
+        let size = method.compress(src, &config).len();
+        println!(
+            "  {:<8} {size:>10} bytes {:>6.2}%",
+            method.name(),
+            100.0 * size as f64 / src.len().max(1) as f64
+        );
+    }
+}
+
+fn bench(src: &[u8], iterations: usize) -> Result<(), String> {
+    let config = FrameConfig::default();
+    let megabytes = src.len() as f64 / (1 << 20) as f64;
+    println!(
+        "{:<8} {:>10} {:>8} {:>14} {:>14}",
+        "method", "size", "ratio", "compress", "decompress"
+    );
+    for method in METHODS {
+        let start = Instant::now();
+        let mut encoded = vec![];
+        for _ in 0..iterations {
+            encoded = method.compress(src, &config);
+        }
+        let compress = start.elapsed().as_secs_f64() / iterations as f64;
+        let start = Instant::now();
+        for _ in 0..iterations {
+            if decompress(&encoded)? != src {
+                return Err(format!("{} doesn't decode its output", method.name()));
+            }
+        }
+        let decompress = start.elapsed().as_secs_f64() / iterations as f64;
+        println!(
+            "{:<8} {:>10} {:>7.2}% {:>9.2} MB/s {:>9.2} MB/s",
+            method.name(),
+            encoded.len(),
+            100.0 * encoded.len() as f64 / src.len().max(1) as f64,
+            megabytes / compress,
+            megabytes / decompress
+        );
+    }
+    Ok(())
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|err| format!("cannot read {path}: {err}"))
+}
+
+fn write(path: &str, data: &[u8]) -> Result<(), String> {
+    fs::write(path, data).map_err(|err| format!("cannot write {path}: {err}"))
+}
+
+fn run(command: Command) -> Result<(), String> {
+    match command {
+        Command::Compress {
+            method,
+            config,
+            input,
+            output,
+        } => {
+            let src = read(&input)?;
+            let encoded = method.compress(&src, &config);
+            write(&output, &encoded)?;
+            println!(
+                "{input} ({} bytes) -> {output} ({} bytes) with {}",
+                src.len(),
+                encoded.len(),
+                method.name()
+            );
+        }
+        Command::Decompress { input, output } => {
+            let decoded = decompress(&read(&input)?).map_err(|err| format!("{input}: {err}"))?;
+            write(&output, &decoded)?;
+            println!("{input} -> {output} ({} bytes)", decoded.len());
+        }
+        Command::Analyze { input } => analyze(&read(&input)?),
+        Command::Bench { iterations, input } => bench(&read(&input)?, iterations)?,
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if args.is_empty() || args[0] == "-h" || args[0] == "--help" {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    let command = match parse_args(args) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    match run(command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[test]
+fn cli_methods_round_trip() {
+    let book1 = fs::read("./rsc/calgary_book1").expect("Cannot find calgary book1 ressource");
+    let src = &book1[..100_000];
+    let config = FrameConfig::default();
+    for method in METHODS {
+        let encoded = method.compress(src, &config);
+        assert_eq!(encoded[4], method.id());
+        assert!(encoded.len() < src.len(), "{}", method.name());
+        assert_eq!(decompress(&encoded).unwrap(), src);
+        assert_eq!(decompress(&method.compress(&[], &config)).unwrap(), []);
+    }
+    assert!(decompress(b"FSCL").is_err());
+    assert!(decompress(b"FSCL\x09").is_err());
+    // Un flux lzw sans code de fin.
+    assert!(decompress(b"FSCL\x04\x01").is_err());
+    assert_eq!(Method::from_name("lzw"), Some(Method::Lzw));
+    assert!(decompress(b"LZ4\0\0").is_err());
+}
+
+#[test]
+fn cli_parse_args() {
+    let args = |line: &str| parse_args(line.split_whitespace().map(String::from).collect());
+    assert_eq!(
+        args("compress -m lz+tans -t 12 book1"),
+        Ok(Command::Compress {
+            method: Method::LzTans,
+            config: FrameConfig {
+                table_log: 12,
+                ..Default::default()
+            },
+            input: "book1".to_string(),
+            output: "book1.fs".to_string(),
+        })
+    );
+    assert_eq!(
+        args("decompress book1.fs"),
+        Ok(Command::Decompress {
+            input: "book1.fs".to_string(),
+            output: "book1".to_string(),
+        })
+    );
+    assert_eq!(
+        args("bench -n 5 book1"),
+        Ok(Command::Bench {
+            iterations: 5,
+            input: "book1".to_string(),
+        })
+    );
+    assert_eq!(
+        args("compress -c xxh64 book1"),
+        Ok(Command::Compress {
+            method: Method::Tans,
+            config: FrameConfig {
+                block_checksum: Checksum::Xxh64,
+                frame_checksum: Checksum::Xxh64,
+                ..Default::default()
+            },
+            input: "book1".to_string(),
+            output: "book1.fs".to_string(),
+        })
+    );
+    assert!(args("compress -m zip book1").is_err());
+    assert!(args("compress -c md5 book1").is_err());
+    assert!(args("compress -b 0 book1").is_err());
+    assert!(args("analyze a b").is_err());
+    assert!(args("decompress").is_err());
+    assert!(args("unzip book1").is_err());
+}
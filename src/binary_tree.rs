@@ -0,0 +1,199 @@
+//! Binary tree match finder (the BT4 of LZMA) for the lempel_ziv encoders.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! The positions that share the same hash of 4 bytes are kept in a binary
+//! search tree, sorted in the lexicographic order of the sequences that start
+//! at these positions. Each new position becomes the root of its tree: while
+//! we walk down from the old root to split the tree in two, we compare the new
+//! sequence with every node on the path. These nodes are the closest
+//! sequences in the lexicographic order, so the longest match is always on
+//! the path.
+//!
+//! Contrary to the hashmap of `lempel_ziv`, the match finder returns every
+//! match longer than the previous ones. An optimal parser can choose between
+//! a long but far match and a shorter but closer one.
+
+/// Empty child or empty tree.
+const NIL: usize = usize::MAX;
+/// Number of bits of the hash of the 4 first bytes.
+const HASH_LOG: u32 = 16;
+
+/// A match found by the `BinaryTreeMatchFinder`. Like the pairs of
+/// `lempel_ziv`, the index is the absolute position of the previous
+/// occurence of the sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    /// Index of the previous occurence of the sequence.
+    pub index: usize,
+    /// Length of the common sequence.
+    pub len: u32,
+}
+
+#[inline]
+fn hash(src: &[u8], index: usize) -> usize {
+    let key = u32::from_le_bytes([src[index], src[index + 1], src[index + 2], src[index + 3]]);
+    (key.wrapping_mul(2654435761) >> (u32::BITS - HASH_LOG)) as usize
+}
+
+/// Binary tree match finder over a complete source. The positions have to be
+/// given in increasing order to `find_matches` or `skip`, each of them is
+/// inserted in the trees.
+///
+/// ```
+/// use final_state_rs::binary_tree::*;
+///
+/// let src = "abcdefgh-abcdxyz-abcdefgh".as_bytes();
+/// let mut finder = BinaryTreeMatchFinder::new(src, usize::MAX, 64, 273);
+/// for index in 0..17 {
+///     finder.skip(index);
+/// }
+/// let matches = finder.find_matches(17);
+/// // "abcd" is found in 9, "abcdefgh" is found in 0. The matches are
+/// // ordered by length, a shorter match is usually closer.
+/// assert_eq!(
+///     matches,
+///     vec![Match { index: 9, len: 4 }, Match { index: 0, len: 8 }]
+/// );
+/// ```
+pub struct BinaryTreeMatchFinder<'a> {
+    src: &'a [u8],
+    /// Root of the tree of each hash.
+    head: Vec<usize>,
+    /// Children of each position, `son[2 * p]` is the smaller child,
+    /// `son[2 * p + 1]` the greater one.
+    son: Vec<usize>,
+    /// Maximum distance between a position and its matches.
+    window_size: usize,
+    /// Maximum number of nodes visited for each position.
+    depth: usize,
+    /// Length at which we stop to search a longer match.
+    max_len: usize,
+}
+
+impl<'a> BinaryTreeMatchFinder<'a> {
+    /// Create a match finder over `src`. The matches won't be farther than
+    /// `window_size` and not longer than `max_len`. At most `depth` previous
+    /// positions are compared for each position.
+    pub fn new(src: &'a [u8], window_size: usize, depth: usize, max_len: usize) -> Self {
+        assert!(max_len >= 4, "The maximum length has to be at least 4");
+        BinaryTreeMatchFinder {
+            src,
+            head: vec![NIL; 1 << HASH_LOG],
+            son: vec![NIL; 2 * src.len()],
+            window_size,
+            depth,
+            max_len,
+        }
+    }
+
+    /// Insert the position in the trees and return all the matches that are
+    /// longer than the previous ones, ordered by length.
+    pub fn find_matches(&mut self, index: usize) -> Vec<Match> {
+        let mut ret = vec![];
+        self.insert(index, Some(&mut ret));
+        ret
+    }
+
+    /// Insert the position in the trees without collecting the matches, used
+    /// for the positions covered by a match.
+    pub fn skip(&mut self, index: usize) {
+        self.insert(index, None);
+    }
+
+    fn insert(&mut self, index: usize, mut matches: Option<&mut Vec<Match>>) {
+        let src = self.src;
+        if index + 4 > src.len() {
+            return;
+        }
+        let len_limit = std::cmp::min(self.max_len, src.len() - index);
+        let h = hash(src, index);
+        let mut current = self.head[h];
+        self.head[h] = index;
+
+        // `ptr0` is the slot where the next greater node will be attached,
+        // `ptr1` the slot of the next smaller one. `len0` and `len1` are the
+        // common lengths already known with these nodes: everything on the
+        // path between them shares at least the minimum of the two.
+        let mut ptr0 = 2 * index + 1;
+        let mut ptr1 = 2 * index;
+        let mut len0 = 0;
+        let mut len1 = 0;
+        let mut best = 0;
+        let mut depth = self.depth;
+        loop {
+            if current == NIL || index - current > self.window_size || depth == 0 {
+                self.son[ptr0] = NIL;
+                self.son[ptr1] = NIL;
+                return;
+            }
+            depth -= 1;
+            let mut len = std::cmp::min(len0, len1);
+            while len < len_limit && src[current + len] == src[index + len] {
+                len += 1;
+            }
+            if len > best {
+                best = len;
+                if let Some(matches) = matches.as_mut() {
+                    matches.push(Match {
+                        index: current,
+                        len: len as u32,
+                    });
+                }
+                if len == len_limit {
+                    // The sequences are identical on the maximum length, the
+                    // new position takes the children of the current node.
+                    self.son[ptr1] = self.son[2 * current];
+                    self.son[ptr0] = self.son[2 * current + 1];
+                    return;
+                }
+            }
+            if src[current + len] < src[index + len] {
+                self.son[ptr1] = current;
+                ptr1 = 2 * current + 1;
+                current = self.son[ptr1];
+                len1 = len;
+            } else {
+                self.son[ptr0] = current;
+                ptr0 = 2 * current;
+                current = self.son[ptr0];
+                len0 = len;
+            }
+        }
+    }
+}
+
+#[test]
+fn binary_tree_matches_are_exhaustive() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+    let src = &book1[..3000];
+
+    let mut finder = BinaryTreeMatchFinder::new(src, usize::MAX, usize::MAX, 273);
+    for index in 0..src.len() - 4 {
+        let matches = finder.find_matches(index);
+        // Naive search of the longest match
+        let mut longest = 0;
+        for s in 0..index {
+            let mut len = 0;
+            while index + len < src.len() && len < 273 && src[s + len] == src[index + len] {
+                len += 1;
+            }
+            longest = std::cmp::max(longest, len);
+        }
+        let found = matches.last().map(|m| m.len as usize).unwrap_or(0);
+        if longest >= 4 {
+            assert_eq!(found, longest, "at index {index}");
+        }
+        for m in matches.iter() {
+            let (s, len) = (m.index, m.len as usize);
+            assert_eq!(src[s..s + len], src[index..index + len]);
+        }
+        assert!(matches.windows(2).all(|w| w[0].len < w[1].len));
+    }
+}
@@ -0,0 +1,287 @@
+//! LIFO bitstream of the entropy coders.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! tANS and rANS decode the symbols in the reverse order of the encoding, the
+//! decoder reads the last value written first. The encoder writes the values
+//! from the least significant bit of the stream, then a mark bit set to 1 and
+//! some zeros until the end of the last byte. The decoder finds the mark in
+//! the last byte and reads the stream backward from it.
+//!
+//! The format is the one of `tiny_bitstream`, which needs `std`. The module
+//! only uses `core` and `alloc`, and a read past the beginning of the stream
+//! returns an error.
+//!
+//! `BitDstream::try_from` skips the mark, the first read returns the last
+//! value written. A `tiny_bitstream::BitDstream` starts on the mark and the
+//! caller reads it first. `t_ans::encode_symbol` and `t_ans::decode_symbol`
+//! take any stream of `WriteBits` and `ReadBits`, with `std` the streams of
+//! `tiny_bitstream` still work with them.
+
+use alloc::vec::Vec;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BitstreamError {
+    /// The stream is empty or its last byte doesn't contain the mark.
+    MissingMark,
+    /// Read more bits than the stream contains.
+    ReadOverflow,
+}
+
+#[inline]
+fn mask(nb_bits: u32) -> u128 {
+    (1 << nb_bits) - 1
+}
+
+/// Stream in which a coder writes the bits of its states.
+pub trait WriteBits {
+    /// Write the `nb_bits` lowest bits of `value`.
+    fn write_bits(&mut self, value: usize, nb_bits: u8);
+}
+
+/// Stream from which a decoder reads the bits of its states, in the reverse
+/// order of the writes.
+pub trait ReadBits {
+    /// Read `nb_bits`, reading 0 bits always succeeds.
+    fn read_bits(&mut self, nb_bits: u8) -> Result<usize, BitstreamError>;
+}
+
+/// Writer of a stream, `Vec<u8>::from` closes it with the mark.
+///
+/// ```
+/// use final_state_rs::bitstream::*;
+///
+/// let mut estream = BitEstream::new();
+/// estream.write(0b101, 3);
+/// estream.write(0xabcd, 16);
+/// let stream: Vec<u8> = estream.into();
+///
+/// let mut dstream = BitDstream::try_from(stream).unwrap();
+/// assert_eq!(dstream.remaining(), 19);
+/// assert_eq!(dstream.read(16), Ok(0xabcd));
+/// assert_eq!(dstream.read(3), Ok(0b101));
+/// assert_eq!(dstream.read(1), Err(BitstreamError::ReadOverflow));
+/// ```
+#[derive(Default)]
+pub struct BitEstream {
+    stream: Vec<u8>,
+    /// Bits not flushed yet, less than 64 between two writes.
+    container: u128,
+    nb_bits: u32,
+}
+
+impl BitEstream {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Write the `nb_bits` lowest bits of `value`, the other bits are
+    /// ignored.
+    #[inline]
+    pub fn write(&mut self, value: usize, nb_bits: u8) {
+        let nb_bits = nb_bits as u32;
+        assert!(nb_bits <= usize::BITS, "Cannot write {nb_bits} bits");
+        self.container |= (value as u128 & mask(nb_bits)) << self.nb_bits;
+        self.nb_bits += nb_bits;
+        if self.nb_bits >= 64 {
+            self.stream
+                .extend_from_slice(&(self.container as u64).to_le_bytes());
+            self.container >>= 64;
+            self.nb_bits -= 64;
+        }
+    }
+
+    /// Write a size on 7 bits for its number of bits, then its bits.
+    pub fn write_size(&mut self, size: usize) {
+        let nb_bits = usize::BITS - size.leading_zeros();
+        self.write(size, nb_bits as u8);
+        self.write(nb_bits as usize, 7);
+    }
+}
+
+impl From<BitEstream> for Vec<u8> {
+    fn from(mut estream: BitEstream) -> Self {
+        estream.write(1, 1); // Mark
+        let nb_bytes = estream.nb_bits.div_ceil(8) as usize;
+        let container = estream.container.to_le_bytes();
+        estream.stream.extend_from_slice(&container[..nb_bytes]);
+        estream.stream
+    }
+}
+
+/// Reader of a stream closed by `BitEstream`, from the last value written to
+/// the first.
+pub struct BitDstream {
+    stream: Vec<u8>,
+    /// Number of bits before the mark not read yet.
+    remaining: usize,
+}
+
+impl TryFrom<Vec<u8>> for BitDstream {
+    type Error = BitstreamError;
+
+    fn try_from(stream: Vec<u8>) -> Result<Self, Self::Error> {
+        match stream.last() {
+            Some(&last) if last != 0 => Ok(BitDstream {
+                remaining: stream.len() * 8 - last.leading_zeros() as usize - 1,
+                stream,
+            }),
+            _ => Err(BitstreamError::MissingMark),
+        }
+    }
+}
+
+impl BitDstream {
+    /// Read `nb_bits`, the stream is left unchanged on error. Reading 0 bits
+    /// always succeeds.
+    #[inline]
+    pub fn read(&mut self, nb_bits: u8) -> Result<usize, BitstreamError> {
+        let nb_bits = nb_bits as usize;
+        assert!(
+            nb_bits <= usize::BITS as usize,
+            "Cannot read {nb_bits} bits"
+        );
+        if nb_bits > self.remaining {
+            return Err(BitstreamError::ReadOverflow);
+        }
+        self.remaining -= nb_bits;
+        if nb_bits == 0 {
+            return Ok(0);
+        }
+        // Au plus 9 octets contiennent les bits lus.
+        let start = self.remaining / 8;
+        let end = (self.remaining + nb_bits).div_ceil(8);
+        let container = match self.stream.get(start..start + 16) {
+            Some(bytes) => u128::from_le_bytes(bytes.try_into().unwrap()),
+            None => self.stream[start..end]
+                .iter()
+                .rev()
+                .fold(0, |container, byte| container << 8 | *byte as u128),
+        };
+        Ok(((container >> (self.remaining % 8)) & mask(nb_bits as u32)) as usize)
+    }
+
+    /// Read a size written by `BitEstream::write_size`.
+    pub fn read_size(&mut self) -> Result<usize, BitstreamError> {
+        let nb_bits = self.read(7)?;
+        if nb_bits > usize::BITS as usize {
+            return Err(BitstreamError::ReadOverflow);
+        }
+        self.read(nb_bits as u8)
+    }
+
+    /// Number of bits not read yet.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Continue with the previous stream when several streams closed by
+    /// `BitEstream` are concatenated. All the bits of the current stream
+    /// have to be read, the next read starts before the mark of the
+    /// previous one.
+    ///
+    /// ```
+    /// use final_state_rs::bitstream::*;
+    ///
+    /// let mut stream = vec![];
+    /// for value in [0b1011, 0xbeef] {
+    ///     let mut estream = BitEstream::new();
+    ///     estream.write(value, 16);
+    ///     stream.append(&mut estream.into());
+    /// }
+    /// let mut dstream = BitDstream::try_from(stream).unwrap();
+    /// assert_eq!(dstream.read(16), Ok(0xbeef));
+    /// dstream.next_stream().unwrap();
+    /// assert_eq!(dstream.read(16), Ok(0b1011));
+    /// assert_eq!(dstream.next_stream(), Err(BitstreamError::MissingMark));
+    /// ```
+    pub fn next_stream(&mut self) -> Result<(), BitstreamError> {
+        if !self.remaining.is_multiple_of(8) {
+            return Err(BitstreamError::MissingMark);
+        }
+        match self.stream[..self.remaining / 8].last() {
+            Some(&last) if last != 0 => {
+                self.remaining -= last.leading_zeros() as usize + 1;
+                Ok(())
+            }
+            _ => Err(BitstreamError::MissingMark),
+        }
+    }
+}
+
+impl WriteBits for BitEstream {
+    #[inline]
+    fn write_bits(&mut self, value: usize, nb_bits: u8) {
+        self.write(value, nb_bits)
+    }
+}
+
+impl ReadBits for BitDstream {
+    #[inline]
+    fn read_bits(&mut self, nb_bits: u8) -> Result<usize, BitstreamError> {
+        self.read(nb_bits)
+    }
+}
+
+#[cfg(feature = "std")]
+impl WriteBits for tiny_bitstream::BitEstream {
+    #[inline]
+    fn write_bits(&mut self, value: usize, nb_bits: u8) {
+        tiny_bitstream::BitWriter::unchecked_write(self, value, nb_bits)
+    }
+}
+
+#[cfg(feature = "std")]
+impl ReadBits for tiny_bitstream::BitDstream {
+    #[inline]
+    fn read_bits(&mut self, nb_bits: u8) -> Result<usize, BitstreamError> {
+        // `tiny_bitstream` échoue à lire 0 bits quand le flux est épuisé.
+        match nb_bits {
+            0 => Ok(0),
+            nb_bits => tiny_bitstream::BitReader::read(self, nb_bits)
+                .map_err(|_| BitstreamError::ReadOverflow),
+        }
+    }
+}
+
+#[test]
+fn bitstream_compatible_with_tiny_bitstream() {
+    use rand::{Rng, SeedableRng};
+    use tiny_bitstream::BitWriter;
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+    let values = (0..10_000)
+        .map(|_| {
+            let nb_bits = rng.gen_range(0..=64u8);
+            (rng.gen::<usize>(), nb_bits)
+        })
+        .collect::<Vec<_>>();
+
+    let mut estream = BitEstream::new();
+    let mut tiny_estream = tiny_bitstream::BitEstream::new();
+    for (value, nb_bits) in values.iter() {
+        estream.write(*value, *nb_bits);
+        tiny_estream.unchecked_write(*value, *nb_bits);
+    }
+    let stream: Vec<u8> = estream.into();
+    let tiny_stream: Vec<u8> = tiny_estream.into();
+    assert_eq!(stream, tiny_stream);
+
+    let mut dstream = BitDstream::try_from(stream).unwrap();
+    for (value, nb_bits) in values.iter().rev() {
+        let expected = *value as u128 & mask(*nb_bits as u32);
+        assert_eq!(dstream.read(*nb_bits), Ok(expected as usize));
+    }
+    assert_eq!(dstream.remaining(), 0);
+    assert_eq!(dstream.read(1), Err(BitstreamError::ReadOverflow));
+    assert_eq!(dstream.read(0), Ok(0));
+
+    // Un flux sans marque.
+    assert!(BitDstream::try_from(vec![]).is_err());
+    assert!(BitDstream::try_from(vec![0xff, 0]).is_err());
+}
@@ -0,0 +1,134 @@
+//! Block splitting driven by the statistics of the source.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! One normalized histogram for a whole file wastes bits when the statistics
+//! change along the file: a header, then some text, then a table of numbers.
+//! The splitter walks the source chunk by chunk and decides for each chunk if
+//! it continues the current block or starts a new one.
+//!
+//! Coding `a` and `b` with the histogram of their union `m` costs, in bits,
+//! `|a| KL(a || m) + |b| KL(b || m)` more than coding them with their own
+//! histograms, where `KL` is the Kullback-Leibler divergence. When that loss
+//! is greater than the cost of the header of a new block, its table mainly,
+//! the chunk starts a new block.
+//!
+//! The walk is greedy, a block is never merged back with the previous one,
+//! and a block never exceeds the maximum size given to `split_blocks`.
+
+use std::ops::Range;
+
+use crate::count::Histogram;
+
+/// Default size of the chunks compared by the splitter.
+pub const CHUNK_SIZE: usize = 4 << 10;
+/// Bytes of a block of `frame` without its table: the type, the size, the
+/// final state and the size of the stream.
+const BLOCK_OVERHEAD: usize = 1 + 4 + 2 + 4;
+
+/// Size in bits of the source of a histogram, coded with its own
+/// statistics.
+fn entropy_cost(hist: &Histogram) -> f64 {
+    let total = hist.total() as f64;
+    hist.counts()
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| *count as f64 * (total / *count as f64).log2())
+        .sum()
+}
+
+/// Bits lost when `block` and `chunk` are coded with one histogram instead
+/// of two.
+fn divergence_cost(block: &Histogram, chunk: &Histogram) -> f64 {
+    let mut merged = block.clone();
+    merged.merge(chunk);
+    entropy_cost(&merged) - entropy_cost(block) - entropy_cost(chunk)
+}
+
+/// Size in bits of the header of a block, its table has a u16 for each
+/// symbol until the max symbol.
+fn header_cost(chunk: &Histogram) -> f64 {
+    (8 * (BLOCK_OVERHEAD + 2 + 2 * (chunk.max_symbol() + 1))) as f64
+}
+
+/// Split the source in blocks of at most `max_block_size` bytes, the
+/// boundaries are on multiples of `chunk_size` where the statistics of the
+/// source change.
+///
+/// ```
+/// use final_state_rs::block_split::*;
+///
+/// // Deux sources aux statistiques différentes, 10 et 8 chunks.
+/// let mut src = vec![];
+/// for i in 0..40_960u32 {
+///     src.push(b"ab"[(i % 3 == 0) as usize]);
+/// }
+/// for i in 0..32_768u32 {
+///     src.push(b"cdef"[(i * 7 % 4) as usize]);
+/// }
+/// let blocks = split_blocks(&src, CHUNK_SIZE, 1 << 20);
+/// assert_eq!(blocks, [0..40_960, 40_960..73_728]);
+/// ```
+pub fn split_blocks(src: &[u8], chunk_size: usize, max_block_size: usize) -> Vec<Range<usize>> {
+    assert!(chunk_size > 0, "The size of the chunks can't be 0");
+    assert!(
+        max_block_size >= chunk_size,
+        "The blocks should be larger than the chunks"
+    );
+    let mut blocks = vec![];
+    let mut start = 0;
+    let mut block = Histogram::new();
+    for (i, chunk) in src.chunks(chunk_size).enumerate() {
+        let chunk_start = i * chunk_size;
+        let chunk = Histogram::from_source(chunk);
+        if block.total() > 0
+            && (block.total() + chunk.total() > max_block_size
+                || divergence_cost(&block, &chunk) > header_cost(&chunk))
+        {
+            blocks.push(start..chunk_start);
+            start = chunk_start;
+            block = chunk;
+        } else {
+            block.merge(&chunk);
+        }
+    }
+    if start < src.len() {
+        blocks.push(start..src.len());
+    }
+    blocks
+}
+
+#[test]
+fn split_mixed_source() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+
+    // Du texte, une table de nombres, puis du texte à nouveau.
+    let mut src = book1[..100_000].to_vec();
+    src.extend((0..25_000u32).flat_map(|v| (v * v % 1000).to_le_bytes()));
+    src.extend_from_slice(&book1[200_000..300_000]);
+    let blocks = split_blocks(&src, CHUNK_SIZE, 1 << 20);
+    let boundaries = blocks.iter().skip(1).map(|b| b.start).collect::<Vec<_>>();
+    for shift in [100_000, 200_000] {
+        assert!(
+            boundaries.iter().any(|b| b.abs_diff(shift) <= CHUNK_SIZE),
+            "No boundary near {shift} in {boundaries:?}"
+        );
+    }
+    assert!(blocks.len() <= 6, "Too many blocks {boundaries:?}");
+    assert_eq!(blocks.first().unwrap().start, 0);
+    assert_eq!(blocks.last().unwrap().end, src.len());
+    assert!(blocks.windows(2).all(|w| w[0].end == w[1].start));
+
+    // Une source homogène reste en un bloc, limité par la taille maximale.
+    let blocks = split_blocks(&book1[..300_000], CHUNK_SIZE, 1 << 20);
+    assert!(blocks.len() <= 2, "{blocks:?}");
+    let blocks = split_blocks(&book1[..300_000], CHUNK_SIZE, 64 << 10);
+    assert!(blocks.iter().all(|b| b.len() <= 64 << 10));
+    assert!(split_blocks(&[], CHUNK_SIZE, 1 << 20).is_empty());
+}
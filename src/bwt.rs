@@ -0,0 +1,588 @@
+//! Burrows–Wheeler transform, move-to-front and zero-run coding, the
+//! preprocessing of bzip2 before the entropy coder.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! The BWT sorts all the suffixes of the source and writes the byte that
+//! precedes each of them. The bytes that precede similar contexts are often
+//! the same, the output is made of long runs of a few symbols. The
+//! move-to-front transform replaces each byte by its rank in a list of the
+//! recently seen bytes, so these runs become runs of zeros and the histogram
+//! is concentrated on the small values. The runs of zeros are finally written
+//! in base 2 with two symbols, like bzip2 does with RUNA and RUNB.
+//!
+//! The output of `encode_zero_runs` is a stream of bytes that can be given to
+//! `t_ans` or `r_ans` directly. `encode_bwt_tans` chains everything with a
+//! tANS coder, by blocks of `BLOCK_SIZE` bytes.
+//!
+//! The suffixes are sorted by prefix doubling: at each round the suffixes are
+//! sorted by their first `2k` bytes with two counting sorts of the ranks of
+//! their first `k` bytes. A virtual end of source, smaller than every byte,
+//! is the first row of the sorted list, its position in the output is the
+//! primary index needed to invert the transform.
+
+use crate::bitstream::BitDstream;
+use crate::count::simple_count_u8_inplace;
+use crate::normalization::normalization_with_compensation_binary_heap;
+use crate::spreads::fse_spread_unsorted;
+use crate::t_ans::{encode_tans, try_decode_tans, TansError};
+
+/// Size of the blocks of `encode_bwt_tans`, like `bzip2 -9`.
+pub const BLOCK_SIZE: usize = 900_000;
+/// Table log of the tANS coder of `encode_bwt_tans`.
+const TABLE_LOG: usize = 12;
+
+/// Symbols of the runs of zeros, a run of `n` zeros is written as the
+/// bijective base 2 representation of `n`, with the digits 1 (RUNA) and 2
+/// (RUNB).
+const RUN_A: u8 = 0;
+const RUN_B: u8 = 1;
+/// The ranks 254 and 255 are written as the escape followed by `rank - 254`.
+const ESCAPE: u8 = 255;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BwtError {
+    /// The stream ends in the middle of a block.
+    Truncated,
+    /// The primary index doesn't match the transformed block.
+    InvalidPrimaryIndex(usize),
+    /// An escape isn't followed by 0 or 1.
+    InvalidEscape,
+    /// The normalized histogram of a block is invalid.
+    InvalidHistogram,
+    /// The size of a decoded block doesn't match its header.
+    SizeMismatch,
+    /// The decoded stream would be greater than the maximum size.
+    TooLarge,
+}
+
+/// Sort the suffixes of `src`, a suffix is smaller than all the suffixes it
+/// prefixes.
+///
+/// ```
+/// use final_state_rs::bwt::suffix_array;
+///
+/// assert_eq!(suffix_array(b"banana"), vec![5, 3, 1, 0, 4, 2]);
+/// ```
+pub fn suffix_array(src: &[u8]) -> Vec<usize> {
+    let n = src.len();
+    // Rang 0 réservé à la fin de la source.
+    let mut rank = src.iter().map(|b| *b as usize + 1).collect::<Vec<usize>>();
+    let mut sa = (0..n).collect::<Vec<usize>>();
+    if n == 0 {
+        return sa;
+    }
+    sa.sort_by_key(|i| src[*i]);
+    let mut tmp = vec![0; n];
+    let mut count = vec![0; n + 257];
+    let mut k = 1;
+    loop {
+        // Ordre selon la seconde clé (rang de i + k): les suffixes plus
+        // courts que k en premier, puis les autres dans l'ordre du tour
+        // précédent.
+        let mut j = 0;
+        for i in n.saturating_sub(k)..n {
+            tmp[j] = i;
+            j += 1;
+        }
+        for &s in sa.iter().filter(|s| **s >= k) {
+            tmp[j] = s - k;
+            j += 1;
+        }
+        // Tri stable selon la première clé.
+        count.fill(0);
+        for &i in tmp.iter() {
+            count[rank[i]] += 1;
+        }
+        let mut sum = 0;
+        for c in count.iter_mut() {
+            let current = *c;
+            *c = sum;
+            sum += current;
+        }
+        for &i in tmp.iter() {
+            sa[count[rank[i]]] = i;
+            count[rank[i]] += 1;
+        }
+
+        let key = |i: usize| (rank[i], if i + k < n { rank[i + k] } else { 0 });
+        tmp[sa[0]] = 1;
+        for w in 1..n {
+            tmp[sa[w]] = tmp[sa[w - 1]] + (key(sa[w]) != key(sa[w - 1])) as usize;
+        }
+        std::mem::swap(&mut rank, &mut tmp);
+        if rank[sa[n - 1]] == n {
+            break;
+        }
+        k *= 2;
+    }
+    sa
+}
+
+/// Burrows–Wheeler transform of the source. Return the last column of the
+/// sorted rotations, without the end of source, and the primary index, the
+/// row where the end of source would be.
+///
+/// ```
+/// use final_state_rs::bwt::*;
+///
+/// let (transformed, primary) = encode_bwt(b"banana");
+/// assert_eq!((&transformed[..], primary), (&b"annbaa"[..], 4));
+/// assert_eq!(decode_bwt(&transformed, primary).unwrap(), b"banana");
+/// ```
+pub fn encode_bwt(src: &[u8]) -> (Vec<u8>, usize) {
+    let mut ret = Vec::with_capacity(src.len());
+    let mut primary = 0;
+    // La première ligne est la fin de la source, précédée du dernier octet.
+    if let Some(last) = src.last() {
+        ret.push(*last);
+    }
+    for (row, &s) in suffix_array(src).iter().enumerate() {
+        if s == 0 {
+            primary = row + 1;
+        } else {
+            ret.push(src[s - 1]);
+        }
+    }
+    (ret, primary)
+}
+
+/// Inverse of `encode_bwt`. Each row of the last column is linked to the row
+/// that starts with the same byte (the nth `a` of the last column is the nth
+/// `a` of the first column), we walk these links from the end of the source.
+pub fn decode_bwt(src: &[u8], primary: usize) -> Result<Vec<u8>, BwtError> {
+    let n = src.len();
+    if primary > n || (n > 0 && primary == 0) {
+        return Err(BwtError::InvalidPrimaryIndex(primary));
+    }
+    let mut hist = [0; 256];
+    simple_count_u8_inplace(src, &mut hist);
+    // Première ligne de chaque octet dans la première colonne, après la fin
+    // de source.
+    let mut starts = [0; 256];
+    let mut sum = 1;
+    for (start, count) in starts.iter_mut().zip(hist.iter()) {
+        *start = sum;
+        sum += count;
+    }
+    let mut next = vec![0; n + 1];
+    for (row, link) in next
+        .iter_mut()
+        .enumerate()
+        .filter(|(row, _)| *row != primary)
+    {
+        let byte = src[row - (row > primary) as usize] as usize;
+        *link = starts[byte];
+        starts[byte] += 1;
+    }
+
+    let mut ret = vec![0; n];
+    let mut row = 0;
+    for byte in ret.iter_mut().rev() {
+        if row == primary {
+            return Err(BwtError::InvalidPrimaryIndex(primary));
+        }
+        *byte = src[row - (row > primary) as usize];
+        row = next[row];
+    }
+    Ok(ret)
+}
+
+/// Replace each byte by its position in the list of the bytes, then move it
+/// to the front of the list.
+///
+/// ```
+/// use final_state_rs::bwt::*;
+///
+/// let encoded = encode_mtf(b"aaabbbaaa");
+/// assert_eq!(encoded, [97, 0, 0, 98, 0, 0, 1, 0, 0]);
+/// assert_eq!(decode_mtf(&encoded), b"aaabbbaaa");
+/// ```
+pub fn encode_mtf(src: &[u8]) -> Vec<u8> {
+    let mut list: [u8; 256] = std::array::from_fn(|i| i as u8);
+    src.iter()
+        .map(|byte| {
+            let rank = list.iter().position(|b| b == byte).unwrap();
+            list.copy_within(..rank, 1);
+            list[0] = *byte;
+            rank as u8
+        })
+        .collect()
+}
+
+/// Inverse of `encode_mtf`.
+pub fn decode_mtf(src: &[u8]) -> Vec<u8> {
+    let mut list: [u8; 256] = std::array::from_fn(|i| i as u8);
+    src.iter()
+        .map(|rank| {
+            let byte = list[*rank as usize];
+            list.copy_within(..*rank as usize, 1);
+            list[0] = byte;
+            byte
+        })
+        .collect()
+}
+
+fn write_run(mut run: usize, ret: &mut Vec<u8>) {
+    while run > 0 {
+        run -= 1;
+        ret.push(if run & 1 == 0 { RUN_A } else { RUN_B });
+        run >>= 1;
+    }
+}
+
+/// Write the runs of zeros of a move-to-front output with RUNA and RUNB, the
+/// other ranks are shifted by one.
+///
+/// ```
+/// use final_state_rs::bwt::*;
+///
+/// // Runs of 1, 2 and 3 zeros.
+/// let src = [0, 5, 0, 0, 6, 0, 0, 0];
+/// let encoded = encode_zero_runs(&src);
+/// assert_eq!(encoded, [0, 6, 1, 7, 0, 0]);
+/// assert_eq!(decode_zero_runs(&encoded, src.len()).unwrap(), src);
+/// ```
+pub fn encode_zero_runs(src: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(src.len());
+    let mut run = 0;
+    for &rank in src {
+        if rank == 0 {
+            run += 1;
+            continue;
+        }
+        write_run(run, &mut ret);
+        run = 0;
+        if rank >= ESCAPE - 1 {
+            ret.push(ESCAPE);
+            ret.push(rank - (ESCAPE - 1));
+        } else {
+            ret.push(rank + 1);
+        }
+    }
+    write_run(run, &mut ret);
+    ret
+}
+
+/// Inverse of `encode_zero_runs`, fails with `BwtError::SizeMismatch` as
+/// soon as the output would be greater than `max_size`, the size of the
+/// block. A few symbols write millions of zeros, the bound is checked before
+/// each run is written.
+pub fn decode_zero_runs(src: &[u8], max_size: usize) -> Result<Vec<u8>, BwtError> {
+    let mut ret = Vec::with_capacity(src.len().min(max_size));
+    let mut run = 0;
+    let mut weight = 1;
+    let mut it = src.iter();
+    while let Some(&symbol) = it.next() {
+        if symbol == RUN_A || symbol == RUN_B {
+            run += weight << symbol;
+            weight <<= 1;
+            if run > max_size - ret.len() {
+                return Err(BwtError::SizeMismatch);
+            }
+            continue;
+        }
+        ret.extend(std::iter::repeat_n(0, run));
+        run = 0;
+        weight = 1;
+        if ret.len() == max_size {
+            return Err(BwtError::SizeMismatch);
+        }
+        if symbol == ESCAPE {
+            match it.next() {
+                Some(low @ (0 | 1)) => ret.push(ESCAPE - 1 + low),
+                _ => return Err(BwtError::InvalidEscape),
+            }
+        } else {
+            ret.push(symbol - 1);
+        }
+    }
+    ret.extend(std::iter::repeat_n(0, run));
+    Ok(ret)
+}
+
+/// Compress with BWT, MTF, zero-run coding and tANS, by blocks of
+/// `BLOCK_SIZE`.
+///
+/// Format of a block, integers in little-endian:
+/// - u32: size of the block, u32: primary index,
+/// - u32: number of symbols after the zero-run coding,
+/// - u8: max symbol, then the normalized histogram on `TABLE_LOG`, u16 per
+///   symbol until the max symbol,
+/// - u16: final state of the tANS encoder,
+/// - u32: size of the tANS stream, then the stream. The stream is empty if
+///   the block has only one symbol.
+///
+/// ```
+/// use final_state_rs::bwt::*;
+///
+/// let src = "abracadabra abracadabra abracadabra".as_bytes();
+/// let encoded = encode_bwt_tans(src);
+/// assert_eq!(decode_bwt_tans(&encoded).unwrap(), src);
+/// ```
+pub fn encode_bwt_tans(src: &[u8]) -> Vec<u8> {
+    let mut ret = vec![];
+    for block in src.chunks(BLOCK_SIZE) {
+        let (transformed, primary) = encode_bwt(block);
+        let symbols = encode_zero_runs(&encode_mtf(&transformed));
+        write_block(block.len(), primary, &symbols, &mut ret);
+    }
+    ret
+}
+
+/// Write the header of a block and its symbols after the zero-run coding.
+fn write_block(block_size: usize, primary: usize, symbols: &[u8], ret: &mut Vec<u8>) {
+    let mut hist = [0; 256];
+    let max_symbol = simple_count_u8_inplace(symbols, &mut hist);
+    let (norm, state, stream) = if hist[max_symbol] == symbols.len() {
+        // Un seul symbole, il prend toute la table et le flux est vide.
+        let mut norm = vec![0; max_symbol + 1];
+        norm[max_symbol] = 1 << TABLE_LOG;
+        (norm, 0, vec![])
+    } else {
+        let norm = normalization_with_compensation_binary_heap(&hist, TABLE_LOG, max_symbol)
+            .expect("The histogram of a block can always be normalized");
+        let spread = fse_spread_unsorted(&norm, TABLE_LOG);
+        let mut state = 1 << TABLE_LOG;
+        let (stream, state) = encode_tans(symbols, &norm, &spread, TABLE_LOG, &mut state);
+        (norm, state, stream)
+    };
+
+    ret.extend_from_slice(&(block_size as u32).to_le_bytes());
+    ret.extend_from_slice(&(primary as u32).to_le_bytes());
+    ret.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+    ret.push(max_symbol as u8);
+    for count in norm.iter() {
+        ret.extend_from_slice(&(*count as u16).to_le_bytes());
+    }
+    ret.extend_from_slice(&(state as u16).to_le_bytes());
+    ret.extend_from_slice(&(stream.len() as u32).to_le_bytes());
+    ret.extend_from_slice(&stream);
+}
+
+/// Next `size` bytes of the source.
+fn take<'a>(src: &'a [u8], pos: &mut usize, size: usize) -> Result<&'a [u8], BwtError> {
+    let bytes = src.get(*pos..*pos + size).ok_or(BwtError::Truncated)?;
+    *pos += size;
+    Ok(bytes)
+}
+
+fn take_u32(src: &[u8], pos: &mut usize) -> Result<usize, BwtError> {
+    Ok(u32::from_le_bytes(take(src, pos, 4)?.try_into().unwrap()) as usize)
+}
+
+fn take_u16(src: &[u8], pos: &mut usize) -> Result<usize, BwtError> {
+    Ok(u16::from_le_bytes(take(src, pos, 2)?.try_into().unwrap()) as usize)
+}
+
+/// Decode a stream of `encode_bwt_tans`.
+pub fn decode_bwt_tans(src: &[u8]) -> Result<Vec<u8>, BwtError> {
+    decode_bwt_tans_with_max_size(src, usize::MAX)
+}
+
+/// Decode a stream of `encode_bwt_tans`, fails with `BwtError::TooLarge` if
+/// the decoded stream is greater than `max_size`. The size of each block is
+/// checked before the block is decoded.
+///
+/// ```
+/// use final_state_rs::bwt::*;
+///
+/// let encoded = encode_bwt_tans(&[7; 1000]);
+/// assert_eq!(decode_bwt_tans_with_max_size(&encoded, 1000).unwrap(), [7; 1000]);
+/// assert_eq!(
+///     decode_bwt_tans_with_max_size(&encoded, 999),
+///     Err(BwtError::TooLarge)
+/// );
+/// ```
+pub fn decode_bwt_tans_with_max_size(src: &[u8], max_size: usize) -> Result<Vec<u8>, BwtError> {
+    let mut ret = vec![];
+    let mut pos = 0;
+    while pos < src.len() {
+        let block_size = take_u32(src, &mut pos)?;
+        let primary = take_u32(src, &mut pos)?;
+        let nb_symbols = take_u32(src, &mut pos)?;
+        // Au plus deux symboles par octet (échappement).
+        if block_size > BLOCK_SIZE || nb_symbols > 2 * block_size {
+            return Err(BwtError::SizeMismatch);
+        }
+        if ret.len() + block_size > max_size {
+            return Err(BwtError::TooLarge);
+        }
+        let max_symbol = take(src, &mut pos, 1)?[0] as usize;
+        let norm = (0..=max_symbol)
+            .map(|_| take_u16(src, &mut pos))
+            .collect::<Result<Vec<usize>, BwtError>>()?;
+        let state = take_u16(src, &mut pos)?;
+        if norm.iter().sum::<usize>() != 1 << TABLE_LOG || state >= 1 << TABLE_LOG {
+            return Err(BwtError::InvalidHistogram);
+        }
+        let stream_size = take_u32(src, &mut pos)?;
+        let stream = take(src, &mut pos, stream_size)?.to_vec();
+        let symbols = if stream.is_empty() {
+            if norm[max_symbol] != 1 << TABLE_LOG {
+                return Err(BwtError::InvalidHistogram);
+            }
+            vec![max_symbol as u8; nb_symbols]
+        } else {
+            // Le décodeur tANS s'attend à un flux qui se termine par une
+            // marque.
+            if BitDstream::try_from(stream.clone()).is_err() {
+                return Err(BwtError::Truncated);
+            }
+            let spread = fse_spread_unsorted(&norm, TABLE_LOG);
+            let mut symbols = vec![0; nb_symbols];
+            try_decode_tans(stream, &norm, &spread, TABLE_LOG, state, &mut symbols).map_err(
+                |err| match err {
+                    TansError::Truncated => BwtError::Truncated,
+                    _ => BwtError::InvalidHistogram,
+                },
+            )?;
+            symbols
+        };
+        let transformed = decode_mtf(&decode_zero_runs(&symbols, block_size)?);
+        if transformed.len() != block_size {
+            return Err(BwtError::SizeMismatch);
+        }
+        ret.extend(decode_bwt(&transformed, primary)?);
+    }
+    Ok(ret)
+}
+
+#[test]
+fn bwt_suffix_array() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+
+    for src in [
+        &b""[..],
+        b"a",
+        b"aaaaaaaaaa",
+        b"abababababa",
+        b"mississippi",
+        &book1[..5000],
+    ] {
+        let mut expected = (0..src.len()).collect::<Vec<usize>>();
+        expected.sort_by_key(|i| &src[*i..]);
+        assert_eq!(suffix_array(src), expected);
+        let (transformed, primary) = encode_bwt(src);
+        assert_eq!(decode_bwt(&transformed, primary).unwrap(), src);
+    }
+    assert_eq!(
+        decode_bwt(b"annbaa", 7),
+        Err(BwtError::InvalidPrimaryIndex(7))
+    );
+}
+
+#[test]
+fn bwt_calgary_book1() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+
+    let encoded = encode_bwt_tans(&book1);
+    assert_eq!(decode_bwt_tans(&encoded).unwrap(), book1);
+    // bzip2 -9 atteint 232598 octets, avec un codage de Huffman par groupes
+    // de 50 symboles. Un seul histogramme par bloc est un peu moins bon.
+    assert!(encoded.len() < 260_000, "{}", encoded.len());
+
+    // Les symboles sont directement utilisables avec rANS.
+    let symbols = encode_zero_runs(&encode_mtf(&encode_bwt(&book1[..20000]).0));
+    let mut hist = [0; 256];
+    let max_symbol = simple_count_u8_inplace(&symbols, &mut hist);
+    let norm = normalization_with_compensation_binary_heap(&hist, 13, max_symbol).unwrap();
+    let (state, nb_bits, stream) = crate::r_ans::encode_rans(&norm, 13, &symbols);
+    let decoded = crate::r_ans::decode_rans(state, nb_bits, stream, &norm, 13, symbols.len());
+    assert_eq!(decoded, symbols);
+}
+
+#[test]
+fn bwt_escapes_and_runs() {
+    // Les rangs 254 et 255 sont échappés, les autres décalés de 1.
+    let ranks = [254, 255, 253, 0];
+    let encoded = encode_zero_runs(&ranks);
+    assert_eq!(encoded, [ESCAPE, 0, ESCAPE, 1, 254, RUN_A]);
+    assert_eq!(decode_zero_runs(&encoded, 4).unwrap(), ranks);
+    assert_eq!(decode_zero_runs(&encoded, 3), Err(BwtError::SizeMismatch));
+    // Le second tour de 0..=255 ne donne que des rangs 255.
+    let src = (0..=255).cycle().take(512).collect::<Vec<u8>>();
+    assert!(encode_mtf(&src)[256..].iter().all(|rank| *rank == 255));
+    let encoded = encode_bwt_tans(&src);
+    assert_eq!(decode_bwt_tans(&encoded).unwrap(), src);
+    // La taille maximale borne la somme des blocs.
+    assert_eq!(
+        decode_bwt_tans_with_max_size(&encoded, src.len()).unwrap(),
+        src
+    );
+    assert_eq!(
+        decode_bwt_tans_with_max_size(&encoded, src.len() - 1),
+        Err(BwtError::TooLarge)
+    );
+
+    // Une plage de 2^k - 1 zéros ne s'écrit qu'avec RUNA : un seul symbole,
+    // le bloc n'a pas de flux tANS.
+    for len in [1, 3, 7, 15] {
+        let src = vec![0; len];
+        let encoded = encode_bwt_tans(&src);
+        assert_eq!(encoded.len(), 12 + 1 + 2 + 2 + 4);
+        assert_eq!(decode_bwt_tans(&encoded).unwrap(), src);
+    }
+    // Une plage qui dépasse un bloc.
+    assert_eq!(
+        decode_zero_runs(&[RUN_B; 20], BLOCK_SIZE),
+        Err(BwtError::SizeMismatch)
+    );
+    assert_eq!(
+        decode_zero_runs(&[5, ESCAPE], BLOCK_SIZE),
+        Err(BwtError::InvalidEscape)
+    );
+    assert_eq!(
+        decode_zero_runs(&[5, ESCAPE, 2], BLOCK_SIZE),
+        Err(BwtError::InvalidEscape)
+    );
+}
+
+#[test]
+fn bwt_invalid_blocks() {
+    // Deux blocs, le second de 1000 octets.
+    let src = (0..BLOCK_SIZE + 1000)
+        .map(|i| (i % 7) as u8 * 37)
+        .collect::<Vec<u8>>();
+    assert_eq!(decode_bwt_tans(&encode_bwt_tans(&src)).unwrap(), src);
+
+    let encoded = encode_bwt_tans(b"abracadabra");
+    assert_eq!(
+        decode_bwt_tans(&encoded[..encoded.len() - 1]),
+        Err(BwtError::Truncated)
+    );
+    // La taille du bloc ne correspond pas à ses symboles.
+    let mut corrupted = encoded.clone();
+    corrupted[0] += 1;
+    assert_eq!(decode_bwt_tans(&corrupted), Err(BwtError::SizeMismatch));
+    // L'index primaire est après la fin du bloc.
+    let mut corrupted = encoded.clone();
+    corrupted[4] = 12;
+    assert_eq!(
+        decode_bwt_tans(&corrupted),
+        Err(BwtError::InvalidPrimaryIndex(12))
+    );
+
+    // Un bloc de 1000 octets dont chaque groupe RUNB×18 écrit 524286 zéros :
+    // le décodeur s'arrête dès la première plage, avant de les écrire.
+    let symbols = [&[RUN_B; 18][..], &[2]].concat().repeat(80);
+    let mut forged = vec![];
+    write_block(1000, 1, &symbols, &mut forged);
+    assert_eq!(
+        decode_bwt_tans_with_max_size(&forged, 1 << 22),
+        Err(BwtError::SizeMismatch)
+    );
+    // Deux plages plus petites qu'un bloc, mais plus grandes ensemble.
+    let runs = [&[RUN_B; 18][..], &[2], &[RUN_B; 18]].concat();
+    assert_eq!(
+        decode_zero_runs(&runs, BLOCK_SIZE),
+        Err(BwtError::SizeMismatch)
+    );
+}
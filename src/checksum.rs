@@ -0,0 +1,195 @@
+//! Checksums used by the container formats.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! CRC32 (IEEE 802.3, reflected polynomial 0xEDB88320) as used by gzip, and
+//! CRC32C (Castagnoli, reflected polynomial 0x82F63B78) as used by iSCSI and
+//! the frames of `frame`. The tables are computed at compile time.
+//!
+//! XXH64 of Yann Collet, zstd keeps the 32 lower bits of it as the checksum
+//! of a frame.
+
+const fn make_crc32_table(polynomial: u32) -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            crc = if crc & 1 != 0 {
+                polynomial ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            k += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = make_crc32_table(0xEDB88320);
+static CRC32C_TABLE: [u32; 256] = make_crc32_table(0x82F63B78);
+
+#[inline]
+fn crc_update(table: &[u32; 256], crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc = table[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Continue the computation of a CRC32 with more data, start with a `crc` of
+/// 0.
+///
+/// ```
+/// use final_state_rs::checksum::*;
+///
+/// let crc = crc32_update(0, "1234".as_bytes());
+/// assert_eq!(crc32_update(crc, "56789".as_bytes()), crc32("123456789".as_bytes()));
+/// ```
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    crc_update(&CRC32_TABLE, crc, data)
+}
+
+/// CRC32 of the data.
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Same as `crc32_update` with the polynomial of CRC32C.
+///
+/// ```
+/// use final_state_rs::checksum::*;
+///
+/// let crc = crc32c_update(0, "1234".as_bytes());
+/// assert_eq!(crc32c_update(crc, "56789".as_bytes()), 0xE3069283);
+/// ```
+pub fn crc32c_update(crc: u32, data: &[u8]) -> u32 {
+    crc_update(&CRC32C_TABLE, crc, data)
+}
+
+/// CRC32C of the data.
+pub fn crc32c(data: &[u8]) -> u32 {
+    crc32c_update(0, data)
+}
+
+const PRIME64_1: u64 = 0x9E3779B185EBCA87;
+const PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+const PRIME64_3: u64 = 0x165667B19E3779F9;
+const PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+const PRIME64_5: u64 = 0x27D4EB2F165667C5;
+
+#[inline]
+fn xxh64_round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+#[inline]
+fn xxh64_merge(acc: u64, value: u64) -> u64 {
+    (acc ^ xxh64_round(0, value))
+        .wrapping_mul(PRIME64_1)
+        .wrapping_add(PRIME64_4)
+}
+
+#[inline]
+fn read_u64(data: &[u8]) -> u64 {
+    u64::from_le_bytes(data[..8].try_into().unwrap())
+}
+
+/// XXH64 of the data with a `seed`.
+///
+/// ```
+/// use final_state_rs::checksum::*;
+///
+/// assert_eq!(xxh64(b"", 0), 0xEF46DB3751D8E999);
+/// ```
+pub fn xxh64(data: &[u8], seed: u64) -> u64 {
+    let mut chunks = data.chunks_exact(32);
+    let mut h = if data.len() >= 32 {
+        let mut v = [
+            seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+            seed.wrapping_add(PRIME64_2),
+            seed,
+            seed.wrapping_sub(PRIME64_1),
+        ];
+        for stripe in chunks.by_ref() {
+            for (i, acc) in v.iter_mut().enumerate() {
+                *acc = xxh64_round(*acc, read_u64(&stripe[8 * i..]));
+            }
+        }
+        let h = v[0]
+            .rotate_left(1)
+            .wrapping_add(v[1].rotate_left(7))
+            .wrapping_add(v[2].rotate_left(12))
+            .wrapping_add(v[3].rotate_left(18));
+        v.iter().fold(h, |h, acc| xxh64_merge(h, *acc))
+    } else {
+        seed.wrapping_add(PRIME64_5)
+    };
+    h = h.wrapping_add(data.len() as u64);
+
+    let mut tail = chunks.remainder();
+    while tail.len() >= 8 {
+        h ^= xxh64_round(0, read_u64(tail));
+        h = h
+            .rotate_left(27)
+            .wrapping_mul(PRIME64_1)
+            .wrapping_add(PRIME64_4);
+        tail = &tail[8..];
+    }
+    if tail.len() >= 4 {
+        let k = u32::from_le_bytes(tail[..4].try_into().unwrap()) as u64;
+        h ^= k.wrapping_mul(PRIME64_1);
+        h = h
+            .rotate_left(23)
+            .wrapping_mul(PRIME64_2)
+            .wrapping_add(PRIME64_3);
+        tail = &tail[4..];
+    }
+    for byte in tail {
+        h ^= (*byte as u64).wrapping_mul(PRIME64_5);
+        h = h.rotate_left(11).wrapping_mul(PRIME64_1);
+    }
+
+    // Avalanche
+    h ^= h >> 33;
+    h = h.wrapping_mul(PRIME64_2);
+    h ^= h >> 29;
+    h = h.wrapping_mul(PRIME64_3);
+    h ^ (h >> 32)
+}
+
+#[test]
+fn crc32_check_values() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    assert_eq!(
+        crc32(b"The quick brown fox jumps over the lazy dog"),
+        0x414FA339
+    );
+}
+
+#[test]
+fn crc32c_check_values() {
+    assert_eq!(crc32c(b""), 0);
+    assert_eq!(crc32c(b"123456789"), 0xE3069283);
+    assert_eq!(crc32c(&[0; 32]), 0x8A9136AA);
+    assert_eq!(crc32c(&[0xff; 32]), 0x62A8AB43);
+}
+
+#[test]
+fn xxh64_check_values() {
+    assert_eq!(xxh64(b"", 0), 0xEF46DB3751D8E999);
+    assert_eq!(xxh64(b"a", 0), 0xD24EC4F1A98C6E5B);
+    assert_eq!(xxh64(b"abc", 0), 0x44BC2CF5AD770999);
+}
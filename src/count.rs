@@ -1,9 +1,11 @@
+use alloc::{vec, vec::Vec};
+
 /// Compute an histogram with a very basic method.
 pub fn simple_count_u8_inplace(src: &[u8], ret: &mut [usize; 256]) -> usize {
     let mut max_symbol = 0;
     src.iter().for_each(|&c| {
         ret[c as usize] += 1;
-        max_symbol = std::cmp::max(max_symbol, c as usize)
+        max_symbol = core::cmp::max(max_symbol, c as usize)
     });
     max_symbol
 }
@@ -26,7 +28,7 @@ pub const fn simple_count_u8(src: &[u8]) -> ([usize; 256], usize) {
             break;
         }
         let c = src[i] as usize;
-        ret[c as usize] += 1;
+        ret[c] += 1;
         if max_symbol < c {
             max_symbol = c;
         }
@@ -122,3 +124,421 @@ pub fn divide_and_conquer_count(src: &[u8], split: usize) -> ([usize; 256], usiz
     });
     (ret, max_symbol)
 }
+
+/// Symbols of a larger alphabet than the bytes, counted in a `Vec<u32>`.
+pub trait Symbol: Copy + Send + Sync {
+    fn index(self) -> usize;
+}
+
+impl Symbol for u16 {
+    #[inline]
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Symbol for u32 {
+    #[inline]
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CountError {
+    /// A symbol is greater than the max symbol given to the counter.
+    SymbolOutOfBound(usize),
+    /// A count would become negative after a subtraction.
+    NegativeCount(usize),
+}
+
+/// Increment the counter of a symbol, or fail if it's above the bound.
+#[inline]
+fn increment<T: Symbol>(bucket: &mut [u32], symbol: T) -> Result<(), CountError> {
+    let index = symbol.index();
+    match bucket.get_mut(index) {
+        Some(count) => {
+            *count += 1;
+            Ok(())
+        }
+        None => Err(CountError::SymbolOutOfBound(index)),
+    }
+}
+
+/// Greatest symbol present in the histogram.
+#[inline]
+fn last_symbol(hist: &[u32]) -> usize {
+    hist.iter().rposition(|count| *count > 0).unwrap_or(0)
+}
+
+fn simple_count<T: Symbol>(src: &[T], max_symbol: usize) -> Result<(Vec<u32>, usize), CountError> {
+    let mut ret = vec![0; max_symbol + 1];
+    for symbol in src {
+        increment(&mut ret, *symbol)?;
+    }
+    let max_symbol = last_symbol(&ret);
+    Ok((ret, max_symbol))
+}
+
+fn multi_bucket_count<T: Symbol>(
+    src: &[T],
+    max_symbol: usize,
+) -> Result<(Vec<u32>, usize), CountError> {
+    let mut buckets = [
+        vec![0; max_symbol + 1],
+        vec![0; max_symbol + 1],
+        vec![0; max_symbol + 1],
+        vec![0; max_symbol + 1],
+    ];
+    let mut chunks = src.chunks_exact(4);
+    for chunk in chunks.by_ref() {
+        // Même principe que `multi_bucket_count_u8`, quatre compteurs
+        // indépendants que le processeur peut incrémenter en même temps.
+        increment(&mut buckets[0], chunk[0])?;
+        increment(&mut buckets[1], chunk[1])?;
+        increment(&mut buckets[2], chunk[2])?;
+        increment(&mut buckets[3], chunk[3])?;
+    }
+    for symbol in chunks.remainder() {
+        increment(&mut buckets[0], *symbol)?;
+    }
+    let [mut ret, bucket2, bucket3, bucket4] = buckets;
+    for (i, count) in ret.iter_mut().enumerate() {
+        *count += bucket2[i] + bucket3[i] + bucket4[i];
+    }
+    let max_symbol = last_symbol(&ret);
+    Ok((ret, max_symbol))
+}
+
+#[cfg(feature = "rayon")]
+fn divide_and_conquer_count_large<T: Symbol>(
+    src: &[T],
+    split: usize,
+    max_symbol: usize,
+) -> Result<(Vec<u32>, usize), CountError> {
+    use rayon::prelude::{ParallelIterator, ParallelSlice};
+
+    let chunk_size = std::cmp::max(1, src.len().div_ceil(split));
+    let buckets = src
+        .par_chunks(chunk_size)
+        .map(|chunk| multi_bucket_count(chunk, max_symbol).map(|(bucket, _)| bucket))
+        .collect::<Result<Vec<Vec<u32>>, CountError>>()?;
+
+    let mut ret = vec![0; max_symbol + 1];
+    for bucket in buckets {
+        for (&b, r) in bucket.iter().zip(ret.iter_mut()) {
+            *r += b;
+        }
+    }
+    let max_symbol = last_symbol(&ret);
+    Ok((ret, max_symbol))
+}
+
+/// Histogram of a source of u16 where no symbol is greater than
+/// `max_symbol`. Return the histogram, of `max_symbol + 1` counters, and the
+/// greatest symbol found.
+///
+/// ```
+/// use final_state_rs::count::*;
+///
+/// let (hist, max_symbol) = simple_count_u16(&[1000, 3, 1000], 1023).unwrap();
+/// assert_eq!((hist.len(), hist[1000], hist[3], max_symbol), (1024, 2, 1, 1000));
+/// assert_eq!(
+///     simple_count_u16(&[1024], 1023),
+///     Err(CountError::SymbolOutOfBound(1024))
+/// );
+/// ```
+pub fn simple_count_u16(src: &[u16], max_symbol: usize) -> Result<(Vec<u32>, usize), CountError> {
+    simple_count(src, max_symbol)
+}
+
+/// Same as `simple_count_u16` for a source of u32.
+pub fn simple_count_u32(src: &[u32], max_symbol: usize) -> Result<(Vec<u32>, usize), CountError> {
+    simple_count(src, max_symbol)
+}
+
+/// Same as `simple_count_u16` with four buckets, like `multi_bucket_count_u8`.
+pub fn multi_bucket_count_u16(
+    src: &[u16],
+    max_symbol: usize,
+) -> Result<(Vec<u32>, usize), CountError> {
+    multi_bucket_count(src, max_symbol)
+}
+
+/// Same as `simple_count_u32` with four buckets, like `multi_bucket_count_u8`.
+pub fn multi_bucket_count_u32(
+    src: &[u32],
+    max_symbol: usize,
+) -> Result<(Vec<u32>, usize), CountError> {
+    multi_bucket_count(src, max_symbol)
+}
+
+/// Same as `multi_bucket_count_u16`, the source is split in `split` parts
+/// counted on the rayon pool.
+#[cfg(feature = "rayon")]
+pub fn divide_and_conquer_count_u16(
+    src: &[u16],
+    split: usize,
+    max_symbol: usize,
+) -> Result<(Vec<u32>, usize), CountError> {
+    divide_and_conquer_count_large(src, split, max_symbol)
+}
+
+/// Same as `multi_bucket_count_u32`, the source is split in `split` parts
+/// counted on the rayon pool.
+#[cfg(feature = "rayon")]
+pub fn divide_and_conquer_count_u32(
+    src: &[u32],
+    split: usize,
+    max_symbol: usize,
+) -> Result<(Vec<u32>, usize), CountError> {
+    divide_and_conquer_count_large(src, split, max_symbol)
+}
+
+/// Number of bytes counted in the tables of 32 bits counters before adding
+/// them to the histogram.
+const SEGMENT_SIZE: usize = 1 << 31;
+
+/// Four tables of 32 bits counters, filled four bytes at a time from a
+/// word of 32 bits, like `HIST_count_parallel_wksp` of zstd. The counters
+/// of 32 bits take half the cache of `usize` ones, and a word read at once
+/// saves three loads on four bytes. Return the greatest symbol of `src`.
+///
+/// ```
+/// use final_state_rs::count::*;
+///
+/// let src = "abracadabra".as_bytes();
+/// let mut hist = [0; 256];
+/// let max_symbol = parallel_lanes_count_u8(src, &mut hist);
+/// assert_eq!((hist[b'a' as usize], max_symbol), (5, b'r' as usize));
+/// ```
+pub fn parallel_lanes_count_u8(src: &[u8], ret: &mut [usize; 256]) -> usize {
+    let mut tables = [[0u32; 256]; 4];
+    let mut max_symbol = 0;
+    // Les compteurs de 32 bits ne doivent pas déborder, les tables sont
+    // vidées après chaque segment.
+    for segment in src.chunks(SEGMENT_SIZE) {
+        let mut chunks = segment.chunks_exact(16);
+        for chunk in chunks.by_ref() {
+            for word in chunk.chunks_exact(4) {
+                let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                tables[0][(word & 0xff) as usize] += 1;
+                tables[1][((word >> 8) & 0xff) as usize] += 1;
+                tables[2][((word >> 16) & 0xff) as usize] += 1;
+                tables[3][(word >> 24) as usize] += 1;
+            }
+        }
+        for byte in chunks.remainder() {
+            tables[0][*byte as usize] += 1;
+        }
+        max_symbol = max_symbol.max(flush_tables(&mut tables, ret));
+    }
+    max_symbol
+}
+
+/// Add the 32 bits tables to the histogram and reset them. Return the
+/// greatest symbol counted in the tables, 0 if they are empty.
+#[inline]
+fn flush_tables(tables: &mut [[u32; 256]], ret: &mut [usize; 256]) -> usize {
+    let mut max_symbol = 0;
+    for table in tables.iter_mut() {
+        for (symbol, (r, count)) in ret.iter_mut().zip(table.iter()).enumerate() {
+            *r += *count as usize;
+            if *count > 0 {
+                max_symbol = max_symbol.max(symbol);
+            }
+        }
+        *table = [0; 256];
+    }
+    max_symbol
+}
+
+/// Count with `std::simd`, 16 bytes at a time. Each lane has its own table
+/// of 32 bits counters, the 16 indexes of a vector are always different: the
+/// counters are loaded with a gather, incremented together and stored back
+/// with a scatter without conflict. Return the greatest symbol of `src`.
+///
+/// The gather and the scatter cost more than the four tables of
+/// `parallel_lanes_count_u8`, even with AVX-512 it is about twice slower on
+/// `benches/counters.rs`. `count_u8` doesn't use it.
+#[cfg(feature = "portable_simd")]
+pub fn simd_count_u8(src: &[u8], ret: &mut [usize; 256]) -> usize {
+    use core::simd::{num::SimdUint, u32x16, u8x16, usizex16};
+
+    let mut tables = [[0u32; 256]; 16];
+    let mut max_symbol = 0;
+    let offsets = usizex16::from_array(core::array::from_fn(|lane| lane * 256));
+    for segment in src.chunks(SEGMENT_SIZE) {
+        let mut chunks = segment.chunks_exact(16);
+        for chunk in chunks.by_ref() {
+            let indexes = u8x16::from_slice(chunk).cast::<usize>() + offsets;
+            let counters = tables.as_flattened_mut();
+            let counts = u32x16::gather_or_default(counters, indexes) + u32x16::splat(1);
+            counts.scatter(counters, indexes);
+        }
+        for byte in chunks.remainder() {
+            tables[0][*byte as usize] += 1;
+        }
+        max_symbol = max_symbol.max(flush_tables(&mut tables, ret));
+    }
+    max_symbol
+}
+
+/// Under that size the tables of the other counters cost more than the
+/// counting itself.
+const SMALL_SOURCE: usize = 1500;
+/// Above that size, with the `rayon` feature, the source is split over the
+/// threads of the pool.
+#[cfg(feature = "rayon")]
+const LARGE_SOURCE: usize = 4 << 20;
+
+/// Pick the counter that should be the fastest for the source, depending on
+/// its size and on the features. See `benches/counters.rs`.
+/// Whatever the counter, return the greatest symbol of `src`.
+///
+/// ```
+/// use final_state_rs::count::*;
+///
+/// let mut hist = [0; 256];
+/// let max_symbol = count_u8(&[3, 1, 3], &mut hist);
+/// assert_eq!((hist[3], max_symbol), (2, 3));
+/// ```
+pub fn count_u8(src: &[u8], ret: &mut [usize; 256]) -> usize {
+    if src.len() < SMALL_SOURCE {
+        return simple_count_u8_inplace(src, ret);
+    }
+    #[cfg(feature = "rayon")]
+    if src.len() >= LARGE_SOURCE {
+        let (hist, max_symbol) = divide_and_conquer_count(src, rayon::current_num_threads());
+        for (r, count) in ret.iter_mut().zip(hist.iter()) {
+            *r += count;
+        }
+        return max_symbol;
+    }
+    parallel_lanes_count_u8(src, ret)
+}
+
+/// Histogram of bytes that can be filled block after block, unlike the
+/// counters above that return a fresh array. Useful to follow the statistics
+/// of a stream, or of a sliding window with `subtract`.
+///
+/// The counts are given to the normalization functions with `counts`:
+///
+/// ```
+/// use final_state_rs::count::Histogram;
+/// use final_state_rs::normalization::normalization_with_compensation_binary_heap;
+///
+/// let mut hist = Histogram::new();
+/// hist.add("abracadabra".as_bytes());
+/// hist.add("abracadabra".as_bytes());
+/// assert_eq!((hist.total(), hist.max_symbol()), (22, b'r' as usize));
+/// let norm =
+///     normalization_with_compensation_binary_heap(hist.counts(), 5, hist.max_symbol()).unwrap();
+/// assert_eq!(norm.iter().sum::<usize>(), 1 << 5);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    counts: [usize; 256],
+    total: usize,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Histogram {
+    /// Empty histogram.
+    pub fn new() -> Self {
+        Histogram {
+            counts: [0; 256],
+            total: 0,
+        }
+    }
+
+    /// Histogram of a source.
+    pub fn from_source(src: &[u8]) -> Self {
+        let mut hist = Self::new();
+        hist.add(src);
+        hist
+    }
+
+    /// Estimate the histogram of a source from one byte every `step` bytes,
+    /// the counts are multiplied by `step` so the total stays close to the
+    /// size of the source.
+    ///
+    /// The rare symbols might be missing, the estimate is fine to compare
+    /// sources or to guess a compression ratio, but a table built on it can't
+    /// always encode the whole source.
+    ///
+    /// ```
+    /// use final_state_rs::count::Histogram;
+    ///
+    /// let src = (0..4000).map(|i| (i % 4) as u8 * 2).collect::<Vec<u8>>();
+    /// let estimate = Histogram::sampled(&src, 3);
+    /// assert_eq!(estimate.total(), 4002);
+    /// assert_eq!(estimate.max_symbol(), 6);
+    /// ```
+    pub fn sampled(src: &[u8], step: usize) -> Self {
+        assert!(step > 0, "The step of the sampling should be positive");
+        let mut hist = Self::new();
+        for symbol in src.iter().step_by(step) {
+            hist.counts[*symbol as usize] += step;
+            hist.total += step;
+        }
+        hist
+    }
+
+    /// Count the bytes of `src`.
+    pub fn add(&mut self, src: &[u8]) {
+        count_u8(src, &mut self.counts);
+        self.total += src.len();
+    }
+
+    /// Add the counts of another histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+        self.total += other.total;
+    }
+
+    /// Remove the counts of another histogram, for example the one of a
+    /// block that leaves a window. Fails without any change if a count would
+    /// become negative.
+    pub fn subtract(&mut self, other: &Histogram) -> Result<(), CountError> {
+        if let Some(symbol) = (0..256).find(|&s| other.counts[s] > self.counts[s]) {
+            return Err(CountError::NegativeCount(symbol));
+        }
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count -= other;
+        }
+        self.total -= other.total;
+        Ok(())
+    }
+
+    /// Number of bytes counted.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Greatest symbol counted, 0 if the histogram is empty.
+    pub fn max_symbol(&self) -> usize {
+        self.counts
+            .iter()
+            .rposition(|count| *count > 0)
+            .unwrap_or(0)
+    }
+
+    /// Counts from the symbol 0 to `max_symbol`, the slice expected by the
+    /// normalization functions.
+    pub fn counts(&self) -> &[usize] {
+        &self.counts[..=self.max_symbol()]
+    }
+
+    /// Count of a symbol.
+    pub fn count(&self, symbol: u8) -> usize {
+        self.counts[symbol as usize]
+    }
+}
@@ -0,0 +1,1015 @@
+//! DEFLATE (RFC 1951) encoder and decoder, and the gzip (RFC 1952) framing.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! The encoder searches the matches with the `BinaryTreeMatchFinder` of the
+//! lempel_ziv encoders in a window of 32 KiB, with one step of lazy matching.
+//! The tokens are cut in blocks, and each block is written in the smallest of
+//! the three block types: stored, fixed Huffman codes or dynamic Huffman
+//! codes. The dynamic codes are length-limited Huffman codes (15 bits, 7 bits
+//! for the code lengths alphabet).
+//!
+//! Contrary to `bitstream`, DEFLATE is a FIFO bit stream where the bits
+//! are packed from the least significant bit of each byte. The Huffman codes
+//! are packed from their most significant bit, so they are reversed before
+//! being written. This file has its own small bit writer and bit reader.
+
+use crate::binary_tree::BinaryTreeMatchFinder;
+use crate::checksum::crc32;
+
+/// Size of the window of the DEFLATE format.
+const WINDOW_SIZE: usize = 32768;
+/// Minimum and maximum length of a match.
+const MIN_MATCH: u32 = 3;
+const MAX_MATCH: usize = 258;
+/// Maximum number of nodes visited by the match finder for each position.
+const SEARCH_DEPTH: usize = 64;
+/// Maximum number of tokens in a block.
+const BLOCK_TOKENS: usize = 16384;
+/// End of block symbol.
+const END_OF_BLOCK: usize = 256;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order of the code lengths of the code lengths alphabet in a dynamic block.
+const CL_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DeflateError {
+    /// The stream ends before the last block.
+    Truncated,
+    /// The block type 3 is reserved.
+    InvalidBlockType,
+    /// The length of a stored block doesn't match its complement.
+    InvalidStoredLength,
+    /// The code lengths of a dynamic block don't describe a valid code.
+    InvalidHuffmanCode,
+    /// A symbol that can't appear in a valid stream.
+    InvalidSymbol(usize),
+    /// A distance that goes before the beginning of the output.
+    InvalidDistance(usize),
+    /// The gzip header is malformed or uses an unknown method.
+    InvalidHeader,
+    /// The CRC32 of the gzip trailer doesn't match the decoded data.
+    ChecksumMismatch,
+    /// The size of the gzip trailer doesn't match the decoded data.
+    SizeMismatch,
+    /// The decoded stream would be greater than the maximum size.
+    TooLarge,
+}
+
+/* *************************************************************************
+  Bit writer and reader, LSB first
+************************************************************************  */
+
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    container: u64,
+    nb_bits: u32,
+}
+
+impl BitWriter {
+    #[inline]
+    fn write(&mut self, value: u32, nb_bits: u32) {
+        self.container |= (value as u64) << self.nb_bits;
+        self.nb_bits += nb_bits;
+        while self.nb_bits >= 8 {
+            self.out.push(self.container as u8);
+            self.container >>= 8;
+            self.nb_bits -= 8;
+        }
+    }
+
+    /// Complete the current byte with zeros.
+    fn align(&mut self) {
+        if self.nb_bits > 0 {
+            self.out.push(self.container as u8);
+            self.container = 0;
+            self.nb_bits = 0;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.out
+    }
+}
+
+struct BitReader<'a> {
+    src: &'a [u8],
+    pos: usize,
+    container: u64,
+    nb_bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(src: &'a [u8]) -> Self {
+        BitReader {
+            src,
+            pos: 0,
+            container: 0,
+            nb_bits: 0,
+        }
+    }
+
+    #[inline]
+    fn read(&mut self, nb_bits: u32) -> Result<u32, DeflateError> {
+        while self.nb_bits < nb_bits {
+            let byte = *self.src.get(self.pos).ok_or(DeflateError::Truncated)?;
+            self.container |= (byte as u64) << self.nb_bits;
+            self.pos += 1;
+            self.nb_bits += 8;
+        }
+        let ret = (self.container & ((1 << nb_bits) - 1)) as u32;
+        self.container >>= nb_bits;
+        self.nb_bits -= nb_bits;
+        Ok(ret)
+    }
+
+    /// Drop the bits until the next byte boundary.
+    fn align(&mut self) {
+        let rest = self.nb_bits % 8;
+        self.container >>= rest;
+        self.nb_bits -= rest;
+    }
+
+    /// Number of bytes consumed, the bits read in advance are given back.
+    fn consumed(&self) -> usize {
+        self.pos - (self.nb_bits / 8) as usize
+    }
+}
+
+/* *************************************************************************
+  Huffman codes
+************************************************************************  */
+
+/// Compute the lengths of a Huffman code for the given frequencies, no code
+/// is longer than `limit`. A symbol with a frequency of 0 has a length of 0.
+///
+/// The lengths are computed without limit, then the longest codes are cut
+/// to `limit` and the Kraft inequality is restored like in zlib: a code
+/// shorter than the limit becomes the parent of two codes one bit longer, one
+/// of them taken from the limit. Each step removes exactly one unit of the
+/// excess, the code stays complete. The lengths are then given to the symbols
+/// from the most frequent to the least frequent.
+fn huffman_lengths(freqs: &[u32], limit: u8) -> Vec<u8> {
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+
+    let mut lengths = vec![0u8; freqs.len()];
+    let used: Vec<usize> = (0..freqs.len()).filter(|s| freqs[*s] > 0).collect();
+    match used.len() {
+        0 => return lengths,
+        1 => {
+            lengths[used[0]] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+
+    // Les noeuds 0..used.len() sont les feuilles, les suivants sont les
+    // noeuds internes.
+    let mut parents = vec![usize::MAX; 2 * used.len() - 1];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = used
+        .iter()
+        .enumerate()
+        .map(|(node, s)| Reverse((freqs[*s] as u64, node)))
+        .collect();
+    let mut next = used.len();
+    while heap.len() > 1 {
+        let Reverse((f1, n1)) = heap.pop().unwrap();
+        let Reverse((f2, n2)) = heap.pop().unwrap();
+        parents[n1] = next;
+        parents[n2] = next;
+        heap.push(Reverse((f1 + f2, next)));
+        next += 1;
+    }
+    // La racine est le dernier noeud, un parent a toujours un numéro plus
+    // grand que ses fils: on calcule les profondeurs depuis la racine.
+    let mut depths = vec![0u32; parents.len()];
+    for node in (0..parents.len() - 1).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+    let limit = limit as usize;
+    let mut bl_count = vec![0u64; limit + 1];
+    for depth in depths.iter().take(used.len()) {
+        bl_count[std::cmp::min(*depth as usize, limit)] += 1;
+    }
+
+    let kraft =
+        |bl_count: &[u64]| -> u64 { (1..=limit).map(|len| bl_count[len] << (limit - len)).sum() };
+    while kraft(&bl_count) > 1 << limit {
+        let bits = (1..limit).rev().find(|bits| bl_count[*bits] > 0).unwrap();
+        bl_count[bits] -= 1;
+        bl_count[bits + 1] += 2;
+        bl_count[limit] -= 1;
+    }
+
+    let mut by_freq = used.clone();
+    by_freq.sort_by_key(|s| std::cmp::Reverse(freqs[*s]));
+    let mut symbols = by_freq.iter();
+    for (len, count) in bl_count.iter().enumerate() {
+        for s in symbols.by_ref().take(*count as usize) {
+            lengths[*s] = len as u8;
+        }
+    }
+    lengths
+}
+
+/// Canonical codes of the given lengths, already reversed to be written in
+/// the LSB first bit stream.
+fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut bl_count = [0u32; 16];
+    for len in lengths.iter().filter(|len| **len > 0) {
+        bl_count[*len as usize] += 1;
+    }
+    let mut next_code = [0u32; 16];
+    let mut code = 0;
+    for bits in 1..16 {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+    lengths
+        .iter()
+        .map(|len| {
+            if *len == 0 {
+                return 0;
+            }
+            let code = next_code[*len as usize];
+            next_code[*len as usize] += 1;
+            code.reverse_bits() >> (32 - *len as u32)
+        })
+        .collect()
+}
+
+fn fixed_lit_lengths() -> Vec<u8> {
+    (0..288)
+        .map(|s| match s {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        })
+        .collect()
+}
+
+/// Decoding table of a canonical Huffman code: the number of codes of each
+/// length, and the symbols ordered by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build the table, an incomplete code is accepted only if `incomplete`.
+    fn new(lengths: &[u8], incomplete: bool) -> Result<Self, DeflateError> {
+        let mut counts = [0u16; 16];
+        for len in lengths.iter() {
+            counts[*len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut left: i32 = 1;
+        for count in counts.iter().skip(1) {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(DeflateError::InvalidHuffmanCode);
+            }
+        }
+        // Un code d'un seul symbole est toujours incomplet.
+        let nb_codes: u16 = counts.iter().sum();
+        if left > 0 && !incomplete && nb_codes != 1 {
+            return Err(DeflateError::InvalidHuffmanCode);
+        }
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; nb_codes as usize];
+        for (symbol, len) in lengths.iter().enumerate() {
+            if *len > 0 {
+                symbols[offsets[*len as usize] as usize] = symbol as u16;
+                offsets[*len as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    /// Decode one symbol, bit per bit.
+    fn decode(&self, reader: &mut BitReader) -> Result<usize, DeflateError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..16 {
+            code |= reader.read(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(DeflateError::InvalidHuffmanCode)
+    }
+}
+
+/* *************************************************************************
+  Encoder
+************************************************************************  */
+
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { len: u16, dist: u16 },
+}
+
+#[inline]
+fn length_code(len: u16) -> usize {
+    LENGTH_BASE.iter().rposition(|base| *base <= len).unwrap()
+}
+
+#[inline]
+fn dist_code(dist: u16) -> usize {
+    DIST_BASE.iter().rposition(|base| *base <= dist).unwrap()
+}
+
+/// Find the tokens of the source with the binary tree match finder. Before
+/// taking a match, the next position is checked: if it gives a longer match,
+/// the current symbol is written as a literal (lazy matching).
+fn find_tokens(src: &[u8]) -> Vec<Token> {
+    let mut finder = BinaryTreeMatchFinder::new(src, WINDOW_SIZE, SEARCH_DEPTH, MAX_MATCH);
+    let best = |finder: &mut BinaryTreeMatchFinder, index: usize| -> Option<(u32, usize)> {
+        if index >= src.len() {
+            return None;
+        }
+        match finder.find_matches(index).last() {
+            Some(m) if m.len >= MIN_MATCH => Some((m.len, index - m.index)),
+            _ => None,
+        }
+    };
+    let mut tokens = vec![];
+    let mut index = 0;
+    let mut current = best(&mut finder, 0);
+    while index < src.len() {
+        let (len, dist) = match current {
+            None => {
+                tokens.push(Token::Literal(src[index]));
+                index += 1;
+                current = best(&mut finder, index);
+                continue;
+            }
+            Some(m) => m,
+        };
+        let next = best(&mut finder, index + 1);
+        if let Some((next_len, _)) = next {
+            if next_len > len {
+                tokens.push(Token::Literal(src[index]));
+                index += 1;
+                current = next;
+                continue;
+            }
+        }
+        tokens.push(Token::Match {
+            len: len as u16,
+            dist: dist as u16,
+        });
+        // La position suivante est déjà dans l'arbre.
+        for i in index + 2..index + len as usize {
+            finder.skip(i);
+        }
+        index += len as usize;
+        current = best(&mut finder, index);
+    }
+    tokens
+}
+
+/// Write the tokens with the given lengths of the literal/length and distance
+/// codes.
+fn write_tokens(tokens: &[Token], lit_lengths: &[u8], dist_lengths: &[u8], w: &mut BitWriter) {
+    let lit_codes = canonical_codes(lit_lengths);
+    let dist_codes = canonical_codes(dist_lengths);
+    for token in tokens {
+        match *token {
+            Token::Literal(symbol) => {
+                w.write(
+                    lit_codes[symbol as usize],
+                    lit_lengths[symbol as usize] as u32,
+                );
+            }
+            Token::Match { len, dist } => {
+                let code = length_code(len);
+                w.write(lit_codes[257 + code], lit_lengths[257 + code] as u32);
+                w.write((len - LENGTH_BASE[code]) as u32, LENGTH_EXTRA[code] as u32);
+                let code = dist_code(dist);
+                w.write(dist_codes[code], dist_lengths[code] as u32);
+                w.write((dist - DIST_BASE[code]) as u32, DIST_EXTRA[code] as u32);
+            }
+        }
+    }
+    w.write(lit_codes[END_OF_BLOCK], lit_lengths[END_OF_BLOCK] as u32);
+}
+
+/// Size in bits of the tokens with the given lengths.
+fn tokens_cost(
+    lit_freqs: &[u32],
+    dist_freqs: &[u32],
+    lit_lengths: &[u8],
+    dist_lengths: &[u8],
+) -> u64 {
+    let mut cost = 0;
+    for (s, freq) in lit_freqs.iter().enumerate() {
+        let extra = if s > END_OF_BLOCK {
+            LENGTH_EXTRA[s - 257] as u64
+        } else {
+            0
+        };
+        cost += *freq as u64 * (lit_lengths[s] as u64 + extra);
+    }
+    for (s, freq) in dist_freqs.iter().enumerate() {
+        cost += *freq as u64 * (dist_lengths[s] as u64 + DIST_EXTRA[s] as u64);
+    }
+    cost
+}
+
+/// Run-length encoding of the code lengths of a dynamic block, with the
+/// symbols 16 (repeat the previous length), 17 and 18 (repeat a zero). Return
+/// the symbols and their extra bits.
+fn code_lengths_rle(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut ret = vec![];
+    let mut i = 0;
+    while i < lengths.len() {
+        let len = lengths[i];
+        let run = lengths[i..].iter().take_while(|l| **l == len).count();
+        i += run;
+        let mut rest = run;
+        if len == 0 {
+            while rest >= 11 {
+                let k = std::cmp::min(rest, 138);
+                ret.push((18, (k - 11) as u8));
+                rest -= k;
+            }
+            if rest >= 3 {
+                ret.push((17, (rest - 3) as u8));
+                rest = 0;
+            }
+        } else {
+            ret.push((len, 0));
+            rest -= 1;
+            while rest >= 3 {
+                let k = std::cmp::min(rest, 6);
+                ret.push((16, (k - 3) as u8));
+                rest -= k;
+            }
+        }
+        ret.extend(std::iter::repeat_n((len, 0), rest));
+    }
+    ret
+}
+
+/// Write the block of `tokens`, which encode `src`, in the smallest block type.
+fn write_block(tokens: &[Token], src: &[u8], last: bool, w: &mut BitWriter) {
+    let mut lit_freqs = vec![0u32; 286];
+    let mut dist_freqs = vec![0u32; 30];
+    for token in tokens {
+        match *token {
+            Token::Literal(symbol) => lit_freqs[symbol as usize] += 1,
+            Token::Match { len, dist } => {
+                lit_freqs[257 + length_code(len)] += 1;
+                dist_freqs[dist_code(dist)] += 1;
+            }
+        }
+    }
+    lit_freqs[END_OF_BLOCK] += 1;
+
+    // Certains décodeurs refusent un code d'un seul symbole, on en garde
+    // toujours deux.
+    let mut dyn_lit_freqs = lit_freqs.clone();
+    let mut dyn_dist_freqs = dist_freqs.clone();
+    for freqs in [&mut dyn_lit_freqs, &mut dyn_dist_freqs] {
+        for s in 0..2 {
+            if freqs.iter().filter(|f| **f > 0).count() < 2 && freqs[s] == 0 {
+                freqs[s] = 1;
+            }
+        }
+    }
+    let lit_lengths = huffman_lengths(&dyn_lit_freqs, 15);
+    let dist_lengths = huffman_lengths(&dyn_dist_freqs, 15);
+    let hlit = std::cmp::max(257, lit_lengths.iter().rposition(|l| *l > 0).unwrap() + 1);
+    let hdist = std::cmp::max(1, dist_lengths.iter().rposition(|l| *l > 0).unwrap() + 1);
+    let rle = code_lengths_rle(&[&lit_lengths[..hlit], &dist_lengths[..hdist]].concat());
+    let mut cl_freqs = vec![0u32; 19];
+    for (symbol, _) in rle.iter() {
+        cl_freqs[*symbol as usize] += 1;
+    }
+    let cl_lengths = huffman_lengths(&cl_freqs, 7);
+    let hclen = std::cmp::max(
+        4,
+        CL_ORDER.iter().rposition(|s| cl_lengths[*s] > 0).unwrap() + 1,
+    );
+    let rle_cost: u64 = rle
+        .iter()
+        .map(|(symbol, _)| {
+            let extra = match symbol {
+                16 => 2,
+                17 => 3,
+                18 => 7,
+                _ => 0,
+            };
+            cl_lengths[*symbol as usize] as u64 + extra
+        })
+        .sum();
+    let dynamic_cost = 3
+        + 14
+        + 3 * hclen as u64
+        + rle_cost
+        + tokens_cost(&lit_freqs, &dist_freqs, &lit_lengths, &dist_lengths);
+
+    let fixed_lit = fixed_lit_lengths();
+    let fixed_dist = vec![5u8; 30];
+    let fixed_cost = 3 + tokens_cost(&lit_freqs, &dist_freqs, &fixed_lit, &fixed_dist);
+
+    // Un bloc stocké est aligné sur l'octet et ne dépasse pas 65535 octets.
+    let stored_cost = (src.len().div_ceil(65535).max(1) * (3 + 7 + 32) + 8 * src.len()) as u64;
+
+    if stored_cost <= fixed_cost && stored_cost <= dynamic_cost {
+        let mut chunks: Vec<&[u8]> = src.chunks(65535).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+        let nb_chunks = chunks.len();
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            w.write((last && i + 1 == nb_chunks) as u32, 1);
+            w.write(0, 2);
+            w.align();
+            w.write(chunk.len() as u32, 16);
+            w.write(!chunk.len() as u32 & 0xffff, 16);
+            for byte in chunk {
+                w.write(*byte as u32, 8);
+            }
+        }
+    } else if fixed_cost <= dynamic_cost {
+        w.write(last as u32, 1);
+        w.write(1, 2);
+        write_tokens(tokens, &fixed_lit, &fixed_dist, w);
+    } else {
+        w.write(last as u32, 1);
+        w.write(2, 2);
+        w.write((hlit - 257) as u32, 5);
+        w.write((hdist - 1) as u32, 5);
+        w.write((hclen - 4) as u32, 4);
+        for s in CL_ORDER.iter().take(hclen) {
+            w.write(cl_lengths[*s] as u32, 3);
+        }
+        let cl_codes = canonical_codes(&cl_lengths);
+        for (symbol, extra) in rle {
+            w.write(
+                cl_codes[symbol as usize],
+                cl_lengths[symbol as usize] as u32,
+            );
+            match symbol {
+                16 => w.write(extra as u32, 2),
+                17 => w.write(extra as u32, 3),
+                18 => w.write(extra as u32, 7),
+                _ => {}
+            }
+        }
+        write_tokens(tokens, &lit_lengths, &dist_lengths, w);
+    }
+}
+
+/// Encode a source in a raw DEFLATE stream.
+///
+/// ```
+/// use final_state_rs::deflate::*;
+///
+/// let src = "Lorem ipsum dolor sit amet, Lorem ipsum dolor sit amet.".as_bytes();
+/// let encoded = encode_deflate(src);
+/// assert!(encoded.len() < src.len());
+/// assert_eq!(decode_deflate(&encoded).unwrap(), src);
+/// ```
+pub fn encode_deflate(src: &[u8]) -> Vec<u8> {
+    let tokens = find_tokens(src);
+    let mut w = BitWriter::default();
+    let mut start = 0;
+    let mut blocks = tokens.chunks(BLOCK_TOKENS).peekable();
+    if blocks.peek().is_none() {
+        write_block(&[], &[], true, &mut w);
+    }
+    while let Some(block) = blocks.next() {
+        let len: usize = block
+            .iter()
+            .map(|token| match token {
+                Token::Literal(_) => 1,
+                Token::Match { len, .. } => *len as usize,
+            })
+            .sum();
+        write_block(
+            block,
+            &src[start..start + len],
+            blocks.peek().is_none(),
+            &mut w,
+        );
+        start += len;
+    }
+    w.finish()
+}
+
+/* *************************************************************************
+  Decoder
+************************************************************************  */
+
+/// Decode a raw DEFLATE stream of at most `max_size` bytes. Return the output
+/// and the number of bytes consumed in `src`.
+fn inflate(src: &[u8], max_size: usize) -> Result<(Vec<u8>, usize), DeflateError> {
+    let mut reader = BitReader::new(src);
+    let mut ret = vec![];
+    loop {
+        let last = reader.read(1)?;
+        match reader.read(2)? {
+            0 => {
+                reader.align();
+                let len = reader.read(16)?;
+                let nlen = reader.read(16)?;
+                if len != !nlen & 0xffff {
+                    return Err(DeflateError::InvalidStoredLength);
+                }
+                if ret.len() + len as usize > max_size {
+                    return Err(DeflateError::TooLarge);
+                }
+                for _ in 0..len {
+                    ret.push(reader.read(8)? as u8);
+                }
+            }
+            1 => {
+                let lit = Huffman::new(&fixed_lit_lengths(), false)?;
+                let dist = Huffman::new(&[5; 30], true)?;
+                inflate_block(&mut reader, &lit, &dist, &mut ret, max_size)?;
+            }
+            2 => {
+                let (lit, dist) = read_dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &lit, &dist, &mut ret, max_size)?;
+            }
+            _ => return Err(DeflateError::InvalidBlockType),
+        }
+        if last == 1 {
+            return Ok((ret, reader.consumed()));
+        }
+    }
+}
+
+fn read_dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), DeflateError> {
+    let hlit = reader.read(5)? as usize + 257;
+    let hdist = reader.read(5)? as usize + 1;
+    let hclen = reader.read(4)? as usize + 4;
+    if hlit > 286 || hdist > 30 {
+        return Err(DeflateError::InvalidHuffmanCode);
+    }
+    let mut cl_lengths = [0u8; 19];
+    for s in CL_ORDER.iter().take(hclen) {
+        cl_lengths[*s] = reader.read(3)? as u8;
+    }
+    let cl = Huffman::new(&cl_lengths, false)?;
+    let mut lengths = vec![];
+    while lengths.len() < hlit + hdist {
+        let (len, repeat) = match cl.decode(reader)? {
+            16 => {
+                let previous = *lengths.last().ok_or(DeflateError::InvalidHuffmanCode)?;
+                (previous, 3 + reader.read(2)?)
+            }
+            17 => (0, 3 + reader.read(3)?),
+            18 => (0, 11 + reader.read(7)?),
+            len => (len as u8, 1),
+        };
+        if lengths.len() + repeat as usize > hlit + hdist {
+            return Err(DeflateError::InvalidHuffmanCode);
+        }
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths[END_OF_BLOCK] == 0 {
+        return Err(DeflateError::InvalidHuffmanCode);
+    }
+    let lit = Huffman::new(&lengths[..hlit], false)?;
+    let dist = Huffman::new(&lengths[hlit..], true)?;
+    Ok((lit, dist))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    lit: &Huffman,
+    dist: &Huffman,
+    ret: &mut Vec<u8>,
+    max_size: usize,
+) -> Result<(), DeflateError> {
+    loop {
+        let symbol = lit.decode(reader)?;
+        if symbol < END_OF_BLOCK {
+            if ret.len() == max_size {
+                return Err(DeflateError::TooLarge);
+            }
+            ret.push(symbol as u8);
+            continue;
+        }
+        if symbol == END_OF_BLOCK {
+            return Ok(());
+        }
+        let code = symbol - 257;
+        if code >= 29 {
+            return Err(DeflateError::InvalidSymbol(symbol));
+        }
+        let len = LENGTH_BASE[code] as usize + reader.read(LENGTH_EXTRA[code] as u32)? as usize;
+        let code = dist.decode(reader)?;
+        if code >= 30 {
+            return Err(DeflateError::InvalidSymbol(code));
+        }
+        let distance = DIST_BASE[code] as usize + reader.read(DIST_EXTRA[code] as u32)? as usize;
+        if distance > ret.len() {
+            return Err(DeflateError::InvalidDistance(distance));
+        }
+        if ret.len() + len > max_size {
+            return Err(DeflateError::TooLarge);
+        }
+        // La copie peut chevaucher les octets qu'elle produit.
+        let start = ret.len() - distance;
+        for i in start..start + len {
+            ret.push(ret[i]);
+        }
+    }
+}
+
+/// Decode a raw DEFLATE stream.
+pub fn decode_deflate(src: &[u8]) -> Result<Vec<u8>, DeflateError> {
+    decode_deflate_with_max_size(src, usize::MAX)
+}
+
+/// Decode a raw DEFLATE stream, fails with `DeflateError::TooLarge` if the
+/// decoded stream is greater than `max_size`. A match of a few bits produces
+/// 258 bytes, a stream from an untrusted source should be decoded with a
+/// bound.
+///
+/// ```
+/// use final_state_rs::deflate::*;
+///
+/// let encoded = encode_deflate(&[7; 1000]);
+/// assert_eq!(decode_deflate_with_max_size(&encoded, 1000).unwrap(), [7; 1000]);
+/// assert_eq!(
+///     decode_deflate_with_max_size(&encoded, 999),
+///     Err(DeflateError::TooLarge)
+/// );
+/// ```
+pub fn decode_deflate_with_max_size(src: &[u8], max_size: usize) -> Result<Vec<u8>, DeflateError> {
+    inflate(src, max_size).map(|(ret, _)| ret)
+}
+
+/* *************************************************************************
+  gzip
+************************************************************************  */
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+
+/// Encode a source in a gzip member, readable by `gzip -d`.
+///
+/// ```
+/// use final_state_rs::deflate::*;
+///
+/// let src = "Lorem ipsum dolor sit amet, Lorem ipsum dolor sit amet.".as_bytes();
+/// let encoded = encode_gzip(src);
+/// assert_eq!(decode_gzip(&encoded).unwrap(), src);
+/// ```
+pub fn encode_gzip(src: &[u8]) -> Vec<u8> {
+    // Méthode 8 (deflate), pas de drapeau, pas de date, système inconnu.
+    let mut ret = vec![GZIP_MAGIC[0], GZIP_MAGIC[1], 8, 0, 0, 0, 0, 0, 0, 255];
+    ret.append(&mut encode_deflate(src));
+    ret.extend_from_slice(&crc32(src).to_le_bytes());
+    ret.extend_from_slice(&(src.len() as u32).to_le_bytes());
+    ret
+}
+
+/// Decode a gzip file, the members are concatenated.
+pub fn decode_gzip(src: &[u8]) -> Result<Vec<u8>, DeflateError> {
+    decode_gzip_with_max_size(src, usize::MAX)
+}
+
+/// Decode a gzip file, fails with `DeflateError::TooLarge` if the
+/// concatenated members are greater than `max_size`.
+pub fn decode_gzip_with_max_size(src: &[u8], max_size: usize) -> Result<Vec<u8>, DeflateError> {
+    let mut ret = vec![];
+    let mut pos = 0;
+    loop {
+        let header = src.get(pos..pos + 10).ok_or(DeflateError::Truncated)?;
+        if header[..2] != GZIP_MAGIC || header[2] != 8 || header[3] & 0xe0 != 0 {
+            return Err(DeflateError::InvalidHeader);
+        }
+        let flags = header[3];
+        pos += 10;
+        if flags & FEXTRA != 0 {
+            let xlen = src.get(pos..pos + 2).ok_or(DeflateError::Truncated)?;
+            pos += 2 + u16::from_le_bytes([xlen[0], xlen[1]]) as usize;
+        }
+        for flag in [FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                let rest = src.get(pos..).ok_or(DeflateError::Truncated)?;
+                pos += rest
+                    .iter()
+                    .position(|b| *b == 0)
+                    .ok_or(DeflateError::Truncated)?
+                    + 1;
+            }
+        }
+        if flags & FHCRC != 0 {
+            pos += 2;
+        }
+        let member = src.get(pos..).ok_or(DeflateError::Truncated)?;
+        let (member, consumed) = inflate(member, max_size - ret.len())?;
+        pos += consumed;
+        let trailer = src.get(pos..pos + 8).ok_or(DeflateError::Truncated)?;
+        if u32::from_le_bytes(trailer[..4].try_into().unwrap()) != crc32(&member) {
+            return Err(DeflateError::ChecksumMismatch);
+        }
+        if u32::from_le_bytes(trailer[4..].try_into().unwrap()) != member.len() as u32 {
+            return Err(DeflateError::SizeMismatch);
+        }
+        pos += 8;
+        ret.extend_from_slice(&member);
+        if pos == src.len() {
+            return Ok(ret);
+        }
+    }
+}
+
+/// The files in `rsc/deflate` were produced by `gzip` 1.12: dynamic blocks
+/// with -1 and -9, a file name in the header of `records.gz`, stored blocks
+/// for random data, and an empty file.
+#[test]
+fn gzip_reference_files() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+    let book1 = &book1[..20000];
+    let records = read_rsc("./rsc/lz4/records");
+    let random = read_rsc("./rsc/deflate/random");
+
+    for (file, expected) in [
+        ("./rsc/deflate/book1_20k_1.gz", book1),
+        ("./rsc/deflate/book1_20k_9.gz", book1),
+        ("./rsc/deflate/records.gz", &records),
+        ("./rsc/deflate/random.gz", &random),
+        ("./rsc/deflate/empty.gz", &[]),
+    ] {
+        let file = read_rsc(file);
+        assert_eq!(decode_gzip(&file).unwrap(), expected);
+
+        // Round trip with our encoder, not far from gzip. On `records`, zlib
+        // itself is 10% larger with -9 than with -6.
+        let encoded = encode_gzip(expected);
+        assert_eq!(decode_gzip(&encoded).unwrap(), expected);
+        assert!(encoded.len() <= file.len() * 11 / 10);
+    }
+}
+
+#[test]
+fn deflate_block_types() {
+    // Un bloc stocké de 3 octets, puis un bloc fixe vide.
+    assert_eq!(
+        decode_deflate(&[0x00, 3, 0, 0xfc, 0xff, b'a', b'b', b'c', 0x03, 0x00]).unwrap(),
+        b"abc"
+    );
+    assert_eq!(decode_deflate(&[0x03, 0x00]).unwrap(), []);
+    assert_eq!(
+        decode_deflate(&[0x01, 3, 0, 0, 0]),
+        Err(DeflateError::InvalidStoredLength)
+    );
+    assert_eq!(decode_deflate(&[0x07]), Err(DeflateError::InvalidBlockType));
+    assert_eq!(
+        decode_deflate_with_max_size(&[0x00, 3, 0, 0xfc, 0xff, b'a', b'b', b'c', 0x03, 0x00], 2),
+        Err(DeflateError::TooLarge)
+    );
+
+    // Des octets incompressibles, dans des blocs stockés avec 5 octets
+    // d'en-tête.
+    let random = (0..70_000)
+        .map(|_| rand::random::<u8>())
+        .collect::<Vec<u8>>();
+    let encoded = encode_deflate(&random);
+    assert_eq!(decode_deflate(&encoded).unwrap(), random);
+    assert!(encoded.len() <= random.len() + 5 * random.len().div_ceil(BLOCK_TOKENS));
+
+    // Une répétition à la distance maximale, en matchs de 258 octets.
+    let src = [&random[..WINDOW_SIZE], &random[..WINDOW_SIZE]].concat();
+    let encoded = encode_deflate(&src);
+    assert_eq!(decode_deflate(&encoded).unwrap(), src);
+    assert!(encoded.len() < WINDOW_SIZE + 1000, "{}", encoded.len());
+    assert_eq!(
+        decode_deflate_with_max_size(&encoded, src.len()).unwrap(),
+        src
+    );
+    assert_eq!(
+        decode_deflate_with_max_size(&encoded, src.len() - 1),
+        Err(DeflateError::TooLarge)
+    );
+}
+
+#[test]
+fn gzip_members_and_errors() {
+    // Un membre avec un nom de fichier et un commentaire.
+    let mut member = vec![0x1f, 0x8b, 8, FNAME | FCOMMENT, 0, 0, 0, 0, 0, 3];
+    member.extend_from_slice(b"hello.txt\0a comment\0");
+    member.append(&mut encode_deflate(b"hello "));
+    member.extend_from_slice(&crc32(b"hello ").to_le_bytes());
+    member.extend_from_slice(&6u32.to_le_bytes());
+    let encoded = [member, encode_gzip(b"world")].concat();
+    assert_eq!(decode_gzip(&encoded).unwrap(), b"hello world");
+    // La taille maximale borne la somme des membres.
+    assert_eq!(
+        decode_gzip_with_max_size(&encoded, 11).unwrap(),
+        b"hello world"
+    );
+    assert_eq!(
+        decode_gzip_with_max_size(&encoded, 10),
+        Err(DeflateError::TooLarge)
+    );
+
+    let mut encoded = encode_gzip(b"hello world");
+    assert_eq!(
+        decode_gzip(&encoded[..encoded.len() - 1]),
+        Err(DeflateError::Truncated)
+    );
+    let len = encoded.len();
+    encoded[len - 1] ^= 1;
+    assert_eq!(decode_gzip(&encoded), Err(DeflateError::SizeMismatch));
+    encoded[len - 8] ^= 1;
+    assert_eq!(decode_gzip(&encoded), Err(DeflateError::ChecksumMismatch));
+    // Méthode 7 au lieu de deflate.
+    assert_eq!(
+        decode_gzip(&[0x1f, 0x8b, 7, 0, 0, 0, 0, 0, 0, 0]),
+        Err(DeflateError::InvalidHeader)
+    );
+}
+
+#[test]
+fn deflate_length_limit() {
+    use rand::seq::SliceRandom;
+
+    // Les codes limités restent complets, comme zlib l'exige.
+    let complete = |lengths: &[u8], limit: u8| {
+        let kraft: u64 = lengths
+            .iter()
+            .filter(|len| **len > 0)
+            .map(|len| 1 << (limit - len))
+            .sum();
+        kraft == 1 << limit && lengths.iter().all(|len| *len <= limit)
+    };
+    for _ in 0..1000 {
+        let freqs = (0..19)
+            .map(|_| match rand::random::<u8>() % 3 {
+                0 => 0,
+                _ => 1 << (rand::random::<u32>() % 20),
+            })
+            .collect::<Vec<u32>>();
+        let lengths = huffman_lengths(&freqs, 7);
+        if freqs.iter().filter(|freq| **freq > 0).count() > 1 {
+            assert!(complete(&lengths, 7), "{freqs:?} {lengths:?}");
+        }
+    }
+    // Des fréquences de Fibonacci donnent un arbre de profondeur 25.
+    let mut fib = vec![1u32, 1];
+    while fib.len() < 26 {
+        fib.push(fib[fib.len() - 1] + fib[fib.len() - 2]);
+    }
+    assert!(complete(&huffman_lengths(&fib, 15), 15));
+
+    // Une source mélangée avec des poids très inégaux, le code des
+    // littéraux dépasse 15 bits sans la limite.
+    let mut src = fib
+        .iter()
+        .enumerate()
+        .flat_map(|(byte, count)| std::iter::repeat_n(byte as u8 * 9, *count as usize / 4 + 1))
+        .collect::<Vec<u8>>();
+    for _ in 0..10 {
+        src.shuffle(&mut rand::thread_rng());
+        assert_eq!(decode_deflate(&encode_deflate(&src)).unwrap(), src);
+    }
+}
@@ -0,0 +1,534 @@
+//! C interface of the tANS and rANS coders.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! The tables are opaque objects: `fs_*_create` allocates an empty table,
+//! `fs_*_build` normalizes a histogram and prepares the encoding and the
+//! decoding tables, then the table encodes and decodes as many buffers as
+//! needed until `fs_*_free`. The normalization is deterministic, the encoder
+//! and the decoder build the same table from the same counts.
+//!
+//! Every function but `create` and `free` returns an `FsError`, `FS_OK` on
+//! success. A panic never crosses the interface, it becomes `FS_PANIC`.
+//!
+//! The header `include/final_state.h` is generated from this file by the test
+//! `tests/ffi.rs`, run it with `BLESS=1` after a change of the interface.
+//!
+//! The rANS encoder writes the size of its flushes before the stream:
+//! `[number of flushes: u32 LE][bits of each flush: u8...][stream]`.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::bitstream::{BitDstream, BitEstream};
+use crate::normalization::normalization_with_compensation_binary_heap;
+use crate::r_ans::{encode_rans, try_decode_rans, RansError};
+use crate::spreads::fse_spread_unsorted;
+use crate::t_ans::{build_decode_table, build_encode_table, encode_symbol};
+
+/// Smallest table log accepted by `fs_tans_build` and `fs_rans_build`.
+pub const FS_MIN_TABLE_LOG: u32 = 5;
+/// Greatest table log accepted by `fs_tans_build` and `fs_rans_build`.
+pub const FS_MAX_TABLE_LOG: u32 = 15;
+
+/// Result of the functions of the interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub enum FsError {
+    /// Success.
+    Ok = 0,
+    /// A pointer is null while its length isn't 0.
+    NullPointer = 1,
+    /// The table log is out of [FS_MIN_TABLE_LOG, FS_MAX_TABLE_LOG].
+    InvalidTableLog = 2,
+    /// The histogram has more than 256 symbols, or less than two symbols
+    /// with a count.
+    InvalidHistogram = 3,
+    /// The table hasn't been built.
+    NotBuilt = 4,
+    /// The source contains a symbol without count in the histogram.
+    InvalidSymbol = 5,
+    /// The destination is too small, the required size is written in
+    /// `dst_len`.
+    DstTooSmall = 6,
+    /// The stream ends before the last symbol.
+    Truncated = 7,
+    /// The state doesn't belong to the table.
+    InvalidState = 8,
+    /// Unexpected panic of the library.
+    Panic = 9,
+}
+
+/// Normalized histogram shared by the two coders.
+struct Table {
+    table_log: usize,
+    norm: Vec<usize>,
+}
+
+struct TansTables {
+    table: Table,
+    spread: Vec<u8>,
+    encode: (Vec<usize>, Vec<usize>, Vec<i32>),
+    decode: (Vec<usize>, Vec<usize>),
+}
+
+/// Opaque tANS table.
+#[derive(Default)]
+pub struct FsTansTable {
+    tables: Option<TansTables>,
+}
+
+struct RansTables {
+    table: Table,
+}
+
+/// Opaque rANS table.
+#[derive(Default)]
+pub struct FsRansTable {
+    tables: Option<RansTables>,
+}
+
+/// Run `f`, a panic becomes `FsError::Panic`.
+fn guard(f: impl FnOnce() -> Result<(), FsError>) -> FsError {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => FsError::Ok,
+        Ok(Err(err)) => err,
+        Err(_) => FsError::Panic,
+    }
+}
+
+/// A slice of a buffer given by C, a null pointer is accepted if the length
+/// is 0.
+unsafe fn slice<'a, T>(ptr: *const T, len: usize) -> Result<&'a [T], FsError> {
+    match (ptr.is_null(), len) {
+        (_, 0) => Ok(&[]),
+        (true, _) => Err(FsError::NullPointer),
+        (false, _) => Ok(std::slice::from_raw_parts(ptr, len)),
+    }
+}
+
+unsafe fn slice_mut<'a, T>(ptr: *mut T, len: usize) -> Result<&'a mut [T], FsError> {
+    match (ptr.is_null(), len) {
+        (_, 0) => Ok(&mut []),
+        (true, _) => Err(FsError::NullPointer),
+        (false, _) => Ok(std::slice::from_raw_parts_mut(ptr, len)),
+    }
+}
+
+unsafe fn reference<'a, T>(ptr: *mut T) -> Result<&'a mut T, FsError> {
+    ptr.as_mut().ok_or(FsError::NullPointer)
+}
+
+/// Copy `src` in the destination, or write the required size and fail.
+fn write_dst(src: &[u8], dst: &mut [u8], dst_len: &mut usize) -> Result<(), FsError> {
+    *dst_len = src.len();
+    if src.len() > dst.len() {
+        return Err(FsError::DstTooSmall);
+    }
+    dst[..src.len()].copy_from_slice(src);
+    Ok(())
+}
+
+impl Table {
+    fn build(counts: &[u32], table_log: u32) -> Result<Self, FsError> {
+        if !(FS_MIN_TABLE_LOG..=FS_MAX_TABLE_LOG).contains(&table_log) {
+            return Err(FsError::InvalidTableLog);
+        }
+        let max_symbol = counts
+            .iter()
+            .rposition(|count| *count > 0)
+            .ok_or(FsError::InvalidHistogram)?;
+        if max_symbol > 255 {
+            return Err(FsError::InvalidHistogram);
+        }
+        let counts = counts[..=max_symbol]
+            .iter()
+            .map(|count| *count as usize)
+            .collect::<Vec<_>>();
+        let table_log = table_log as usize;
+        // Une table normalisée est rendue telle quelle.
+        let norm = normalization_with_compensation_binary_heap(&counts, table_log, max_symbol)
+            .map_err(|_| FsError::InvalidHistogram)?;
+        Ok(Table { table_log, norm })
+    }
+
+    fn check_symbols(&self, src: &[u8]) -> Result<(), FsError> {
+        match src.iter().all(|symbol| {
+            self.norm
+                .get(*symbol as usize)
+                .is_some_and(|count| *count > 0)
+        }) {
+            true => Ok(()),
+            false => Err(FsError::InvalidSymbol),
+        }
+    }
+}
+
+/// Allocate an empty tANS table, to build with `fs_tans_build` and to free
+/// with `fs_tans_free`.
+#[no_mangle]
+pub extern "C" fn fs_tans_create() -> *mut FsTansTable {
+    Box::into_raw(Box::default())
+}
+
+/// Build the table from the counts of `nb_symbols` symbols, a previous table
+/// is replaced.
+///
+/// # Safety
+///
+/// `table` comes from `fs_tans_create`, `counts` points to `nb_symbols`
+/// integers.
+#[no_mangle]
+pub unsafe extern "C" fn fs_tans_build(
+    table: *mut FsTansTable,
+    counts: *const u32,
+    nb_symbols: usize,
+    table_log: u32,
+) -> FsError {
+    guard(|| {
+        let table = reference(table)?;
+        let built = Table::build(slice(counts, nb_symbols)?, table_log)?;
+        let spread = fse_spread_unsorted(&built.norm, built.table_log);
+        table.tables = Some(TansTables {
+            encode: build_encode_table(&built.norm, built.table_log, &spread),
+            decode: build_decode_table(built.table_log, &spread, &built.norm),
+            spread,
+            table: built,
+        });
+        Ok(())
+    })
+}
+
+/// Encode `src_len` symbols in `dst`, write the size of the stream in
+/// `dst_len` and the final state, needed by the decoder, in `state`.
+///
+/// # Safety
+///
+/// `table` comes from `fs_tans_create`, `src` points to `src_len` bytes,
+/// `dst` to `dst_capacity` bytes, `dst_len` and `state` to an integer.
+#[no_mangle]
+pub unsafe extern "C" fn fs_tans_encode(
+    table: *mut FsTansTable,
+    src: *const u8,
+    src_len: usize,
+    dst: *mut u8,
+    dst_capacity: usize,
+    dst_len: *mut usize,
+    state: *mut u32,
+) -> FsError {
+    guard(|| {
+        let tables = reference(table)?.tables.as_ref().ok_or(FsError::NotBuilt)?;
+        let (src, dst) = (slice(src, src_len)?, slice_mut(dst, dst_capacity)?);
+        let (dst_len, state) = (reference(dst_len)?, reference(state)?);
+        tables.table.check_symbols(src)?;
+
+        let (table, delta_nb_bits, starts) = &tables.encode;
+        let mut estream = BitEstream::new();
+        let mut current = 1 << tables.table.table_log;
+        for symbol in src.iter() {
+            current = encode_symbol(
+                delta_nb_bits,
+                starts,
+                table,
+                current,
+                *symbol as usize,
+                &mut estream,
+            );
+        }
+        let stream: Vec<u8> = estream.into();
+        write_dst(&stream, dst, dst_len)?;
+        *state = (current - (1 << tables.table.table_log)) as u32;
+        Ok(())
+    })
+}
+
+/// Decode `dst_len` symbols of a stream of `fs_tans_encode`, `state` is the
+/// final state of the encoder.
+///
+/// # Safety
+///
+/// `table` comes from `fs_tans_create`, `src` points to `src_len` bytes and
+/// `dst` to `dst_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn fs_tans_decode(
+    table: *mut FsTansTable,
+    src: *const u8,
+    src_len: usize,
+    state: u32,
+    dst: *mut u8,
+    dst_len: usize,
+) -> FsError {
+    guard(|| {
+        let tables = reference(table)?.tables.as_ref().ok_or(FsError::NotBuilt)?;
+        let (src, dst) = (slice(src, src_len)?, slice_mut(dst, dst_len)?);
+        let mut state = state as usize;
+        if state >= 1 << tables.table.table_log {
+            return Err(FsError::InvalidState);
+        }
+
+        let (nb_bits, new_states) = &tables.decode;
+        let mut dstream = BitDstream::try_from(src.to_vec()).map_err(|_| FsError::Truncated)?;
+        for symbol in dst.iter_mut().rev() {
+            let bits = dstream
+                .read(nb_bits[state] as u8)
+                .map_err(|_| FsError::Truncated)?;
+            *symbol = tables.spread[state];
+            state = new_states[state] + bits;
+        }
+        Ok(())
+    })
+}
+
+/// Free a table of `fs_tans_create`, a null table is ignored.
+///
+/// # Safety
+///
+/// `table` comes from `fs_tans_create` and isn't used after.
+#[no_mangle]
+pub unsafe extern "C" fn fs_tans_free(table: *mut FsTansTable) {
+    if !table.is_null() {
+        drop(Box::from_raw(table));
+    }
+}
+
+/// Allocate an empty rANS table, to build with `fs_rans_build` and to free
+/// with `fs_rans_free`.
+#[no_mangle]
+pub extern "C" fn fs_rans_create() -> *mut FsRansTable {
+    Box::into_raw(Box::default())
+}
+
+/// Build the table from the counts of `nb_symbols` symbols, a previous table
+/// is replaced.
+///
+/// # Safety
+///
+/// `table` comes from `fs_rans_create`, `counts` points to `nb_symbols`
+/// integers.
+#[no_mangle]
+pub unsafe extern "C" fn fs_rans_build(
+    table: *mut FsRansTable,
+    counts: *const u32,
+    nb_symbols: usize,
+    table_log: u32,
+) -> FsError {
+    guard(|| {
+        let table = reference(table)?;
+        let built = Table::build(slice(counts, nb_symbols)?, table_log)?;
+        table.tables = Some(RansTables { table: built });
+        Ok(())
+    })
+}
+
+/// Encode `src_len` symbols in `dst`, the sizes of the flushes followed by
+/// the stream. Write the size of the output in `dst_len` and the final
+/// state, needed by the decoder, in `state`.
+///
+/// # Safety
+///
+/// `table` comes from `fs_rans_create`, `src` points to `src_len` bytes,
+/// `dst` to `dst_capacity` bytes, `dst_len` and `state` to an integer.
+#[no_mangle]
+pub unsafe extern "C" fn fs_rans_encode(
+    table: *mut FsRansTable,
+    src: *const u8,
+    src_len: usize,
+    dst: *mut u8,
+    dst_capacity: usize,
+    dst_len: *mut usize,
+    state: *mut u32,
+) -> FsError {
+    guard(|| {
+        let tables = reference(table)?.tables.as_ref().ok_or(FsError::NotBuilt)?;
+        let (src, dst) = (slice(src, src_len)?, slice_mut(dst, dst_capacity)?);
+        let (dst_len, state) = (reference(dst_len)?, reference(state)?);
+        tables.table.check_symbols(src)?;
+
+        let (final_state, bits, stream) =
+            encode_rans(&tables.table.norm, tables.table.table_log, src);
+        let mut encoded = (bits.len() as u32).to_le_bytes().to_vec();
+        encoded.extend_from_slice(&bits);
+        encoded.extend_from_slice(&stream);
+        write_dst(&encoded, dst, dst_len)?;
+        *state = final_state as u32;
+        Ok(())
+    })
+}
+
+/// Decode `dst_len` symbols of an output of `fs_rans_encode`, `state` is the
+/// final state of the encoder.
+///
+/// # Safety
+///
+/// `table` comes from `fs_rans_create`, `src` points to `src_len` bytes and
+/// `dst` to `dst_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn fs_rans_decode(
+    table: *mut FsRansTable,
+    src: *const u8,
+    src_len: usize,
+    state: u32,
+    dst: *mut u8,
+    dst_len: usize,
+) -> FsError {
+    guard(|| {
+        let tables = reference(table)?.tables.as_ref().ok_or(FsError::NotBuilt)?;
+        let (src, dst) = (slice(src, src_len)?, slice_mut(dst, dst_len)?);
+        let nb_flushes = src
+            .get(..4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+            .ok_or(FsError::Truncated)?;
+        let bits = src.get(4..4 + nb_flushes).ok_or(FsError::Truncated)?;
+        let stream = &src[4 + nb_flushes..];
+
+        let decoded = try_decode_rans(
+            state as usize,
+            bits.to_vec(),
+            stream.to_vec(),
+            &tables.table.norm,
+            tables.table.table_log,
+            dst.len(),
+        )
+        .map_err(|err| match err {
+            RansError::InvalidTable => FsError::InvalidHistogram,
+            RansError::InvalidState => FsError::InvalidState,
+            RansError::Truncated => FsError::Truncated,
+            RansError::TooLarge => FsError::DstTooSmall,
+        })?;
+        dst.copy_from_slice(&decoded);
+        Ok(())
+    })
+}
+
+/// Free a table of `fs_rans_create`, a null table is ignored.
+///
+/// # Safety
+///
+/// `table` comes from `fs_rans_create` and isn't used after.
+#[no_mangle]
+pub unsafe extern "C" fn fs_rans_free(table: *mut FsRansTable) {
+    if !table.is_null() {
+        drop(Box::from_raw(table));
+    }
+}
+
+#[test]
+fn ffi_round_trip() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+    let src = &book1[..100_000];
+    let mut counts = [0u32; 256];
+    for symbol in src.iter() {
+        counts[*symbol as usize] += 1;
+    }
+
+    unsafe {
+        let tans = fs_tans_create();
+        let mut dst = vec![0; src.len()];
+        let (mut dst_len, mut state) = (0, 0);
+        let encode = |dst: &mut [u8], dst_len: &mut usize, state: &mut u32| {
+            fs_tans_encode(
+                tans,
+                src.as_ptr(),
+                src.len(),
+                dst.as_mut_ptr(),
+                dst.len(),
+                dst_len,
+                state,
+            )
+        };
+        assert_eq!(
+            encode(&mut dst, &mut dst_len, &mut state),
+            FsError::NotBuilt
+        );
+        assert_eq!(
+            fs_tans_build(tans, counts.as_ptr(), 256, 30),
+            FsError::InvalidTableLog
+        );
+        assert_eq!(fs_tans_build(tans, counts.as_ptr(), 256, 11), FsError::Ok);
+        assert_eq!(
+            encode(&mut dst[..10], &mut dst_len, &mut state),
+            FsError::DstTooSmall
+        );
+        assert!(dst_len > 10 && dst_len < src.len());
+        assert_eq!(encode(&mut dst, &mut dst_len, &mut state), FsError::Ok);
+        let mut decoded = vec![0; src.len()];
+        let code = fs_tans_decode(
+            tans,
+            dst.as_ptr(),
+            dst_len,
+            state,
+            decoded.as_mut_ptr(),
+            src.len(),
+        );
+        assert_eq!(code, FsError::Ok);
+        assert_eq!(decoded, src);
+        let code = fs_tans_decode(
+            tans,
+            dst.as_ptr(),
+            10,
+            state,
+            decoded.as_mut_ptr(),
+            src.len(),
+        );
+        assert_eq!(code, FsError::Truncated);
+        assert_eq!(
+            fs_tans_encode(
+                tans,
+                [0].as_ptr(),
+                1,
+                dst.as_mut_ptr(),
+                dst.len(),
+                &mut dst_len,
+                &mut state
+            ),
+            FsError::InvalidSymbol
+        );
+        fs_tans_free(tans);
+
+        let rans = fs_rans_create();
+        assert_eq!(fs_rans_build(rans, counts.as_ptr(), 256, 11), FsError::Ok);
+        let code = fs_rans_encode(
+            rans,
+            src.as_ptr(),
+            src.len(),
+            dst.as_mut_ptr(),
+            dst.len(),
+            &mut dst_len,
+            &mut state,
+        );
+        assert_eq!(code, FsError::Ok);
+        let code = fs_rans_decode(
+            rans,
+            dst.as_ptr(),
+            dst_len,
+            state,
+            decoded.as_mut_ptr(),
+            src.len(),
+        );
+        assert_eq!(code, FsError::Ok);
+        assert_eq!(decoded, src);
+        let code = fs_rans_decode(
+            rans,
+            dst.as_ptr(),
+            3,
+            state,
+            decoded.as_mut_ptr(),
+            src.len(),
+        );
+        assert_eq!(code, FsError::Truncated);
+        assert_eq!(
+            fs_rans_build(rans, std::ptr::null(), 256, 11),
+            FsError::NullPointer
+        );
+        assert_eq!(
+            fs_rans_build(rans, [5].as_ptr(), 1, 11),
+            FsError::InvalidHistogram
+        );
+        fs_rans_free(rans);
+    }
+}
@@ -0,0 +1,229 @@
+//! Reversible filters for arrays of numbers, applied before the entropy
+//! coders.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! The bytes of an array of little-endian integers or floats have a flat
+//! histogram: the low bytes of the values look random even if the values are
+//! close to each other. These filters don't compress anything but they move
+//! the information so the histogram of the bytes becomes sharper:
+//!
+//! - `Delta(width)` replaces each element of `width` bytes by its difference
+//!   with the previous element, a slowly varying signal becomes small values.
+//! - `ZigZag(width)` maps the signed elements to unsigned ones, 0, -1, 1, -2
+//!   become 0, 1, 2, 3, so the small negative values after a delta have their
+//!   high bytes at zero.
+//! - `Shuffle(width)` writes the first byte of every element, then the second
+//!   byte of every element... The high bytes, often zeros, end in the same
+//!   part of the output.
+//!
+//! The filters are chained in order with `encode_filters`, and undone in the
+//! reverse order with `decode_filters`. The bytes at the end of the source
+//! that don't fill an element are copied as they are.
+
+/// A reversible filter over elements of `width` bytes, between 1 and 8 for
+/// `Delta` and `ZigZag`, between 1 and 255 for `Shuffle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Difference with the previous element, wrapping.
+    Delta(usize),
+    /// Byte planes of the elements.
+    Shuffle(usize),
+    /// Signed to unsigned mapping of the elements.
+    ZigZag(usize),
+}
+
+#[inline]
+fn load(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |acc, byte| (acc << 8) | *byte as u64)
+}
+
+#[inline]
+fn store(mut value: u64, bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        *byte = value as u8;
+        value >>= 8;
+    }
+}
+
+#[inline]
+fn mask(width: usize) -> u64 {
+    u64::MAX >> (64 - 8 * width)
+}
+
+impl Filter {
+    /// Size of the elements.
+    pub fn width(&self) -> usize {
+        match self {
+            Filter::Delta(width) | Filter::Shuffle(width) | Filter::ZigZag(width) => *width,
+        }
+    }
+
+    /// Check the width of the elements.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Filter::Delta(width) | Filter::ZigZag(width) => (1..=8).contains(width),
+            Filter::Shuffle(width) => (1..=255).contains(width),
+        }
+    }
+
+    /// Identifier of the filter in a frame.
+    pub(crate) fn id(&self) -> u8 {
+        match self {
+            Filter::Delta(_) => 1,
+            Filter::Shuffle(_) => 2,
+            Filter::ZigZag(_) => 3,
+        }
+    }
+
+    /// Filter of an identifier and a width read in a frame.
+    pub(crate) fn from_id(id: u8, width: usize) -> Option<Filter> {
+        let filter = match id {
+            1 => Filter::Delta(width),
+            2 => Filter::Shuffle(width),
+            3 => Filter::ZigZag(width),
+            _ => return None,
+        };
+        filter.is_valid().then_some(filter)
+    }
+
+    /// Apply the filter.
+    ///
+    /// ```
+    /// use final_state_rs::filters::Filter;
+    ///
+    /// let src = [10u16, 12, 11, 11].iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>();
+    /// let encoded = Filter::Delta(2).encode(&src);
+    /// assert_eq!(encoded, [10, 0, 2, 0, 0xff, 0xff, 0, 0]);
+    /// assert_eq!(Filter::Delta(2).decode(&encoded), src);
+    /// ```
+    pub fn encode(&self, src: &[u8]) -> Vec<u8> {
+        assert!(self.is_valid(), "Invalid width of filter {self:?}");
+        let mut ret = src.to_vec();
+        let width = self.width();
+        let end = src.len() - src.len() % width;
+        match self {
+            Filter::Delta(_) => {
+                let mut previous = 0;
+                for element in ret[..end].chunks_exact_mut(width) {
+                    let value = load(element);
+                    store(value.wrapping_sub(previous) & mask(width), element);
+                    previous = value;
+                }
+            }
+            Filter::ZigZag(_) => {
+                let bits = 8 * width as u32;
+                for element in ret[..end].chunks_exact_mut(width) {
+                    let value = load(element);
+                    let sign = 0u64.wrapping_sub((value >> (bits - 1)) & 1);
+                    store(((value << 1) ^ sign) & mask(width), element);
+                }
+            }
+            Filter::Shuffle(_) => {
+                let count = end / width;
+                for (i, element) in src[..end].chunks_exact(width).enumerate() {
+                    for (plane, byte) in element.iter().enumerate() {
+                        ret[plane * count + i] = *byte;
+                    }
+                }
+            }
+        }
+        ret
+    }
+
+    /// Undo the filter.
+    pub fn decode(&self, src: &[u8]) -> Vec<u8> {
+        assert!(self.is_valid(), "Invalid width of filter {self:?}");
+        let mut ret = src.to_vec();
+        let width = self.width();
+        let end = src.len() - src.len() % width;
+        match self {
+            Filter::Delta(_) => {
+                let mut previous = 0u64;
+                for element in ret[..end].chunks_exact_mut(width) {
+                    previous = previous.wrapping_add(load(element)) & mask(width);
+                    store(previous, element);
+                }
+            }
+            Filter::ZigZag(_) => {
+                for element in ret[..end].chunks_exact_mut(width) {
+                    let value = load(element);
+                    let sign = 0u64.wrapping_sub(value & 1);
+                    store(((value >> 1) ^ sign) & mask(width), element);
+                }
+            }
+            Filter::Shuffle(_) => {
+                let count = end / width;
+                for (i, element) in ret[..end].chunks_exact_mut(width).enumerate() {
+                    for (plane, byte) in element.iter_mut().enumerate() {
+                        *byte = src[plane * count + i];
+                    }
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// Apply the filters in order.
+///
+/// ```
+/// use final_state_rs::filters::*;
+///
+/// let src = (0..1000u32).flat_map(|v| (v * 3).to_le_bytes()).collect::<Vec<u8>>();
+/// let filters = [Filter::Delta(4), Filter::ZigZag(4), Filter::Shuffle(4)];
+/// let encoded = encode_filters(&src, &filters);
+/// // Toutes les différences valent 3, zigzag donne 6.
+/// assert!(encoded[1..1000].iter().all(|b| *b == 6));
+/// assert!(encoded[1000..].iter().all(|b| *b == 0));
+/// assert_eq!(decode_filters(&encoded, &filters), src);
+/// ```
+pub fn encode_filters(src: &[u8], filters: &[Filter]) -> Vec<u8> {
+    filters
+        .iter()
+        .fold(src.to_vec(), |data, filter| filter.encode(&data))
+}
+
+/// Undo the filters, in the reverse order.
+pub fn decode_filters(src: &[u8], filters: &[Filter]) -> Vec<u8> {
+    filters
+        .iter()
+        .rev()
+        .fold(src.to_vec(), |data, filter| filter.decode(&data))
+}
+
+#[test]
+fn filters_round_trip() {
+    let src: Vec<u8> = (0..10_001).map(|_| rand::random::<u8>()).collect();
+    for width in 1..=8 {
+        for filter in [
+            Filter::Delta(width),
+            Filter::Shuffle(width),
+            Filter::ZigZag(width),
+        ] {
+            assert_eq!(filter.decode(&filter.encode(&src)), src, "{filter:?}");
+            assert_eq!(filter.encode(&src[..width - 1]), src[..width - 1]);
+        }
+    }
+    assert_eq!(Filter::Shuffle(3).encode(b"abcabcab"), b"aabbccab");
+
+    let zigzag = [0i16, -1, 1, -2, 2, i16::MIN, i16::MAX]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect::<Vec<u8>>();
+    let expected = [0u16, 1, 2, 3, 4, u16::MAX, u16::MAX - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect::<Vec<u8>>();
+    assert_eq!(Filter::ZigZag(2).encode(&zigzag), expected);
+    assert_eq!(Filter::from_id(1, 9), None);
+    assert_eq!(Filter::from_id(4, 2), None);
+}
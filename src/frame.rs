@@ -0,0 +1,836 @@
+//! Frame format around the tANS and rANS coders.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! The coders of `t_ans` and `r_ans` need a side channel: the normalized
+//! histogram, the final state and the length of the source. A frame keeps all
+//! of it with the compressed streams, and records the `filters` applied
+//! before the coders so the decoder can undo them.
+//!
+//! Format, integers in little-endian:
+//! - the magic `FSRS`,
+//! - u8: number of filters, then for each filter its id and its width (u8),
+//! - an optional `Checksum` block,
+//! - an optional `Index` block,
+//! - the blocks, until the block with the `LAST_BLOCK` flag,
+//! - the checksum of the frame, if any.
+//!
+//! A block starts with its type (u8, with the `LAST_BLOCK` flag) and the size
+//! of the decoded block (u32), then:
+//! - `Raw`: the bytes of the block,
+//! - `Rle`: the byte repeated in the block,
+//! - `Tans`: the table (table_log as u8, max symbol as u8, then the
+//!   normalized histogram until the max symbol, u16 per symbol), the final
+//!   state (u16), the size of the stream (u32) and the stream,
+//! - `Rans`: the table, the final state (u32), the number of flushes (u32)
+//!   and the number of bits of each flush (u8), the size of the stream (u32)
+//!   and the stream,
+//! - `Index`: its size is the number of blocks that follow, then the size of
+//!   each encoded block (u32). It lets `par_decode_frame` find the blocks
+//!   without decoding them, and `decode_frame` skips it.
+//! - `Checksum`: the id of the checksum of the blocks (u8) and the id of the
+//!   checksum of the frame (u8), without the size of a block.
+//!
+//! With a checksum of the blocks, each block ends with the checksum of its
+//! decoded bytes, before the filters are undone. The checksum of the frame
+//! is the one of the source. A corrupted stream is then detected by the
+//! decoder, instead of decoding to other bytes.
+//!
+//! The encoder keeps a block raw when the entropy coder doesn't make it
+//! smaller. With `FrameConfig::split`, the blocks end where the statistics of
+//! the source change, see `block_split`.
+
+use std::ops::Range;
+
+use crate::bitstream::BitDstream;
+use crate::block_split::{split_blocks, CHUNK_SIZE};
+use crate::checksum::{crc32c, xxh64};
+use crate::count::simple_count_u8_inplace;
+use crate::filters::{decode_filters, encode_filters, Filter};
+use crate::normalization::normalization_with_compensation_binary_heap;
+use crate::r_ans::{encode_rans, try_decode_rans, RansError};
+use crate::spreads::fse_spread_unsorted;
+use crate::t_ans::{encode_tans, try_decode_tans, TansError};
+
+const MAGIC: [u8; 4] = *b"FSRS";
+/// Flag of the type of the last block of a frame.
+const LAST_BLOCK: u8 = 0x80;
+/// Bounds of the table log of a block, the state of the tANS coder is
+/// written on 16 bits.
+const MIN_TABLE_LOG: usize = 5;
+const MAX_TABLE_LOG: usize = 15;
+
+/// Entropy coder of the blocks of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coder {
+    Tans,
+    Rans,
+}
+
+/// Type of a block, written in its first byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockType {
+    Raw = 0,
+    Rle = 1,
+    Tans = 2,
+    Rans = 3,
+    Index = 4,
+    Checksum = 5,
+}
+
+/// Checksum of the blocks or of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    None = 0,
+    /// CRC32C, 4 bytes.
+    Crc32c = 1,
+    /// XXH64 with a seed of 0, 8 bytes.
+    Xxh64 = 2,
+}
+
+impl Checksum {
+    fn from_id(id: u8) -> Option<Checksum> {
+        [Checksum::None, Checksum::Crc32c, Checksum::Xxh64]
+            .into_iter()
+            .find(|checksum| *checksum as u8 == id)
+    }
+
+    /// Append the checksum of the data.
+    fn write(self, data: &[u8], ret: &mut Vec<u8>) {
+        match self {
+            Checksum::None => {}
+            Checksum::Crc32c => ret.extend_from_slice(&crc32c(data).to_le_bytes()),
+            Checksum::Xxh64 => ret.extend_from_slice(&xxh64(data, 0).to_le_bytes()),
+        }
+    }
+
+    /// Read the checksum of the data.
+    fn check(self, data: &[u8], src: &[u8], pos: &mut usize) -> Result<(), FrameError> {
+        let mut expected = vec![];
+        self.write(data, &mut expected);
+        match take(src, pos, expected.len())? == expected {
+            true => Ok(()),
+            false => Err(FrameError::ChecksumMismatch),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameConfig {
+    /// Entropy coder of the blocks.
+    pub coder: Coder,
+    /// Table log of the normalized histograms, raised if a block has too
+    /// many symbols.
+    pub table_log: usize,
+    /// Size of the blocks, each block has its own histogram.
+    pub block_size: usize,
+    /// Split the blocks where the statistics of the source change,
+    /// `block_size` is then the maximum size of a block.
+    pub split: bool,
+    /// Filters applied to the source before the blocks are coded.
+    pub filters: Vec<Filter>,
+    /// Checksum at the end of each block.
+    pub block_checksum: Checksum,
+    /// Checksum at the end of the frame.
+    pub frame_checksum: Checksum,
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        FrameConfig {
+            coder: Coder::Tans,
+            table_log: 11,
+            block_size: 128 << 10,
+            split: true,
+            filters: vec![],
+            block_checksum: Checksum::None,
+            frame_checksum: Checksum::None,
+        }
+    }
+}
+
+impl FrameConfig {
+    /// Table log of a block with `distinct` symbols.
+    pub fn block_table_log(&self, distinct: usize) -> usize {
+        // Au moins deux états par symbole pour que la normalisation
+        // converge.
+        self.table_log
+            .max((usize::BITS - distinct.leading_zeros()) as usize + 1)
+            .clamp(MIN_TABLE_LOG, MAX_TABLE_LOG)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// The frame ends before its last block.
+    Truncated,
+    /// The frame doesn't start with the magic.
+    InvalidMagic,
+    /// Unknown filter id, or invalid width (id, width).
+    InvalidFilter(u8, u8),
+    /// Unknown block type.
+    InvalidBlockType(u8),
+    /// The normalized histogram or the state of a block is invalid.
+    InvalidHistogram,
+    /// The index doesn't match the blocks of the frame.
+    InvalidIndex,
+    /// The decoded frame would be greater than the maximum size.
+    TooLarge,
+    /// Unknown checksum id.
+    InvalidChecksum(u8),
+    /// The checksum of a block or of the frame doesn't match the decoded
+    /// data.
+    ChecksumMismatch,
+}
+
+/// Write the table of a block.
+fn write_table(norm: &[usize], table_log: usize, ret: &mut Vec<u8>) {
+    ret.push(table_log as u8);
+    ret.push((norm.len() - 1) as u8);
+    for count in norm.iter() {
+        ret.extend_from_slice(&(*count as u16).to_le_bytes());
+    }
+}
+
+/// Encode one block with the coder, fall back on a raw block if it isn't
+/// smaller.
+fn encode_block(src: &[u8], config: &FrameConfig, last: bool, ret: &mut Vec<u8>) {
+    let mut hist = [0; 256];
+    let max_symbol = simple_count_u8_inplace(src, &mut hist);
+    let distinct = hist.iter().filter(|count| **count > 0).count();
+    let flag = if last { LAST_BLOCK } else { 0 };
+    let start = ret.len();
+    ret.push(flag);
+    ret.extend_from_slice(&(src.len() as u32).to_le_bytes());
+
+    if distinct == 1 {
+        ret[start] |= BlockType::Rle as u8;
+        ret.push(src[0]);
+        config.block_checksum.write(src, ret);
+        return;
+    }
+    if distinct > 1 {
+        let table_log = config.block_table_log(distinct);
+        let norm = normalization_with_compensation_binary_heap(&hist, table_log, max_symbol)
+            .expect("The histogram of a block can always be normalized");
+        write_table(&norm, table_log, ret);
+        match config.coder {
+            Coder::Tans => {
+                ret[start] |= BlockType::Tans as u8;
+                let spread = fse_spread_unsorted(&norm, table_log);
+                let mut state = 1 << table_log;
+                let (stream, state) = encode_tans(src, &norm, &spread, table_log, &mut state);
+                ret.extend_from_slice(&(state as u16).to_le_bytes());
+                ret.extend_from_slice(&(stream.len() as u32).to_le_bytes());
+                ret.extend_from_slice(&stream);
+            }
+            Coder::Rans => {
+                ret[start] |= BlockType::Rans as u8;
+                let (state, nb_bits, stream) = encode_rans(&norm, table_log, src);
+                ret.extend_from_slice(&(state as u32).to_le_bytes());
+                ret.extend_from_slice(&(nb_bits.len() as u32).to_le_bytes());
+                ret.extend_from_slice(&nb_bits);
+                ret.extend_from_slice(&(stream.len() as u32).to_le_bytes());
+                ret.extend_from_slice(&stream);
+            }
+        }
+        if ret.len() - start < 5 + src.len() {
+            config.block_checksum.write(src, ret);
+            return;
+        }
+    }
+    // Bloc vide ou incompressible
+    ret.truncate(start + 5);
+    ret[start] = flag | BlockType::Raw as u8;
+    ret.extend_from_slice(src);
+    config.block_checksum.write(src, ret);
+}
+
+/// Compress the source in a frame.
+///
+/// ```
+/// use final_state_rs::frame::*;
+/// use final_state_rs::filters::Filter;
+///
+/// let src = (0..5000u16).flat_map(|v| (v / 3).to_le_bytes()).collect::<Vec<u8>>();
+/// let config = FrameConfig {
+///     filters: vec![Filter::Delta(2), Filter::Shuffle(2)],
+///     ..Default::default()
+/// };
+/// let encoded = encode_frame(&src, &config);
+/// assert!(encoded.len() < encode_frame(&src, &FrameConfig::default()).len());
+/// assert_eq!(decode_frame(&encoded).unwrap(), src);
+/// ```
+pub fn encode_frame(src: &[u8], config: &FrameConfig) -> Vec<u8> {
+    let mut ret = vec![];
+    write_header(config, &mut ret);
+
+    let filtered = encode_filters(src, &config.filters);
+    let blocks = block_ranges(&filtered, config);
+    for (i, block) in blocks.iter().enumerate() {
+        encode_block(
+            &filtered[block.clone()],
+            config,
+            i + 1 == blocks.len(),
+            &mut ret,
+        );
+    }
+    config.frame_checksum.write(src, &mut ret);
+    ret
+}
+
+/// Write the magic, the filters and the checksums of the frame.
+fn write_header(config: &FrameConfig, ret: &mut Vec<u8>) {
+    assert!(config.block_size > 0, "The size of the blocks can't be 0");
+    assert!(config.filters.len() <= u8::MAX as usize, "Too many filters");
+    ret.extend_from_slice(&MAGIC);
+    ret.push(config.filters.len() as u8);
+    for filter in config.filters.iter() {
+        assert!(filter.is_valid(), "Invalid width of filter {filter:?}");
+        ret.push(filter.id());
+        ret.push(filter.width() as u8);
+    }
+    if (config.block_checksum, config.frame_checksum) != (Checksum::None, Checksum::None) {
+        ret.push(BlockType::Checksum as u8);
+        ret.push(config.block_checksum as u8);
+        ret.push(config.frame_checksum as u8);
+    }
+}
+
+/// Blocks of the filtered source, an empty source still has one empty block
+/// to carry the `LAST_BLOCK` flag.
+fn block_ranges(filtered: &[u8], config: &FrameConfig) -> Vec<Range<usize>> {
+    if filtered.is_empty() {
+        return std::iter::once(0..0).collect();
+    }
+    if config.split {
+        let chunk_size = CHUNK_SIZE.min(config.block_size);
+        split_blocks(filtered, chunk_size, config.block_size)
+    } else {
+        (0..filtered.len())
+            .step_by(config.block_size)
+            .map(|start| start..filtered.len().min(start + config.block_size))
+            .collect()
+    }
+}
+
+/// Compress the source in a frame with an index, the blocks are encoded on
+/// the pool of `rayon`. The blocks are the same as the ones of
+/// `encode_frame`.
+///
+/// ```
+/// use final_state_rs::frame::*;
+///
+/// let src = "abracadabra".repeat(100_000).into_bytes();
+/// let config = FrameConfig { block_size: 64 << 10, ..Default::default() };
+/// let encoded = par_encode_frame(&src, &config);
+/// assert_eq!(par_decode_frame(&encoded).unwrap(), src);
+/// assert_eq!(decode_frame(&encoded).unwrap(), src);
+/// ```
+#[cfg(feature = "rayon")]
+pub fn par_encode_frame(src: &[u8], config: &FrameConfig) -> Vec<u8> {
+    use rayon::prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+    let mut ret = vec![];
+    write_header(config, &mut ret);
+    let filtered = encode_filters(src, &config.filters);
+    let blocks = block_ranges(&filtered, config);
+    let encoded = blocks
+        .par_iter()
+        .enumerate()
+        .map(|(i, block)| {
+            let mut ret = vec![];
+            encode_block(
+                &filtered[block.clone()],
+                config,
+                i + 1 == blocks.len(),
+                &mut ret,
+            );
+            ret
+        })
+        .collect::<Vec<Vec<u8>>>();
+
+    ret.push(BlockType::Index as u8);
+    ret.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+    for block in encoded.iter() {
+        ret.extend_from_slice(&(block.len() as u32).to_le_bytes());
+    }
+    for block in encoded.iter() {
+        ret.extend_from_slice(block);
+    }
+    config.frame_checksum.write(src, &mut ret);
+    ret
+}
+
+/// Next `size` bytes of the source.
+fn take<'a>(src: &'a [u8], pos: &mut usize, size: usize) -> Result<&'a [u8], FrameError> {
+    let bytes = src.get(*pos..*pos + size).ok_or(FrameError::Truncated)?;
+    *pos += size;
+    Ok(bytes)
+}
+
+fn take_u16(src: &[u8], pos: &mut usize) -> Result<usize, FrameError> {
+    Ok(u16::from_le_bytes(take(src, pos, 2)?.try_into().unwrap()) as usize)
+}
+
+fn take_u32(src: &[u8], pos: &mut usize) -> Result<usize, FrameError> {
+    Ok(u32::from_le_bytes(take(src, pos, 4)?.try_into().unwrap()) as usize)
+}
+
+/// Read the table of a block, return the table log and the normalized
+/// histogram.
+fn take_table(src: &[u8], pos: &mut usize) -> Result<(usize, Vec<usize>), FrameError> {
+    let header = take(src, pos, 2)?;
+    let (table_log, max_symbol) = (header[0] as usize, header[1] as usize);
+    let norm = (0..=max_symbol)
+        .map(|_| take_u16(src, pos))
+        .collect::<Result<Vec<usize>, FrameError>>()?;
+    if !(MIN_TABLE_LOG..=MAX_TABLE_LOG).contains(&table_log)
+        || norm.iter().sum::<usize>() != 1 << table_log
+    {
+        return Err(FrameError::InvalidHistogram);
+    }
+    Ok((table_log, norm))
+}
+
+/// Take a stream of `bitstream`, it ends with a mark.
+fn take_stream(src: &[u8], pos: &mut usize) -> Result<Vec<u8>, FrameError> {
+    let size = take_u32(src, pos)?;
+    let stream = take(src, pos, size)?.to_vec();
+    if BitDstream::try_from(stream.clone()).is_err() {
+        return Err(FrameError::Truncated);
+    }
+    Ok(stream)
+}
+
+/// Decode one block and check its checksum, return true if it is the last
+/// one. Fails if the output would be greater than `max_size`.
+fn decode_block(
+    src: &[u8],
+    pos: &mut usize,
+    ret: &mut Vec<u8>,
+    max_size: usize,
+    checksum: Checksum,
+) -> Result<bool, FrameError> {
+    let block_start = ret.len();
+    let block_type = take(src, pos, 1)?[0];
+    let size = take_u32(src, pos)?;
+    if size > max_size.saturating_sub(ret.len()) {
+        return Err(FrameError::TooLarge);
+    }
+    match block_type & !LAST_BLOCK {
+        t if t == BlockType::Raw as u8 => ret.extend_from_slice(take(src, pos, size)?),
+        t if t == BlockType::Rle as u8 => {
+            let byte = take(src, pos, 1)?[0];
+            ret.extend(std::iter::repeat_n(byte, size));
+        }
+        t if t == BlockType::Tans as u8 => {
+            let (table_log, norm) = take_table(src, pos)?;
+            let state = take_u16(src, pos)?;
+            if state >= 1 << table_log {
+                return Err(FrameError::InvalidHistogram);
+            }
+            let stream = take_stream(src, pos)?;
+            let spread = fse_spread_unsorted(&norm, table_log);
+            let start = ret.len();
+            ret.resize(start + size, 0);
+            try_decode_tans(stream, &norm, &spread, table_log, state, &mut ret[start..]).map_err(
+                |err| match err {
+                    TansError::Truncated => FrameError::Truncated,
+                    _ => FrameError::InvalidHistogram,
+                },
+            )?;
+        }
+        t if t == BlockType::Rans as u8 => {
+            let (table_log, norm) = take_table(src, pos)?;
+            let state = take_u32(src, pos)?;
+            let nb_flushes = take_u32(src, pos)?;
+            let nb_bits = take(src, pos, nb_flushes)?.to_vec();
+            let stream = take_stream(src, pos)?;
+            let decoded =
+                try_decode_rans(state, nb_bits, stream, &norm, table_log, size).map_err(|err| {
+                    match err {
+                        RansError::Truncated => FrameError::Truncated,
+                        _ => FrameError::InvalidHistogram,
+                    }
+                })?;
+            ret.extend(decoded);
+        }
+        t => return Err(FrameError::InvalidBlockType(t)),
+    }
+    checksum.check(&ret[block_start..], src, pos)?;
+    Ok(block_type & LAST_BLOCK != 0)
+}
+
+/// Read the magic and the filters of a frame.
+fn take_header(src: &[u8], pos: &mut usize) -> Result<Vec<Filter>, FrameError> {
+    if take(src, pos, 4)? != MAGIC {
+        return Err(FrameError::InvalidMagic);
+    }
+    let nb_filters = take(src, pos, 1)?[0] as usize;
+    take(src, pos, 2 * nb_filters)?
+        .chunks(2)
+        .map(|f| Filter::from_id(f[0], f[1] as usize).ok_or(FrameError::InvalidFilter(f[0], f[1])))
+        .collect()
+}
+
+/// Read the checksums of the blocks and of the frame if there are some.
+fn take_checksums(src: &[u8], pos: &mut usize) -> Result<(Checksum, Checksum), FrameError> {
+    if src.get(*pos) != Some(&(BlockType::Checksum as u8)) {
+        return Ok((Checksum::None, Checksum::None));
+    }
+    *pos += 1;
+    let ids = take(src, pos, 2)?;
+    let checksum = |id| Checksum::from_id(id).ok_or(FrameError::InvalidChecksum(id));
+    Ok((checksum(ids[0])?, checksum(ids[1])?))
+}
+
+/// Read the index of the frame if there is one, return the size of each
+/// encoded block.
+fn take_index(src: &[u8], pos: &mut usize) -> Result<Option<Vec<usize>>, FrameError> {
+    if src.get(*pos) != Some(&(BlockType::Index as u8)) {
+        return Ok(None);
+    }
+    *pos += 1;
+    let nb_blocks = take_u32(src, pos)?;
+    let sizes = take(src, pos, 4 * nb_blocks)?
+        .chunks(4)
+        .map(|size| u32::from_le_bytes(size.try_into().unwrap()) as usize)
+        .collect::<Vec<usize>>();
+    if sizes.is_empty() {
+        return Err(FrameError::InvalidIndex);
+    }
+    Ok(Some(sizes))
+}
+
+/// Decode a frame of `encode_frame`.
+pub fn decode_frame(src: &[u8]) -> Result<Vec<u8>, FrameError> {
+    decode_frame_with_max_size(src, usize::MAX)
+}
+
+/// Decode a frame of `encode_frame`, fails with `FrameError::TooLarge` if the
+/// decoded frame is greater than `max_size`. The size of a block is written
+/// in its header, a frame from an untrusted source should be decoded with a
+/// bound so a forged size doesn't allocate gigabytes.
+///
+/// ```
+/// use final_state_rs::frame::*;
+///
+/// let encoded = encode_frame(&[7; 1000], &FrameConfig::default());
+/// assert_eq!(decode_frame_with_max_size(&encoded, 1000).unwrap(), [7; 1000]);
+/// assert_eq!(
+///     decode_frame_with_max_size(&encoded, 999),
+///     Err(FrameError::TooLarge)
+/// );
+/// ```
+pub fn decode_frame_with_max_size(src: &[u8], max_size: usize) -> Result<Vec<u8>, FrameError> {
+    let mut pos = 0;
+    let filters = take_header(src, &mut pos)?;
+    let (block_checksum, frame_checksum) = take_checksums(src, &mut pos)?;
+    take_index(src, &mut pos)?;
+    let mut ret = vec![];
+    while !decode_block(src, &mut pos, &mut ret, max_size, block_checksum)? {}
+    let ret = decode_filters(&ret, &filters);
+    frame_checksum.check(&ret, src, &mut pos)?;
+    Ok(ret)
+}
+
+/// Decode a frame, the blocks are decoded on the pool of `rayon` if the
+/// frame has an index, like the frames of `par_encode_frame`. Otherwise the
+/// blocks are decoded one after the other.
+#[cfg(feature = "rayon")]
+pub fn par_decode_frame(src: &[u8]) -> Result<Vec<u8>, FrameError> {
+    par_decode_frame_with_max_size(src, usize::MAX)
+}
+
+/// Same as `par_decode_frame`, fails with `FrameError::TooLarge` if the
+/// decoded frame is greater than `max_size`. The sizes written in the
+/// headers of the blocks are summed before any block is decoded, each block
+/// is then decoded with its own size as a bound.
+///
+/// ```
+/// use final_state_rs::frame::*;
+///
+/// let encoded = par_encode_frame(&[7; 1000], &FrameConfig::default());
+/// assert_eq!(par_decode_frame_with_max_size(&encoded, 1000).unwrap(), [7; 1000]);
+/// assert_eq!(
+///     par_decode_frame_with_max_size(&encoded, 999),
+///     Err(FrameError::TooLarge)
+/// );
+/// ```
+#[cfg(feature = "rayon")]
+pub fn par_decode_frame_with_max_size(src: &[u8], max_size: usize) -> Result<Vec<u8>, FrameError> {
+    use rayon::prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+    let mut pos = 0;
+    let filters = take_header(src, &mut pos)?;
+    let (block_checksum, frame_checksum) = take_checksums(src, &mut pos)?;
+    let sizes = match take_index(src, &mut pos)? {
+        Some(sizes) => sizes,
+        None => return decode_frame_with_max_size(src, max_size),
+    };
+    let mut blocks = vec![];
+    let mut total = 0;
+    for size in sizes.iter() {
+        let block = take(src, &mut pos, *size).map_err(|_| FrameError::InvalidIndex)?;
+        // La taille décodée est après le type du bloc.
+        let decoded_size = take_u32(block, &mut 1)?;
+        if decoded_size > max_size - total {
+            return Err(FrameError::TooLarge);
+        }
+        total += decoded_size;
+        blocks.push((block, decoded_size));
+    }
+    let decoded = blocks
+        .par_iter()
+        .enumerate()
+        .map(|(i, (block, decoded_size))| {
+            let mut pos = 0;
+            let mut ret = vec![];
+            let last = decode_block(block, &mut pos, &mut ret, *decoded_size, block_checksum)?;
+            if last != (i + 1 == blocks.len()) || pos != block.len() {
+                return Err(FrameError::InvalidIndex);
+            }
+            Ok(ret)
+        })
+        .collect::<Result<Vec<Vec<u8>>, FrameError>>()?;
+    let ret = decode_filters(&decoded.concat(), &filters);
+    frame_checksum.check(&ret, src, &mut pos)?;
+    Ok(ret)
+}
+
+#[test]
+fn frame_round_trip() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+
+    for coder in [Coder::Tans, Coder::Rans] {
+        let config = FrameConfig {
+            coder,
+            block_size: 50_000,
+            ..Default::default()
+        };
+        for src in [
+            vec![],
+            vec![42],
+            vec![7; 100_000],
+            book1[..120_000].to_vec(),
+            (0..20_000).map(|_| rand::random::<u8>()).collect(),
+        ] {
+            let encoded = encode_frame(&src, &config);
+            assert_eq!(decode_frame(&encoded).unwrap(), src);
+            assert!(encoded.len() <= src.len() + 5 * src.len().div_ceil(50_000) + 10);
+        }
+        let encoded = encode_frame(&book1[..120_000], &config);
+        // rANS garde le nombre de bits de chaque flush, un octet pour 16 bits.
+        let ratio = match coder {
+            Coder::Tans => 0.6,
+            Coder::Rans => 0.85,
+        };
+        assert!((encoded.len() as f64) < 120_000.0 * ratio);
+    }
+}
+
+#[test]
+fn frame_split_blocks() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+
+    // Du texte, une table de petits nombres, puis du texte.
+    let mut src = book1[..60_000].to_vec();
+    src.extend((0..15_000u32).flat_map(|v| (v % 17).to_le_bytes()));
+    src.extend_from_slice(&book1[..60_000]);
+    for coder in [Coder::Tans, Coder::Rans] {
+        let config = FrameConfig {
+            coder,
+            block_size: 1 << 20,
+            ..Default::default()
+        };
+        let split = encode_frame(&src, &config);
+        assert_eq!(decode_frame(&split).unwrap(), src);
+        let whole = encode_frame(
+            &src,
+            &FrameConfig {
+                split: false,
+                ..config
+            },
+        );
+        assert_eq!(decode_frame(&whole).unwrap(), src);
+        assert!(
+            split.len() < whole.len(),
+            "{} >= {}",
+            split.len(),
+            whole.len()
+        );
+    }
+}
+
+#[test]
+fn frame_filters_on_telemetry() {
+    // Un capteur sur 16 bits qui varie lentement, avec du bruit.
+    let mut value = 20_000i32;
+    let src = (0..50_000)
+        .flat_map(|i| {
+            value += (i % 7) - 3 + rand::random::<i32>() % 3;
+            (value as u16).to_le_bytes()
+        })
+        .collect::<Vec<u8>>();
+    let plain = encode_frame(&src, &FrameConfig::default());
+    let config = FrameConfig {
+        filters: vec![Filter::Delta(2), Filter::ZigZag(2), Filter::Shuffle(2)],
+        ..Default::default()
+    };
+    let filtered = encode_frame(&src, &config);
+    assert_eq!(decode_frame(&filtered).unwrap(), src);
+    assert!(filtered.len() < plain.len() / 2);
+
+    assert_eq!(decode_frame(b"FSR"), Err(FrameError::Truncated));
+    assert_eq!(decode_frame(b"LZ4\0\0"), Err(FrameError::InvalidMagic));
+    assert_eq!(
+        decode_frame(b"FSRS\x01\x01\x09"),
+        Err(FrameError::InvalidFilter(1, 9))
+    );
+    assert_eq!(
+        decode_frame(b"FSRS\x00\x87\0\0\0\0"),
+        Err(FrameError::InvalidBlockType(7))
+    );
+    assert_eq!(
+        decode_frame(&filtered[..filtered.len() - 1]),
+        Err(FrameError::Truncated)
+    );
+}
+
+#[test]
+fn frame_checksums() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+    let src = &book1[..100_000];
+
+    let config = FrameConfig {
+        block_size: 30_000,
+        ..Default::default()
+    };
+    // Sans checksum, un bit modifié dans le flux tANS du premier bloc donne
+    // souvent d'autres octets sans erreur.
+    let plain = encode_frame(src, &config);
+    let corrupted = (5_000..6_000)
+        .find(|pos| {
+            let mut encoded = plain.clone();
+            encoded[*pos] ^= 0x10;
+            decode_frame(&encoded).is_ok_and(|decoded| decoded != src)
+        })
+        .expect("A corrupted stream decodes silently");
+
+    let checksums = [Checksum::None, Checksum::Crc32c, Checksum::Xxh64];
+    for (block_checksum, frame_checksum) in checksums
+        .iter()
+        .flat_map(|block| checksums.iter().map(move |frame| (*block, *frame)))
+        .skip(1)
+    {
+        let config = FrameConfig {
+            block_checksum,
+            frame_checksum,
+            ..config.clone()
+        };
+        for src in [&[][..], &[9; 1000], src] {
+            let encoded = encode_frame(src, &config);
+            assert_eq!(decode_frame(&encoded).unwrap(), src);
+        }
+        // Le bloc commence après l'en-tête de 8 octets.
+        let mut encoded = encode_frame(src, &config);
+        assert_eq!(encoded[8..6_003], plain[5..6_000]);
+        encoded[corrupted + 3] ^= 0x10;
+        assert_eq!(decode_frame(&encoded), Err(FrameError::ChecksumMismatch));
+    }
+
+    let config = FrameConfig {
+        frame_checksum: Checksum::Xxh64,
+        ..Default::default()
+    };
+    let encoded = encode_frame(src, &config);
+    assert_eq!(
+        decode_frame(&encoded[..encoded.len() - 1]),
+        Err(FrameError::Truncated)
+    );
+    assert_eq!(
+        decode_frame(b"FSRS\x00\x05\x00\x07"),
+        Err(FrameError::InvalidChecksum(7))
+    );
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn frame_parallel_round_trip() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+
+    for coder in [Coder::Tans, Coder::Rans] {
+        let config = FrameConfig {
+            coder,
+            block_size: 64 << 10,
+            filters: vec![Filter::Delta(1)],
+            ..Default::default()
+        };
+        for src in [vec![], vec![3; 1000], book1.clone()] {
+            let sequential = encode_frame(&src, &config);
+            let parallel = par_encode_frame(&src, &config);
+            assert_eq!(par_decode_frame(&parallel).unwrap(), src);
+            assert_eq!(decode_frame(&parallel).unwrap(), src);
+            assert_eq!(par_decode_frame(&sequential).unwrap(), src);
+            // Les mêmes blocs, précédés de l'index.
+            let nb_blocks = u32::from_le_bytes(parallel[8..12].try_into().unwrap()) as usize;
+            assert_eq!(parallel[..7], sequential[..7]);
+            assert_eq!(parallel[12 + 4 * nb_blocks..], sequential[7..]);
+        }
+    }
+
+    let config = FrameConfig {
+        block_checksum: Checksum::Crc32c,
+        frame_checksum: Checksum::Xxh64,
+        ..Default::default()
+    };
+    let encoded = par_encode_frame(&book1, &config);
+    assert_eq!(par_decode_frame(&encoded).unwrap(), book1);
+    assert_eq!(decode_frame(&encoded).unwrap(), book1);
+
+    let src = book1[..200_000].to_vec();
+    let mut encoded = par_encode_frame(&src, &FrameConfig::default());
+    assert_eq!(
+        par_decode_frame(&encoded[..encoded.len() - 1]),
+        Err(FrameError::InvalidIndex)
+    );
+    // Le premier bloc devient plus grand que ses données.
+    encoded[10] = encoded[10].wrapping_add(1);
+    assert_eq!(par_decode_frame(&encoded), Err(FrameError::InvalidIndex));
+
+    // La taille maximale est vérifiée sur les tailles des blocs, avant de
+    // les décoder.
+    let encoded = par_encode_frame(&book1, &FrameConfig::default());
+    assert_eq!(
+        par_decode_frame_with_max_size(&encoded, book1.len()).unwrap(),
+        book1
+    );
+    assert_eq!(
+        par_decode_frame_with_max_size(&encoded, book1.len() - 1),
+        Err(FrameError::TooLarge)
+    );
+    let sequential = encode_frame(&book1, &FrameConfig::default());
+    assert_eq!(
+        par_decode_frame_with_max_size(&sequential, book1.len() - 1),
+        Err(FrameError::TooLarge)
+    );
+    // Un bloc qui annonce 4 GiB.
+    let mut forged = par_encode_frame(&[7; 1000], &FrameConfig::default());
+    let nb_blocks = u32::from_le_bytes(forged[6..10].try_into().unwrap()) as usize;
+    let header = 10 + 4 * nb_blocks;
+    forged[header + 1..header + 5].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(
+        par_decode_frame_with_max_size(&forged, 1 << 20),
+        Err(FrameError::TooLarge)
+    );
+}
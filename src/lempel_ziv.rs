@@ -21,6 +21,202 @@
 
 use std::collections::HashMap;
 
+use crate::binary_tree::BinaryTreeMatchFinder;
+
+/// Paramètres des encodeurs lz, acceptés par toutes les fonctions
+/// `internal_encode_*`. La configuration par défaut correspond au format
+/// historique: des répétitions de 5 à 32767 symboles, des paires de 4 octets
+/// dont l'index est sur 16 bits, et 4 symboles écrits tels quels au début de
+/// la source.
+///
+/// Le format des paires s'adapte aux limites choisies. Une paire commence par
+/// un bit à 1, suivi de la taille sur le nombre de bits nécessaires pour
+/// écrire `max_match`, puis de l'index sur `index_bits` bits. Le tout est
+/// arrondi à l'octet supérieur et écrit en big-endian. Pour des petits
+/// enregistrements, une taille maximum de 127 et un index sur 15 bits donnent
+/// des paires de 3 octets.
+///
+/// Un littéral supérieur ou égal à 0x80 commencerait lui aussi par un bit à 1,
+/// il ouvre une suite de littéraux: une paire de taille 0 dont l'index est le
+/// nombre de littéraux qui la suivent, écrits tels quels. La suite garde tous
+/// les littéraux jusqu'à la prochaine répétition, au plus `2^index_bits - 1`.
+/// Une source sans répétition grandit donc d'une paire par suite, 4 octets
+/// pour 65535 symboles avec la configuration par défaut.
+///
+/// Avec `rep_codes`, les encodeurs gardent les trois dernières distances
+/// utilisées (la distance entre un symbole et l'index de sa répétition). Une
+/// répétition à l'une de ces distances est écrite sur un seul octet
+/// `11rrllll`: `rr` est le rang de la distance et `llll` la taille moins
+/// `min_match`. Si `llll` vaut 15, la taille complète suit sur le nombre
+/// d'octets nécessaires pour écrire `max_match`. Une paire commence alors par
+/// `10`, elle prend un bit de plus.
+///
+/// ```
+/// use final_state_rs::lempel_ziv::*;
+///
+/// let config = LzConfig {
+///     min_match: 4,
+///     max_match: 127,
+///     index_bits: 15,
+///     ..Default::default()
+/// };
+/// assert_eq!(config.pair_size(), 3);
+///
+/// let src = "id=1;name=foo;id=2;name=bar;id=3;name=baz;".as_bytes();
+/// let encoded = internal_encode_lz_with_hashmap_u8::<Faster>(src, &[], &config);
+/// assert!(encoded.len() < encode_lz_with_hashmap_u8(src).len());
+/// assert_eq!(decode_lz_u8_with_config(&encoded, &[], &config), src);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LzConfig {
+    /// Taille minimum d'une répétition, les séquences plus courtes sont
+    /// écrites comme des littéraux.
+    pub min_match: u32,
+    /// Taille maximum d'une répétition, une séquence plus longue est
+    /// découpée en plusieurs paires.
+    pub max_match: u32,
+    /// Distance maximum entre un symbole et la répétition qui le précède.
+    pub window_size: usize,
+    /// Nombre maximum d'occurences précédentes comparées pour chaque
+    /// position.
+    pub search_depth: usize,
+    /// Nombre de symboles écrits tels quels au début d'une source sans
+    /// dictionnaire.
+    pub literal_prefix: usize,
+    /// Nombre de bits de l'index d'une paire. Seuls les `2^index_bits`
+    /// premiers symboles du dictionnaire et de la source sont référençables.
+    pub index_bits: u32,
+    /// Active les répétitions aux dernières distances utilisées.
+    pub rep_codes: bool,
+}
+
+impl Default for LzConfig {
+    fn default() -> Self {
+        LzConfig {
+            min_match: 5,
+            max_match: 32767,
+            window_size: usize::MAX,
+            search_depth: usize::MAX,
+            literal_prefix: 4,
+            index_bits: 16,
+            rep_codes: false,
+        }
+    }
+}
+
+impl LzConfig {
+    /// Configuration par défaut avec une fenêtre de `window_size` symboles.
+    pub fn with_window(window_size: usize) -> Self {
+        LzConfig {
+            window_size,
+            ..Default::default()
+        }
+    }
+
+    /// Nombre de bits de la taille d'une paire.
+    fn len_bits(&self) -> u32 {
+        u32::BITS - self.max_match.leading_zeros()
+    }
+
+    /// Taille en octets d'une paire.
+    pub fn pair_size(&self) -> usize {
+        ((1 + self.rep_codes as u32 + self.len_bits() + self.index_bits) as usize).div_ceil(8)
+    }
+
+    /// Premier index qui ne peut pas être écrit dans une paire.
+    fn max_index(&self) -> usize {
+        1 << self.index_bits
+    }
+
+    fn check(&self) {
+        assert!(
+            self.min_match >= 1,
+            "The minimum match has to be at least 1"
+        );
+        assert!(
+            self.min_match <= self.max_match,
+            "The minimum match can't be greater than the maximum"
+        );
+        // L'index d'une paire de taille 0 compte les littéraux d'une suite.
+        assert!(self.index_bits >= 8, "The index needs at least 8 bits");
+        assert!(self.pair_size() <= 8, "A pair can't be larger than 8 bytes");
+    }
+
+    /// Octets d'une paire taille-index.
+    fn pair_bytes(&self, len: u32, index: usize) -> Vec<u8> {
+        let size = self.pair_size();
+        let bits: u64 = (1 << (size * 8 - 1)) | ((len as u64) << self.index_bits) | index as u64;
+        bits.to_be_bytes()[8 - size..].to_vec()
+    }
+
+    /// Ecrit une paire taille-index, qui termine la suite de littéraux.
+    fn write_pair(&self, len: u32, index: usize, ret: &mut LzOutput) {
+        ret.run = None;
+        ret.bytes.extend(self.pair_bytes(len, index));
+    }
+
+    /// Taille en octets d'une répétition aux dernières distances.
+    fn rep_size(&self, len: u32) -> usize {
+        if len - self.min_match < 15 {
+            1
+        } else {
+            1 + self.len_bits().div_ceil(8) as usize
+        }
+    }
+
+    /// Vrai si `size` octets sont moins coûteux que les `len` littéraux qu'ils
+    /// remplacent. Une répétition termine la suite de littéraux en cours, elle
+    /// paie alors l'en-tête de la suite suivante.
+    fn is_shorter(&self, size: usize, len: u32, ret: &LzOutput) -> bool {
+        let header = match ret.run {
+            Some(_) => self.pair_size(),
+            None => 0,
+        };
+        size + header < len as usize
+    }
+
+    /// Ecrit une répétition à la distance de rang `rep`.
+    fn write_rep(&self, rep: usize, len: u32, ret: &mut LzOutput) {
+        ret.run = None;
+        let ret = &mut ret.bytes;
+        let code = len - self.min_match;
+        if code < 15 {
+            ret.push(0xc0 | (rep as u8) << 4 | code as u8);
+        } else {
+            ret.push(0xc0 | (rep as u8) << 4 | 15);
+            let size = self.len_bits().div_ceil(8) as usize;
+            ret.extend_from_slice(&len.to_be_bytes()[4 - size..]);
+        }
+    }
+
+    /// Ecrit un littéral dans la suite en cours. Sans suite, un littéral qui
+    /// commence par un bit à 1 en ouvre une nouvelle.
+    fn write_literal(&self, symbol: u8, ret: &mut LzOutput) {
+        match ret.run {
+            Some((header, count)) if count + 1 < self.max_index() => {
+                let bytes = self.pair_bytes(0, count + 1);
+                ret.bytes[header..header + bytes.len()].copy_from_slice(&bytes);
+                ret.run = Some((header, count + 1));
+            }
+            _ if symbol < 0x80 => ret.run = None,
+            _ => {
+                ret.run = Some((ret.bytes.len(), 1));
+                ret.bytes.extend(self.pair_bytes(0, 1));
+            }
+        }
+        ret.bytes.push(symbol);
+    }
+}
+
+/// Sortie d'un encodeur lz.
+#[derive(Default)]
+struct LzOutput {
+    bytes: Vec<u8>,
+    /// Position de l'en-tête de la suite de littéraux en cours et nombre de
+    /// littéraux qu'elle contient.
+    run: Option<(usize, usize)>,
+}
+
 /// La fonction suivante encodera une source en suivant une variation de
 /// l'algorithme lempel_ziv. Pour le moment, nous chercherons des récurrences de
 /// termes dans tout l'interval précédent l'index actuelle. Autrement dit, pour
@@ -67,63 +263,92 @@ use std::collections::HashMap;
 /// assert_eq!(decoded, encoded);
 /// ```
 pub fn encode_lz_no_windows_u8(src: &[u8]) -> Vec<u8> {
-    internal_encode_lz_no_windows_u8::<Original>(src)
+    internal_encode_lz_no_windows_u8::<Original>(src, &[], &LzConfig::default())
 }
 
 /// Implémentation générique de lz sans fenêtre. Utilisé par
 /// `encode_lz_no_windows_u8` et `encode_lz_no_windows_u8_fast` décrit plus
 /// loin. Élimine de la duplication de code par pur principe.
+///
+/// Le dictionnaire `dict` est un préfixe partagé entre l'encodeur et le
+/// décodeur. Il n'est pas écrit dans la sortie mais les paires peuvent le
+/// référencer, les indexes sont alors ceux de la concaténation du dictionnaire
+/// et de la source.
 //
 // Nous reviendrons rapidement sur les raisons de cette généricité, pour le
 // moment vous pouvez faire abstraction du template.
-fn internal_encode_lz_no_windows_u8<T: WhileEqual>(src: &[u8]) -> Vec<u8> {
-    let mut index = 4;
-    let mut ret: Vec<u8> = vec![];
-    ret.append(&mut src[..4].to_vec());
+pub fn internal_encode_lz_no_windows_u8<T: WhileEqual>(
+    src: &[u8],
+    dict: &[u8],
+    config: &LzConfig,
+) -> Vec<u8> {
+    config.check();
+    let (src, index) = concat_dict(src, dict, config);
+    let mut ret = LzOutput::default();
+    for symbol in src[dict.len()..index].iter() {
+        config.write_literal(*symbol, &mut ret);
+    }
+    encode_lz_from::<T>(&src, index, config, &mut RepOffsets::new(), &mut ret);
+    ret.bytes
+}
 
-    while index < src.len() - 4 {
-        let mut s = 0;
+/// Encode `src` à partir de `index`, les symboles précédents sont supposés
+/// déjà écrits dans `ret`. Les répétitions sont cherchées dans la fenêtre de
+/// la configuration.
+fn encode_lz_from<T: WhileEqual>(
+    src: &[u8],
+    mut index: usize,
+    config: &LzConfig,
+    reps: &mut RepOffsets,
+    ret: &mut LzOutput,
+) {
+    while index + 4 < src.len() {
+        let rep = reps.find::<T>(src, index, config);
         let mut repetition = Pair::default();
 
-        // Recherche de la plus longue séquence dans l'interval
-        // [s; index - 4]. Puisque nous savons qu'un charactère identique
-        // en `index - 4` donnera une taille maximum de 4, nous pouvons
-        // dors et déjà éviter un encodage superflue.
-        while s < index - 4 {
+        // Recherche de la plus longue séquence dans l'interval [s; index[.
+        // Une séquence commençant en `s` peut déborder sur `index`, c'est
+        // ce qui permet d'encoder une longue répétition ("aaaa...", ou
+        // "ababab...") en une seule paire.
+        //
+        // On part de la séquence la plus proche, ainsi une recherche limitée
+        // par `search_depth` garde les candidats les plus récents. A taille
+        // égale, la séquence la plus ancienne est gardée.
+        let start = index.saturating_sub(config.window_size);
+        let mut s = std::cmp::min(index, config.max_index());
+        let mut depth = config.search_depth;
+        while s > start && depth > 0 {
+            s -= 1;
             if src[s] == src[index] {
                 // Si src[s] == src[index], nous pouvons commencer à rechercher
                 // la taille de la séquence commune à partir des deux indexes.
-                let len = T::while_equal(src, s, index);
-                if (5..32768).contains(&len) && repetition.len < len {
+                // Un chevauchement peut produire une séquence plus longue
+                // que ce que la paire peut représenter, on la tronque.
+                depth -= 1;
+                let len = std::cmp::min(T::while_equal(src, s, index), config.max_match);
+                if len >= config.min_match && repetition.len <= len {
                     repetition.len = len;
                     repetition.index = s;
                 }
             }
-            s += 1;
         }
-        if repetition.len == 0 {
-            // Je n'ai trouvé aucune répétition,
-            // donc j'écris le symbole et j'avance de 1.
-            ret.push(src[index]);
-            index += 1;
-        } else {
+        match write_repetition(config, reps, rep, &repetition, index, ret) {
+            0 => {
+                // Je n'ai trouvé aucune répétition,
+                // donc j'écris le symbole et j'avance de 1.
+                config.write_literal(src[index], ret);
+                index += 1;
+            }
             // J'ai trouvé une répétition, j'avance de la
             // taille de celle-ci
-
-            // Construit la paire taille-index sur 32 bits
-            const FLAG_MASK: u32 = 1 << 15;
-            let bits: u32 = ((repetition.len | FLAG_MASK) << 16) + repetition.index as u32;
-            ret.append(&mut bits.to_be_bytes().to_vec());
-            index += repetition.len as usize;
+            len => index += len,
         }
     }
     // Ecrit les dernier bits restants dans le cas où index est
     // dans l'interval [len - 4; len[
-    if index < src.len() {
-        let diff = src.len() - index;
-        ret.append(&mut src[src.len() - diff..].to_vec());
+    for symbol in src[std::cmp::min(index, src.len())..].iter() {
+        config.write_literal(*symbol, ret);
     }
-    ret
 }
 
 /// Pour éviter trop de duplication de code entre une version optimisée et une version
@@ -149,7 +374,9 @@ fn internal_encode_lz_no_windows_u8<T: WhileEqual>(src: &[u8]) -> Vec<u8> {
 pub trait WhileEqual {
     /// La fonction `while_equal` prend comme arguments une source et deux indexes.
     /// Elle calculera le nombre de carractères identiques à partir de ces deux
-    /// indexes dans la limite suivante min(index - from, src.len - index).
+    /// indexes dans la limite de `src.len - index`. La séquence commençant en
+    /// `from` peut chevaucher celle commençant en `index`, le décodeur sait
+    /// reproduire une telle copie octet par octet.
     fn while_equal(src: &[u8], from: usize, index: usize) -> u32;
 }
 
@@ -163,7 +390,7 @@ impl WhileEqual for Original {
         let mut s = from + 1;
         let mut i = index + 1;
 
-        while s < index && i < src.len() && src[s] == src[i] {
+        while i < src.len() && src[s] == src[i] {
             s += 1;
             i += 1;
         }
@@ -186,7 +413,7 @@ impl WhileEqual for Fast {
 
         // Split in 4 the tests, each block will be done in parrallel by an OoO
         // processor.
-        while s + 4 < index && i + 4 < src.len() {
+        while i + 4 < src.len() {
             let mut b1 = false;
             if src[s] == src[i] {
                 b1 = true;
@@ -212,7 +439,7 @@ impl WhileEqual for Fast {
         }
 
         // Fix the last bytes unchecked
-        while s < index && i < src.len() && src[s] == src[i] {
+        while i < src.len() && src[s] == src[i] {
             s += 1;
             i += 1;
         }
@@ -223,12 +450,12 @@ impl WhileEqual for Fast {
 
 impl WhileEqual for Faster {
     /// Use an unsafe conversion of *const u8 into *const usize. Which
-    /// allow us to test 4 or 8 bytes once. Panic if src.len() > BYTES_LEN
-    /// where BYTES_LEN is 4 or 8 depending of the target.
+    /// allow us to test 4 or 8 bytes once, the last bytes of the source are
+    /// compared one by one. The pointers are not aligned, so every
+    /// dereferencement is an unaligned read.
     fn while_equal(src: &[u8], from: usize, index: usize) -> u32 {
         assert!(from < index);
         assert!(index < src.len());
-        assert!(src.len() > BYTES_LEN + 1);
         assert_eq!(src[from], src[index]);
 
         let mut s = from + 1;
@@ -240,21 +467,22 @@ impl WhileEqual for Faster {
         let mut ps = unsafe { src.as_ptr().add(s) as *const usize };
         let mut is = unsafe { src.as_ptr().add(i) as *const usize };
 
-        // s + BYTES_LEN < index && i + BYTES_LEN < src.len(): verification en
-        // premier lieu que nous n'empiétons pas sur la partie droite de la
-        // source. Puis en second lieu que nos déréferencements ce font bien sur
-        // un interval où nous avons notre source.
-        while s + BYTES_LEN < index && i + BYTES_LEN < src.len() && unsafe { *ps == *is } {
+        // i + BYTES_LEN < src.len(): nos déréferencements ce font bien sur un
+        // interval où nous avons notre source. Puisque s < i, il n'est pas
+        // nécessaire de vérifier s.
+        while i + BYTES_LEN < src.len() && unsafe { ps.read_unaligned() == is.read_unaligned() } {
+            // Les pointeurs sont des `*const usize`, avancer de 1 revient à
+            // avancer de BYTES_LEN octets.
             unsafe {
-                ps = ps.add(BYTES_LEN);
-                is = is.add(BYTES_LEN);
+                ps = ps.add(1);
+                is = is.add(1);
             }
             s += BYTES_LEN;
             i += BYTES_LEN;
         }
 
         // Fix the last bytes unchecked
-        while s < index && i < src.len() && src[s] == src[i] {
+        while i < src.len() && src[s] == src[i] {
             s += 1;
             i += 1;
         }
@@ -270,13 +498,13 @@ impl WhileEqual for Faster {
 /// Do the same thing as `encode_lz_no_windows_u8` but use `while_equal_fast`
 /// which is optimized for OoO processor.
 pub fn encode_lz_no_windows_u8_fast(src: &[u8]) -> Vec<u8> {
-    internal_encode_lz_no_windows_u8::<Fast>(src)
+    internal_encode_lz_no_windows_u8::<Fast>(src, &[], &LzConfig::default())
 }
 
 /// Do the same thing as `encode_lz_no_windows_u8` but use `while_equal_faster`
 /// which has a better optimization.
 pub fn encode_lz_no_windows_u8_faster(src: &[u8]) -> Vec<u8> {
-    internal_encode_lz_no_windows_u8::<Faster>(src)
+    internal_encode_lz_no_windows_u8::<Faster>(src, &[], &LzConfig::default())
 }
 
 /// Checks that theorically lz is more performant to compress than its
@@ -298,11 +526,19 @@ fn compare_lz() {
 
 /// lempel_ziv variation of lz algorithm with a windows size.
 pub fn encode_lempel_ziv_u8(src: &[u8], windows_size: usize) -> Vec<u8> {
-    internal_encode_lempel_ziv_u8::<Original>(src, windows_size)
+    internal_encode_lempel_ziv_u8::<Original>(src, &[], &LzConfig::with_window(windows_size))
 }
 
-/// Internal implementation of the lempel_ziv algorithm.
-pub fn internal_encode_lempel_ziv_u8<T: WhileEqual>(src: &[u8], windows_size: usize) -> Vec<u8> {
+/// Internal implementation of the lempel_ziv algorithm. The dictionary is
+/// reachable while it's in the window, or in the first part of the source
+/// (see below). The window is the one of the configuration.
+pub fn internal_encode_lempel_ziv_u8<T: WhileEqual>(
+    src: &[u8],
+    dict: &[u8],
+    config: &LzConfig,
+) -> Vec<u8> {
+    config.check();
+    let windows_size = config.window_size;
     assert!(windows_size < src.len());
 
     // On peut découper le calcule de la sortie en 2 algorithmes. La première
@@ -311,52 +547,34 @@ pub fn internal_encode_lempel_ziv_u8<T: WhileEqual>(src: &[u8], windows_size: us
     // vérification si windows_size < index.
 
     // TODO: use a bitstream instead of a vec
-    let mut ret = internal_encode_lz_no_windows_u8::<T>(&src[..=windows_size]);
-
-    let mut index = windows_size + 1;
-    while index < src.len() - 4 {
-        let mut s = index - windows_size;
-        let mut repetition = Pair::default();
-
-        // Recherche de la plus longue séquence.
-        while s < index - 4 {
-            if src[s] == src[index] {
-                let len = T::while_equal(src, s, index);
-                if (5..32768).contains(&len) && repetition.len < len {
-                    repetition.len = len;
-                    repetition.index = s;
-                }
-            }
-            s += 1;
-        }
-        if repetition.len == 0 {
-            // Je n'ai trouvé aucune répétition,
-            // donc j'écris le symbole et j'avance de 1.
-            ret.push(src[index]);
-            index += 1;
-        } else {
-            // J'ai trouvé une répétition, j'avance de la
-            // taille de celle-ci
-
-            // Construit la paire taille-index sur 32 bits
-            const FLAG_MASK: u32 = 1 << 15;
-            let bits: u32 = ((repetition.len | FLAG_MASK) << 16) + repetition.index as u32;
-            ret.append(&mut bits.to_be_bytes().to_vec());
-            index += repetition.len as usize;
-        }
+    let no_windows = LzConfig {
+        window_size: usize::MAX,
+        ..*config
+    };
+    let (src, index) = concat_dict(src, dict, config);
+    let mut ret = LzOutput::default();
+    for symbol in src[dict.len()..index].iter() {
+        config.write_literal(*symbol, &mut ret);
     }
-    // Ecrit les dernier bits restants dans le cas où index est
-    // dans l'interval [len - 4; len[
-    if index < src.len() {
-        let diff = src.len() - index;
-        ret.append(&mut src[src.len() - diff..].to_vec());
-    }
-    ret
+    let mut reps = RepOffsets::new();
+    // Une petite fenêtre peut se terminer dans les littéraux du début.
+    let end = std::cmp::max(index, dict.len() + windows_size + 1);
+    encode_lz_from::<T>(&src[..end], index, &no_windows, &mut reps, &mut ret);
+    encode_lz_from::<T>(&src, end, config, &mut reps, &mut ret);
+    ret.bytes
 }
 
 /// Internal implementation of the lempel-ziv algorithm.
-pub fn internal_encode_lz_with_hashmap_u8<T: WhileEqual>(src: &[u8]) -> Vec<u8> {
+///
+/// Les clés de la hashmap sont les 4 premiers symboles de chaque séquence,
+/// une répétition plus courte n'est jamais trouvée.
+pub fn internal_encode_lz_with_hashmap_u8<T: WhileEqual>(
+    src: &[u8],
+    dict: &[u8],
+    config: &LzConfig,
+) -> Vec<u8> {
     use std::collections::hash_map::Entry::*;
+    config.check();
 
     // On peut découper le calcule de la sortie en 2 algorithmes. La première
     // partie pour les indexes <= à windows_size, et la deuxième pour les
@@ -366,24 +584,45 @@ pub fn internal_encode_lz_with_hashmap_u8<T: WhileEqual>(src: &[u8]) -> Vec<u8>
     // TODO: use a bitstream instead of a vec
     // let mut ret = internal_encode_lz_no_windows_u8::<T>(&src[..=windows_size]);
 
-    let mut ret = vec![];
+    let mut ret = LzOutput::default();
     let mut hmap = HashMap::<u32, Vec<usize>>::default();
 
-    let mut index = 0;
-    while index < src.len() - 4 {
+    // Comme pour `internal_encode_lz_no_windows_u8`, les 4 premiers symboles
+    // sont écrits tels quels s'il n'y a pas de dictionnaire. Sans ça, une
+    // répétition qui chevauche le début de la source serait encodée ici et pas
+    // dans la version sans fenêtre. Le dictionnaire est indexé en premier.
+    let (src, mut index) = concat_dict(src, dict, config);
+    for symbol in src[dict.len()..index].iter() {
+        config.write_literal(*symbol, &mut ret);
+    }
+    for i in 0..std::cmp::min(index, src.len().saturating_sub(3)) {
+        let key = unsafe { (src.as_ptr().add(i) as *const u32).read_unaligned() };
+        hmap.entry(key).or_default().push(i);
+    }
+
+    let mut reps = RepOffsets::new();
+    while index + 4 < src.len() {
+        let rep = reps.find::<T>(&src, index, config);
         let mut repetition = Pair::default();
 
-        // Recherche de la plus longue séquence.
+        // Recherche de la plus longue séquence, de la plus proche à la plus
+        // ancienne comme dans `encode_lz_from`.
 
         // TODO: an error is hidden in that code. When I try with more
         //       than 100k, we have got a problem.
-        let key = unsafe { *(src.as_ptr().add(index) as *const u32) };
+        let key = unsafe { (src.as_ptr().add(index) as *const u32).read_unaligned() };
         match hmap.entry(key) {
             Occupied(mut entry) => {
                 let prev = entry.get_mut();
-                for s in prev.iter() {
-                    let len = T::while_equal(src, *s, index);
-                    if (5..32768).contains(&len) && repetition.len < len {
+                let candidates = prev
+                    .iter()
+                    .rev()
+                    .take_while(|s| index - **s <= config.window_size)
+                    .filter(|s| **s < config.max_index())
+                    .take(config.search_depth);
+                for s in candidates {
+                    let len = std::cmp::min(T::while_equal(&src, *s, index), config.max_match);
+                    if len >= config.min_match && repetition.len <= len {
                         repetition.len = len;
                         repetition.index = *s;
                     }
@@ -394,21 +633,19 @@ pub fn internal_encode_lz_with_hashmap_u8<T: WhileEqual>(src: &[u8]) -> Vec<u8>
                 e.insert(vec![index]);
             }
         };
-        if repetition.len == 0 {
+        let len = write_repetition(config, &mut reps, rep, &repetition, index, &mut ret);
+        if len == 0 {
             // Je n'ai trouvé aucune répétition,
             // donc j'écris le symbole et j'avance de 1.
-            ret.push(src[index]);
+            config.write_literal(src[index], &mut ret);
             index += 1;
         } else {
             // J'ai trouvé une répétition, j'avance de la
-            // taille de celle-ci
-
-            // Construit la paire taille-index sur 32 bits
-            const FLAG_MASK: u32 = 1 << 15;
-            let bits: u32 = ((repetition.len | FLAG_MASK) << 16) + repetition.index as u32;
-            ret.append(&mut bits.to_be_bytes().to_vec());
-            for i in index + 1..index + repetition.len as usize {
-                let key = unsafe { *(src.as_ptr().add(i) as *const u32) };
+            // taille de celle-ci. Les clés sont lues sur 4 octets, on
+            // s'arrête avant de dépasser la fin de la source.
+            let end = std::cmp::min(index + len, src.len() - 3);
+            for i in index + 1..end {
+                let key = unsafe { (src.as_ptr().add(i) as *const u32).read_unaligned() };
                 match hmap.entry(key) {
                     Occupied(mut entry) => {
                         entry.get_mut().push(i);
@@ -418,42 +655,400 @@ pub fn internal_encode_lz_with_hashmap_u8<T: WhileEqual>(src: &[u8]) -> Vec<u8>
                     }
                 };
             }
-            index += repetition.len as usize;
+            index += len;
         }
     }
     // Ecrit les dernier bits restants dans le cas où index est
     // dans l'interval [len - 4; len[
-    if index < src.len() {
-        let diff = src.len() - index;
-        ret.append(&mut src[src.len() - diff..].to_vec());
+    for symbol in src[std::cmp::min(index, src.len())..].iter() {
+        config.write_literal(*symbol, &mut ret);
     }
-    ret
+    ret.bytes
 }
 
 pub fn encode_lz_with_hashmap_u8(src: &[u8]) -> Vec<u8> {
-    internal_encode_lz_with_hashmap_u8::<Faster>(src)
+    internal_encode_lz_with_hashmap_u8::<Faster>(src, &[], &LzConfig::default())
+}
+
+/// Lempel-ziv sans fenêtre dont les répétitions sont cherchées avec le
+/// `BinaryTreeMatchFinder`. Les arbres donnent directement la plus longue
+/// répétition de chaque position, sans parcourir toutes les occurences
+/// précédentes comme la version avec une hashmap. Pour une source qui n'est
+/// pas trop grande, la sortie a la même taille que `encode_lz_no_windows_u8`.
+///
+/// ```
+/// use final_state_rs::lempel_ziv::*;
+///
+/// let src = "Lorem ipsum dolor sit amet, Lorem ipsum dolor sit amet.".as_bytes();
+/// let encoded = encode_lz_with_binary_tree_u8(src);
+/// assert_eq!(encoded.len(), encode_lz_no_windows_u8(src).len());
+/// assert_eq!(decode_lz_u8(&encoded), src);
+/// ```
+pub fn encode_lz_with_binary_tree_u8(src: &[u8]) -> Vec<u8> {
+    let config = LzConfig {
+        search_depth: BINARY_TREE_DEPTH,
+        ..Default::default()
+    };
+    internal_encode_lz_with_binary_tree_u8(src, &[], &config)
+}
+
+/// Nombre maximum de noeuds visités dans l'arbre pour chaque position.
+const BINARY_TREE_DEPTH: usize = 256;
+
+/// Internal implementation of the lempel-ziv algorithm with a binary tree
+/// match finder. At most `search_depth` nodes are visited for each position
+/// and, as with the hashmap, a match is at least 4 bytes long.
+pub fn internal_encode_lz_with_binary_tree_u8(
+    src: &[u8],
+    dict: &[u8],
+    config: &LzConfig,
+) -> Vec<u8> {
+    config.check();
+    let mut ret = LzOutput::default();
+    let (src, mut index) = concat_dict(src, dict, config);
+    for symbol in src[dict.len()..index].iter() {
+        config.write_literal(*symbol, &mut ret);
+    }
+    let max_len = std::cmp::max(config.max_match as usize, 4);
+    let mut finder =
+        BinaryTreeMatchFinder::new(&src, config.window_size, config.search_depth, max_len);
+    for i in 0..index {
+        finder.skip(i);
+    }
+
+    let mut reps = RepOffsets::new();
+    while index + 4 < src.len() {
+        let rep = reps.find::<Faster>(&src, index, config);
+        // Les répétitions sont triées par taille, on prend la plus longue
+        // dont l'index tient dans une paire.
+        let matches = finder.find_matches(index);
+        let repetition = match matches.iter().rev().find(|m| m.index < config.max_index()) {
+            Some(m) if m.len >= config.min_match => Pair {
+                index: m.index,
+                len: std::cmp::min(m.len, config.max_match),
+            },
+            _ => Pair::default(),
+        };
+        match write_repetition(config, &mut reps, rep, &repetition, index, &mut ret) {
+            0 => {
+                config.write_literal(src[index], &mut ret);
+                index += 1;
+            }
+            len => {
+                for i in index + 1..index + len {
+                    finder.skip(i);
+                }
+                index += len;
+            }
+        }
+    }
+    for symbol in src[std::cmp::min(index, src.len())..].iter() {
+        config.write_literal(*symbol, &mut ret);
+    }
+    ret.bytes
+}
+
+/// Concatène le dictionnaire et la source. Retourne aussi l'index du premier
+/// symbole à encoder: sans dictionnaire, les `literal_prefix` premiers
+/// symboles sont écrits tels quels.
+fn concat_dict(src: &[u8], dict: &[u8], config: &LzConfig) -> (Vec<u8>, usize) {
+    let start = if dict.is_empty() {
+        std::cmp::min(config.literal_prefix, src.len())
+    } else {
+        dict.len()
+    };
+    ([dict, src].concat(), start)
+}
+
+/// Ecrit la répétition trouvée à `index`, ou celle à l'une des dernières
+/// distances si elle est plus avantageuse. Une répétition aux dernières
+/// distances est gardée tant qu'elle ne couvre pas moins de symboles que ce
+/// que coûte une paire. Une répétition n'est écrite que si elle est plus
+/// courte que ses littéraux, un `min_match` inférieur à la taille d'une paire
+/// n'agrandit pas la sortie. Retourne la taille écrite, 0 si aucune
+/// répétition.
+fn write_repetition(
+    config: &LzConfig,
+    reps: &mut RepOffsets,
+    rep: (usize, u32),
+    repetition: &Pair,
+    index: usize,
+    ret: &mut LzOutput,
+) -> usize {
+    let (rank, mut rep_len) = rep;
+    if rep_len > 0 && !config.is_shorter(config.rep_size(rep_len), rep_len, ret) {
+        rep_len = 0;
+    }
+    let len = match config.is_shorter(config.pair_size(), repetition.len, ret) {
+        true => repetition.len,
+        false => 0,
+    };
+    if rep_len > 0 && (rep_len + config.pair_size() as u32) > len {
+        config.write_rep(rank, rep_len, ret);
+        reps.promote(rank);
+        rep_len as usize
+    } else if len > 0 {
+        config.write_pair(repetition.len, repetition.index, ret);
+        reps.push(index - repetition.index);
+        repetition.len as usize
+    } else {
+        0
+    }
 }
 
 /// Decode any output from encode_lempel_ziv* and encode_lz*.
+///
+/// A pair may reference a sequence that overlaps the bytes it produces (its
+/// index plus its length goes beyond the current output length), so the copy
+/// is done byte per byte.
+///
+/// ```
+/// use final_state_rs::lempel_ziv::*;
+///
+/// let src = "abcdabababababababababababababababababababababab".as_bytes();
+/// let encoded = encode_lz_no_windows_u8(src);
+/// assert_eq!(encoded.len(), 10);
+/// assert_eq!(decode_lz_u8(&encoded), src);
+/// ```
 pub fn decode_lz_u8(src: &[u8]) -> Vec<u8> {
-    let mut ret: Vec<u8> = vec![];
-    let mut it = src.iter();
+    decode_lz_u8_with_dict(src, &[])
+}
+
+/// Decode any output from the encoders `*_with_dict`, the dictionary has to
+/// be the same than the one given to the encoder.
+pub fn decode_lz_u8_with_dict(src: &[u8], dict: &[u8]) -> Vec<u8> {
+    decode_lz_u8_with_config(src, dict, &LzConfig::default())
+}
+
+/// Decode any output from the `internal_encode_*` functions, the dictionary
+/// and the configuration have to be the same than the ones given to the
+/// encoder. Only the format of the pairs depends on the configuration.
+///
+/// Panic if the source is invalid, see `try_decode_lz_u8_with_config`.
+pub fn decode_lz_u8_with_config(src: &[u8], dict: &[u8], config: &LzConfig) -> Vec<u8> {
+    try_decode_lz_u8_with_config(src, dict, config, usize::MAX)
+        .unwrap_or_else(|err| panic!("Cannot decode the lz source: {err:?}"))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LzError {
+    /// The source ends in the middle of a pair.
+    Truncated,
+    /// A pair or a repetition references a symbol that isn't decoded yet.
+    InvalidIndex(usize),
+    /// A repetition uses an unknown rank of the last distances.
+    InvalidRank(usize),
+    /// The decoded source would be greater than the maximum size.
+    TooLarge,
+}
+
+/// Same as `decode_lz_u8_with_config`, but an invalid source returns an error
+/// instead of a panic. A pair of a few bytes can produce thousands of
+/// symbols, the decoder stops with `LzError::TooLarge` before the output
+/// exceeds `max_size`.
+///
+/// ```
+/// use final_state_rs::lempel_ziv::*;
+///
+/// let config = LzConfig::default();
+/// let encoded = encode_lz_no_windows_u8("abcdeabcdeabcde".as_bytes());
+/// let decoded = try_decode_lz_u8_with_config(&encoded, &[], &config, 100);
+/// assert_eq!(decoded.unwrap(), b"abcdeabcdeabcde");
+/// assert_eq!(
+///     try_decode_lz_u8_with_config(&encoded, &[], &config, 10),
+///     Err(LzError::TooLarge)
+/// );
+/// // Une paire qui référence le symbole 9 alors que seuls 2 sont décodés.
+/// assert_eq!(
+///     try_decode_lz_u8_with_config(&[b'a', b'b', 0x80, 0x05, 0x00, 0x09], &[], &config, 100),
+///     Err(LzError::InvalidIndex(9))
+/// );
+/// ```
+pub fn try_decode_lz_u8_with_config(
+    src: &[u8],
+    dict: &[u8],
+    config: &LzConfig,
+    max_size: usize,
+) -> Result<Vec<u8>, LzError> {
+    config.check();
+    let mut ret: Vec<u8> = dict.to_vec();
+    let max_size = dict.len().saturating_add(max_size);
+    let mut reps = RepOffsets::new();
+    let size = config.pair_size();
+    let len_mask = (1u64 << (size * 8 - 1 - config.index_bits as usize)) - 1;
+    let index_mask = (1u64 << config.index_bits) - 1;
     const FLAG_BIT: u8 = 1 << 7;
-    const FLAG_MASK: u8 = FLAG_BIT - 1;
-    while let Some(symbol) = it.next() {
-        if *symbol >= FLAG_BIT {
-            let hi_bits_len = ((*symbol & FLAG_MASK) as u16) << 8;
-            let lo_bits_len = *it.next().unwrap();
-            let len = (hi_bits_len + lo_bits_len as u16) as usize;
-            let hi_bits_index = (*it.next().unwrap() as u16) << 8;
-            let lo_bits_index = *it.next().unwrap() as u16;
-            let index = (hi_bits_index + lo_bits_index) as usize;
-            ret.append(&mut ret[index..index + len].to_vec());
+    let mut i = 0;
+    while i < src.len() {
+        let (index, len) = if config.rep_codes && src[i] >= FLAG_BIT | FLAG_BIT >> 1 {
+            // Répétition à l'une des dernières distances
+            let rank = (src[i] >> 4 & 3) as usize;
+            let mut len = (src[i] & 15) as usize + config.min_match as usize;
+            i += 1;
+            if src[i - 1] & 15 == 15 {
+                let len_size = config.len_bits().div_ceil(8) as usize;
+                len = src
+                    .get(i..i + len_size)
+                    .ok_or(LzError::Truncated)?
+                    .iter()
+                    .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+                i += len_size;
+            }
+            if rank >= reps.0.len() {
+                return Err(LzError::InvalidRank(rank));
+            }
+            let distance = reps.promote(rank);
+            if distance > ret.len() {
+                return Err(LzError::InvalidIndex(distance));
+            }
+            (ret.len() - distance, len)
+        } else if src[i] >= FLAG_BIT {
+            let bits = src
+                .get(i..i + size)
+                .ok_or(LzError::Truncated)?
+                .iter()
+                .fold(0u64, |bits, byte| (bits << 8) | *byte as u64);
+            i += size;
+            let len = ((bits >> config.index_bits) & len_mask) as usize;
+            let index = (bits & index_mask) as usize;
+            if len == 0 {
+                // Suite de `index` littéraux
+                let literals = src.get(i..i + index).ok_or(LzError::Truncated)?;
+                if index > max_size - ret.len() {
+                    return Err(LzError::TooLarge);
+                }
+                ret.extend_from_slice(literals);
+                i += index;
+                continue;
+            }
+            if index >= ret.len() {
+                return Err(LzError::InvalidIndex(index));
+            }
+            reps.push(ret.len() - index);
+            (index, len)
         } else {
-            ret.push(*symbol);
+            if ret.len() >= max_size {
+                return Err(LzError::TooLarge);
+            }
+            ret.push(src[i]);
+            i += 1;
+            continue;
+        };
+        if len > max_size - ret.len() {
+            return Err(LzError::TooLarge);
+        }
+        for i in index..index + len {
+            ret.push(ret[i]);
         }
     }
-    ret
+    Ok(ret.split_off(dict.len()))
+}
+
+/// Lorsque les sources sont petites et semblables, par exemple des messages
+/// d'un même protocole, lz ne trouve presque aucune répétition à l'intérieur de
+/// chacune. On peut partager un dictionnaire entre l'encodeur et le décodeur,
+/// un préfixe qui n'est jamais écrit mais que les paires peuvent référencer.
+///
+/// Les indexes des paires étant écrits sur 16 bits, la taille du dictionnaire
+/// plus celle de la source ne devrait pas dépasser 65536.
+///
+/// ```
+/// use final_state_rs::lempel_ziv::*;
+///
+/// let samples: Vec<String> = (0..100)
+///     .map(|i| format!("{{\"id\":{i},\"event\":\"connection\",\"status\":\"accepted\"}}"))
+///     .collect();
+/// let samples: Vec<&[u8]> = samples.iter().map(|s| s.as_bytes()).collect();
+/// let dict = train_dictionary(&samples, 1024);
+///
+/// let message = "{\"id\":4242,\"event\":\"connection\",\"status\":\"accepted\"}".as_bytes();
+/// let encoded = encode_lz_with_hashmap_u8_with_dict(message, &dict);
+/// assert!(encoded.len() < encode_lz_with_hashmap_u8(message).len());
+/// assert_eq!(decode_lz_u8_with_dict(&encoded, &dict), message);
+/// ```
+pub fn encode_lz_with_hashmap_u8_with_dict(src: &[u8], dict: &[u8]) -> Vec<u8> {
+    internal_encode_lz_with_hashmap_u8::<Faster>(src, dict, &LzConfig::default())
+}
+
+/// Same as `encode_lz_no_windows_u8` with a shared prefix dictionary.
+pub fn encode_lz_no_windows_u8_with_dict(src: &[u8], dict: &[u8]) -> Vec<u8> {
+    internal_encode_lz_no_windows_u8::<Faster>(src, dict, &LzConfig::default())
+}
+
+/// Same as `encode_lempel_ziv_u8` with a shared prefix dictionary.
+pub fn encode_lempel_ziv_u8_with_dict(src: &[u8], dict: &[u8], windows_size: usize) -> Vec<u8> {
+    internal_encode_lempel_ziv_u8::<Faster>(src, dict, &LzConfig::with_window(windows_size))
+}
+
+/// Taille des sous-séquences comptées par `train_dictionary`.
+const DICT_DMER_SIZE: usize = 6;
+/// Taille des segments sélectionnés par `train_dictionary`.
+const DICT_SEGMENT_SIZE: usize = 32;
+
+/// Construit un dictionnaire d'au plus `dict_size` octets à partir d'un corpus
+/// d'exemples. L'approche est une version simplifiée de l'algorithme "cover"
+/// de zstd.
+///
+/// On compte, pour chaque sous-séquence de `DICT_DMER_SIZE` octets, le nombre
+/// d'exemples dans lesquels elle apparait. Un segment de `DICT_SEGMENT_SIZE`
+/// octets vaut la somme des fréquences de ses sous-séquences présentes dans au
+/// moins deux exemples. On sélectionne le meilleur segment, on oublie les
+/// sous-séquences qu'il couvre, puis on recommence jusqu'à remplir le
+/// dictionnaire. Les meilleurs segments sont placés à la fin.
+pub fn train_dictionary(samples: &[&[u8]], dict_size: usize) -> Vec<u8> {
+    let mut frequencies = HashMap::<&[u8], usize>::default();
+    for sample in samples {
+        let mut seen = std::collections::HashSet::<&[u8]>::default();
+        for dmer in sample.windows(DICT_DMER_SIZE) {
+            if seen.insert(dmer) {
+                *frequencies.entry(dmer).or_default() += 1;
+            }
+        }
+    }
+    frequencies.retain(|_, f| *f > 1);
+
+    let mut segments: Vec<&[u8]> = vec![];
+    let mut size = 0;
+    while size < dict_size {
+        // Recherche du meilleur segment, le score d'un segment est mis à jour
+        // en glissant d'une position à chaque étape.
+        let mut best: (usize, &[u8]) = (0, &[]);
+        for sample in samples.iter().filter(|s| s.len() >= DICT_DMER_SIZE) {
+            let scores: Vec<usize> = sample
+                .windows(DICT_DMER_SIZE)
+                .map(|dmer| *frequencies.get(dmer).unwrap_or(&0))
+                .collect();
+            let nb_dmers = std::cmp::min(
+                scores.len(),
+                DICT_SEGMENT_SIZE.saturating_sub(DICT_DMER_SIZE) + 1,
+            );
+            let mut score: usize = scores[..nb_dmers].iter().sum();
+            for start in 0..=scores.len() - nb_dmers {
+                if start > 0 {
+                    score = score + scores[start + nb_dmers - 1] - scores[start - 1];
+                }
+                if score > best.0 {
+                    let end = std::cmp::min(start + DICT_SEGMENT_SIZE, sample.len());
+                    best = (score, &sample[start..end]);
+                }
+            }
+        }
+        if best.0 == 0 {
+            break;
+        }
+        for dmer in best.1.windows(DICT_DMER_SIZE) {
+            frequencies.remove(dmer);
+        }
+        let segment = &best.1[..std::cmp::min(best.1.len(), dict_size - size)];
+        size += segment.len();
+        segments.push(segment);
+    }
+    segments
+        .iter()
+        .rev()
+        .flat_map(|s| s.iter())
+        .copied()
+        .collect()
 }
 
 /* *************************************************************************
@@ -483,12 +1078,12 @@ pub fn while_equal_faster(src: &[u8], from: usize, index: usize) -> u32 {
 /// lempel_ziv variation of lz algorithm with a windows size. With the optimization
 /// for OoO processors.
 pub fn encode_lempel_ziv_u8_fast(src: &[u8], windows_size: usize) -> Vec<u8> {
-    internal_encode_lempel_ziv_u8::<Fast>(src, windows_size)
+    internal_encode_lempel_ziv_u8::<Fast>(src, &[], &LzConfig::with_window(windows_size))
 }
 
 /// lempel_ziv variation of lz algorithm with a windows size. With the usize optimization.
 pub fn encode_lz_u8_faster(src: &[u8], windows_size: usize) -> Vec<u8> {
-    internal_encode_lempel_ziv_u8::<Faster>(src, windows_size)
+    internal_encode_lempel_ziv_u8::<Faster>(src, &[], &LzConfig::with_window(windows_size))
 }
 
 /// Representation of a size-index pair, we could have done without it and used
@@ -505,6 +1100,52 @@ struct Pair {
     len: u32,
 }
 
+/// The last three distances used by a pair or a repetition at one of these
+/// distances, the most recent first. The encoder and the decoder update them
+/// in the same way.
+struct RepOffsets([usize; 3]);
+
+impl RepOffsets {
+    fn new() -> Self {
+        RepOffsets([1, 4, 8])
+    }
+
+    /// Return the rank and the length of the longest repetition at one of the
+    /// last distances, a length of 0 if there is none.
+    fn find<T: WhileEqual>(&self, src: &[u8], index: usize, config: &LzConfig) -> (usize, u32) {
+        let mut best = (0, 0);
+        if !config.rep_codes {
+            return best;
+        }
+        for (rank, distance) in self.0.iter().enumerate() {
+            if *distance > index || src[index - distance] != src[index] {
+                continue;
+            }
+            let len = std::cmp::min(
+                T::while_equal(src, index - distance, index),
+                config.max_match,
+            );
+            if len >= config.min_match && len > best.1 {
+                best = (rank, len);
+            }
+        }
+        best
+    }
+
+    /// Move the distance of rank `rank` in the first place and return it.
+    fn promote(&mut self, rank: usize) -> usize {
+        let distance = self.0[rank];
+        self.0.copy_within(0..rank, 1);
+        self.0[0] = distance;
+        distance
+    }
+
+    /// Add the distance of a new pair.
+    fn push(&mut self, distance: usize) {
+        self.0 = [distance, self.0[0], self.0[1]];
+    }
+}
+
 // The empties structures Original, Fast, Faster and X86_64 are used to dispatch
 // statically the lempel_ziv and lz algorithm which uses the while_equal functions.
 // Since the while_equal function has multiple implementation, you can choose
@@ -532,7 +1173,6 @@ impl WhileEqual for X86_64 {
     fn while_equal(src: &[u8], from: usize, index: usize) -> u32 {
         assert!(from < index);
         assert!(index < src.len());
-        assert!(src.len() > I64X2_BYTES_LEN + 1);
         assert_eq!(src[from], src[index]);
 
         let mut s = from + 1;
@@ -543,11 +1183,10 @@ impl WhileEqual for X86_64 {
         use std::arch::x86_64::_SIDD_CMP_EQUAL_ORDERED;
 
         const I64X2_BYTES_LEN: usize = 16;
-        // s + I64X2_BYTES_LEN < index && i + I64X2_BYTES_LEN < src.len(): verification en
-        // premier lieu que nous n'empiétons pas sur la partie droite de la
-        // source. Puis en second lieu que nos déréferencements ce font bien sur
-        // un interval où nous avons notre source.
-        while s + I64X2_BYTES_LEN < index && i + I64X2_BYTES_LEN < src.len() {
+        // i + I64X2_BYTES_LEN < src.len(): nos déréferencements ce font bien
+        // sur un interval où nous avons notre source. Puisque s < i, il n'est
+        // pas nécessaire de vérifier s.
+        while i + I64X2_BYTES_LEN < src.len() {
             let ps = unsafe { _mm_loadu_si128(src[s..].as_ptr() as *const _) };
             let pi = unsafe { _mm_loadu_si128(src[i..].as_ptr() as *const _) };
             if unsafe { _mm_cmpistrc::<_SIDD_CMP_EQUAL_ORDERED>(ps, pi) } != 0 {
@@ -558,7 +1197,7 @@ impl WhileEqual for X86_64 {
         }
 
         // Fix the last bytes unchecked
-        while s < index && i < src.len() && src[s] == src[i] {
+        while i < src.len() && src[s] == src[i] {
             s += 1;
             i += 1;
         }
@@ -620,6 +1259,21 @@ fn lempel_ziv_calgary_book1_compression_test() {
     assert_eq!(book1, decoded)
 }
 
+#[test]
+fn lempel_ziv_short_sources() {
+    // Une fenêtre plus petite que les littéraux du début, et des sources plus
+    // courtes qu'un mot pour `Faster`.
+    let src = "<Y 1874>\n<A T. HARDY>\n<T Madding Crowd(Penguin 1978)>".as_bytes();
+    for windows_size in 1..8 {
+        let encoded = encode_lempel_ziv_u8(src, windows_size);
+        assert_eq!(decode_lz_u8(&encoded), src);
+    }
+    for len in 0..10 {
+        let src = &[0; 10][..len];
+        assert_eq!(decode_lz_u8(&encode_lz_with_hashmap_u8(src)), src);
+    }
+}
+
 #[test]
 fn while_equal_functions_consistency() {
     use std::fs::File;
@@ -680,6 +1334,14 @@ fn while_equal_consitency_doctest_enhanced() {
     assert_eq!(len1, len2);
     assert_eq!(len1, len3);
 
+    let src = "ABCDEFGHIJKLMNOPQRSTUVWXYZ-ABCDEFGHIJKLMNOPQRSTUVWXYZ+".as_bytes();
+    let len1 = Fast::while_equal(src, 0, 27);
+    let len2 = Original::while_equal(src, 0, 27);
+    let len3 = Faster::while_equal(src, 0, 27);
+    assert_eq!(len1, 26);
+    assert_eq!(len1, len2);
+    assert_eq!(len1, len3);
+
     let src = "AA".as_bytes();
     let len1 = Fast::while_equal(src, 0, 1);
     let len2 = Original::while_equal(src, 0, 1);
@@ -732,7 +1394,9 @@ fn lempel_ziv_optimizations_functions_consistency() {
 
     println!("len {}", src.len());
     println!("{:?} {:?}", &src[216..221], &src[252..257]);
-    println!("{}", unsafe { *(src.as_ptr().add(216) as *const u32) });
+    println!("{}", unsafe {
+        (src.as_ptr().add(216) as *const u32).read_unaligned()
+    });
 
     println!("encode no windows");
     let encoded1 = encode_lz_no_windows_u8_fast(src);
@@ -765,3 +1429,241 @@ fn lempel_ziv_with_dict() {
     println!("{} < {}", encoded.len(), src.len());
     assert_eq!(src, decode_lz_u8(&encoded));
 }
+
+#[test]
+fn overlapping_repetitions() {
+    // Une longue plage d'un même symbole doit tenir en une seule paire
+    // après le préfixe de 4 octets et le symbole qui initie la plage.
+    let src = [b'a'; 1000];
+    let encoded = encode_lz_no_windows_u8(&src);
+    assert_eq!(encoded.len(), 4 + 4);
+    assert_eq!(decode_lz_u8(&encoded), src);
+
+    // Même chose avec un motif de 2 octets.
+    let src = "xyzw".repeat(2) + &"ab".repeat(500);
+    let src = src.as_bytes();
+    let encoded1 = encode_lz_no_windows_u8_faster(src);
+    let encoded2 = encode_lz_with_hashmap_u8(src);
+    let encoded3 = encode_lz_u8_faster(src, 100);
+    assert_eq!(encoded1, encoded2);
+    assert!(encoded1.len() < 20);
+    assert!(encoded3.len() < 20);
+    assert_eq!(decode_lz_u8(&encoded1), src);
+    assert_eq!(decode_lz_u8(&encoded3), src);
+
+    // Une plage plus longue que la taille maximum d'une paire.
+    let src = [b'z'; 100_000];
+    let encoded = encode_lz_with_hashmap_u8(&src);
+    assert!(encoded.len() < 32);
+    assert_eq!(decode_lz_u8(&encoded), src);
+}
+
+#[test]
+fn lempel_ziv_with_prefix_dictionary() {
+    let messages: Vec<String> = (0..200)
+        .map(|i| {
+            format!(
+                "2023-01-{:02} INFO [worker-{}] request handled in {}ms, status=OK",
+                i % 28 + 1,
+                i % 7,
+                i * 13 % 997
+            )
+        })
+        .collect();
+    let samples: Vec<&[u8]> = messages[..150].iter().map(|m| m.as_bytes()).collect();
+    let dict = train_dictionary(&samples, 512);
+    assert!(!dict.is_empty() && dict.len() <= 512);
+
+    let mut total = 0;
+    let mut total_with_dict = 0;
+    for message in messages[150..].iter().map(|m| m.as_bytes()) {
+        let encoded1 = encode_lz_no_windows_u8_with_dict(message, &dict);
+        let encoded2 = encode_lz_with_hashmap_u8_with_dict(message, &dict);
+        let encoded3 = encode_lempel_ziv_u8_with_dict(message, &dict, 16);
+        assert_eq!(encoded1, encoded2);
+        assert_eq!(decode_lz_u8_with_dict(&encoded1, &dict), message);
+        assert_eq!(decode_lz_u8_with_dict(&encoded3, &dict), message);
+        total += encode_lz_with_hashmap_u8(message).len();
+        total_with_dict += encoded1.len();
+    }
+    assert!(total_with_dict * 2 < total);
+}
+
+#[test]
+fn lempel_ziv_with_binary_tree() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+
+    // La plus longue répétition est toujours trouvée, seul l'index peut
+    // changer quand plusieurs répétitions ont la même taille.
+    let src = &book1[..10000];
+    let encoded1 = encode_lz_with_binary_tree_u8(src);
+    let encoded2 = encode_lz_with_hashmap_u8(src);
+    assert_eq!(encoded1.len(), encoded2.len());
+    assert_eq!(decode_lz_u8(&encoded1), src);
+
+    let src = [b'z'; 60_000];
+    let encoded = encode_lz_with_binary_tree_u8(&src);
+    assert!(encoded.len() < 32);
+    assert_eq!(decode_lz_u8(&encoded), src);
+
+    let encoded = encode_lz_with_binary_tree_u8(&[1, 2, 3]);
+    assert_eq!(decode_lz_u8(&encoded), [1, 2, 3]);
+}
+
+#[test]
+fn lempel_ziv_with_config() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+
+    // Des enregistrements courts: des paires de 3 octets.
+    let records: Vec<u8> = (0..500)
+        .flat_map(|i: u32| format!("{:04};ok;{};", i % 37, i % 3).into_bytes())
+        .collect();
+    let short = LzConfig {
+        min_match: 4,
+        max_match: 127,
+        index_bits: 15,
+        ..Default::default()
+    };
+    assert_eq!(short.pair_size(), 3);
+    let encoded1 = internal_encode_lz_with_hashmap_u8::<Faster>(&records, &[], &short);
+    let encoded2 = internal_encode_lz_with_binary_tree_u8(&records, &[], &short);
+    assert_eq!(decode_lz_u8_with_config(&encoded1, &[], &short), records);
+    assert_eq!(decode_lz_u8_with_config(&encoded2, &[], &short), records);
+    assert!(encoded1.len() < encode_lz_with_hashmap_u8(&records).len());
+
+    // Une paire n'est gardée que si elle est plus courte que ses littéraux,
+    // même quand `min_match` est plus petit que la taille d'une paire.
+    let tiny = LzConfig {
+        min_match: 2,
+        window_size: 1000,
+        ..short
+    };
+    let letters: Vec<u8> = (0..5000)
+        .map(|_| b'a' + rand::random::<u8>() % 16)
+        .collect();
+    for encoded in [
+        internal_encode_lz_no_windows_u8::<Fast>(&letters, &[], &tiny),
+        internal_encode_lempel_ziv_u8::<Faster>(&letters, &[], &tiny),
+        internal_encode_lz_with_hashmap_u8::<Faster>(&letters, &[], &tiny),
+        internal_encode_lz_with_binary_tree_u8(&letters, &[], &tiny),
+    ] {
+        assert!(encoded.len() <= letters.len());
+        assert_eq!(decode_lz_u8_with_config(&encoded, &[], &tiny), letters);
+    }
+
+    // De longues répétitions: des paires de 6 octets avec une taille sur 23
+    // bits et un index sur 17 bits.
+    let long = LzConfig {
+        max_match: (1 << 23) - 1,
+        index_bits: 17,
+        ..Default::default()
+    };
+    assert_eq!(long.pair_size(), 6);
+    let src = [&book1[..20000], &book1[..20000], &book1[..100000]].concat();
+    let encoded = internal_encode_lz_with_hashmap_u8::<Faster>(&src, &[], &long);
+    assert_eq!(decode_lz_u8_with_config(&encoded, &[], &long), src);
+
+    // Fenêtre et profondeur de recherche.
+    let src = &book1[..4000];
+    let limited = LzConfig {
+        window_size: 1000,
+        search_depth: 8,
+        literal_prefix: 0,
+        ..Default::default()
+    };
+    for encoded in [
+        internal_encode_lz_no_windows_u8::<Fast>(src, &[], &limited),
+        internal_encode_lempel_ziv_u8::<Faster>(src, &[], &limited),
+        internal_encode_lz_with_hashmap_u8::<Faster>(src, &[], &limited),
+        internal_encode_lz_with_binary_tree_u8(src, &[], &limited),
+    ] {
+        assert!(encoded.len() < src.len());
+        assert!(encoded.len() > encode_lz_no_windows_u8_faster(src).len());
+        assert_eq!(decode_lz_u8_with_config(&encoded, &[], &limited), src);
+    }
+
+    // Les littéraux supérieurs à 0x7f sont échappés.
+    let src: Vec<u8> = (0..5000).map(|_| rand::random::<u8>()).collect();
+    let src = [&src[..], &src[..1000]].concat();
+    let encoded = encode_lz_with_hashmap_u8(&src);
+    assert_eq!(decode_lz_u8(&encoded), src);
+    let encoded = internal_encode_lz_no_windows_u8::<Faster>(&src, &[], &short);
+    assert_eq!(decode_lz_u8_with_config(&encoded, &[], &short), src);
+
+    // Ils sont écrits dans des suites de littéraux, une source sans
+    // répétition ne grandit que d'une paire par suite.
+    let src: Vec<u8> = (0..100_000).map(|_| rand::random::<u8>()).collect();
+    for (encoded, config) in [
+        (encode_lz_with_hashmap_u8(&src), LzConfig::default()),
+        (encode_lz_with_binary_tree_u8(&src), LzConfig::default()),
+        (
+            internal_encode_lz_with_hashmap_u8::<Faster>(&src, &[], &short),
+            short,
+        ),
+    ] {
+        let runs = src.len().div_ceil(config.max_index() - 1);
+        assert!(encoded.len() <= src.len() + runs * config.pair_size());
+        assert_eq!(decode_lz_u8_with_config(&encoded, &[], &config), src);
+    }
+    let config = LzConfig::default();
+    assert_eq!(
+        try_decode_lz_u8_with_config(&[0x80, 0x00, 0x00, 0x03, 0xff, 0xfe], &[], &config, 100),
+        Err(LzError::Truncated)
+    );
+    assert_eq!(
+        try_decode_lz_u8_with_config(&[0x80, 0x00, 0x00, 0x02, 0xff, 0xfe], &[], &config, 1),
+        Err(LzError::TooLarge)
+    );
+}
+
+#[test]
+fn lempel_ziv_with_rep_codes() {
+    // Des enregistrements de taille fixe dont quelques champs changent: la
+    // même distance revient à chaque enregistrement.
+    let records: Vec<u8> = (0..400u32)
+        .flat_map(|i| {
+            format!(
+                "{:05}|sensor-{:02}|temperature={:03}|unit=celsius|status=nominal\n",
+                i,
+                i % 16,
+                (i * 7) % 400
+            )
+            .into_bytes()
+        })
+        .collect();
+    let config = LzConfig::default();
+    let with_reps = LzConfig {
+        rep_codes: true,
+        ..Default::default()
+    };
+    assert_eq!(with_reps.pair_size(), 5);
+
+    let encode = |config: &LzConfig| {
+        let windowed = LzConfig {
+            window_size: 2000,
+            ..*config
+        };
+        [
+            internal_encode_lz_no_windows_u8::<Faster>(&records, &[], config),
+            internal_encode_lempel_ziv_u8::<Faster>(&records, &[], &windowed),
+            internal_encode_lz_with_hashmap_u8::<Faster>(&records, &[], config),
+            internal_encode_lz_with_binary_tree_u8(&records, &[], config),
+        ]
+    };
+    for (encoded1, encoded2) in encode(&config).iter().zip(encode(&with_reps).iter()) {
+        assert_eq!(decode_lz_u8_with_config(encoded2, &[], &with_reps), records);
+        assert!(encoded2.len() < encoded1.len() * 9 / 10);
+    }
+
+    // Des répétitions plus longues que 15 + min_match, et des littéraux
+    // échappés.
+    let mut src: Vec<u8> = (0..300).map(|_| rand::random::<u8>()).collect();
+    for i in 0..50 {
+        src.extend_from_within(i * 3..i * 3 + 100);
+        src.push(rand::random());
+    }
+    let encoded = internal_encode_lz_with_hashmap_u8::<Faster>(&src, &[], &with_reps);
+    assert_eq!(decode_lz_u8_with_config(&encoded, &[], &with_reps), src);
+}
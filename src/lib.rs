@@ -1,11 +1,48 @@
 #![cfg_attr(feature = "portable_simd", feature(portable_simd))]
+// Sans la feature `std`, le coeur du codage entropique (`count`,
+// `normalization`, `spreads`, `t_ans` et `r_ans`) n'utilise que `core` et
+// `alloc`.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod binary_tree;
+pub mod bitstream;
+#[cfg(feature = "std")]
+pub mod block_split;
+#[cfg(feature = "std")]
+pub mod bwt;
+#[cfg(feature = "std")]
+pub mod checksum;
 pub mod count;
+#[cfg(feature = "std")]
+pub mod deflate;
+#[cfg(feature = "std")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod filters;
+#[cfg(feature = "std")]
+pub mod frame;
+#[cfg(feature = "std")]
 pub mod lempel_ziv;
+#[cfg(feature = "std")]
+pub mod lz4;
+#[cfg(feature = "std")]
+pub mod lz_stream;
+#[cfg(feature = "std")]
+pub mod lzw;
 pub mod normalization;
 pub mod r_ans;
+#[cfg(feature = "std")]
+pub mod seekable;
 pub mod spreads;
 pub mod t_ans;
+#[cfg(feature = "std")]
+pub mod zstd;
 
-#[cfg(test)]
+// Les tests d'origine convertissent un `tiny_bitstream::BitEstream` avec
+// `try_into`.
+#[cfg(all(test, feature = "std"))]
+#[allow(clippy::unnecessary_fallible_conversions)]
 mod tests;
@@ -0,0 +1,350 @@
+//! LZ4 block format encoder and decoder.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! The `lempel_ziv` encoders write a bespoke format. This file writes the
+//! LZ4 block format, as described in `lz4_Block_format.md` of the reference
+//! implementation, so the output can be read by any LZ4 decoder (the frame
+//! format is out of the scope of this file).
+//!
+//! A block is a list of sequences. A sequence starts with a token, the high
+//! 4 bits are the number of literals and the low 4 bits the length of the
+//! match minus 4. A value of 15 means that the length continues in the next
+//! bytes (each 255 adds 255, the first byte lower than 255 ends it). Then the
+//! literals are written, followed by the offset of the match on 2 bytes in
+//! little-endian, and the continuation of the match length. The last sequence
+//! contains only literals.
+//!
+//! The match lengths are computed with the `WhileEqual` implementations of
+//! `lempel_ziv`.
+
+use crate::lempel_ziv::{Faster, WhileEqual};
+
+/// Minimum length of a match in the LZ4 format.
+const MIN_MATCH: usize = 4;
+/// The last 5 bytes of a block are always literals.
+const LAST_LITERALS: usize = 5;
+/// A match can't start in the last 12 bytes of a block.
+const MF_LIMIT: usize = 12;
+/// Maximum distance of a match, the offset is written on 16 bits.
+const MAX_DISTANCE: usize = u16::MAX as usize;
+/// Number of bits of the hash used to find the previous occurences of 4
+/// bytes.
+const HASH_LOG: u32 = 16;
+/// Maximum number of previous occurences visited to find a match.
+const MAX_CHAIN: usize = 64;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Lz4Error {
+    /// The block ends in the middle of a sequence.
+    Truncated,
+    /// The offset of a match is 0 or references a byte before the beginning
+    /// of the block.
+    InvalidOffset(usize),
+    /// The decoded block would be larger than the maximum size given to the
+    /// decoder.
+    OutputTooLarge,
+}
+
+#[inline]
+fn hash(src: &[u8], index: usize) -> usize {
+    let key = u32::from_le_bytes([src[index], src[index + 1], src[index + 2], src[index + 3]]);
+    (key.wrapping_mul(2654435761) >> (u32::BITS - HASH_LOG)) as usize
+}
+
+/// Write a length in the LZ4 way after the token: a list of 255 and a last
+/// byte lower than 255.
+#[inline]
+fn write_length(mut len: usize, ret: &mut Vec<u8>) {
+    while len >= 255 {
+        ret.push(255);
+        len -= 255;
+    }
+    ret.push(len as u8);
+}
+
+/// Write a sequence, `literals` are the bytes before the match. A match with
+/// a length of 0 marks the last sequence.
+fn write_sequence(literals: &[u8], offset: usize, match_len: usize, ret: &mut Vec<u8>) {
+    let lit_len = literals.len();
+    let ml_code = match_len.saturating_sub(MIN_MATCH);
+    let token = ((std::cmp::min(lit_len, 15) as u8) << 4) | std::cmp::min(ml_code, 15) as u8;
+    ret.push(token);
+    if lit_len >= 15 {
+        write_length(lit_len - 15, ret);
+    }
+    ret.extend_from_slice(literals);
+    if match_len == 0 {
+        return;
+    }
+    ret.extend_from_slice(&(offset as u16).to_le_bytes());
+    if ml_code >= 15 {
+        write_length(ml_code - 15, ret);
+    }
+}
+
+/// Encode a source into a LZ4 block.
+///
+/// ```
+/// use final_state_rs::lz4::*;
+///
+/// let src = "Lorem ipsum dolor sit amet, Lorem ipsum dolor sit amet.".as_bytes();
+/// let block = encode_lz4_block(src);
+/// assert!(block.len() < src.len());
+/// assert_eq!(decode_lz4_block(&block, src.len()).unwrap(), src);
+/// ```
+pub fn encode_lz4_block(src: &[u8]) -> Vec<u8> {
+    internal_encode_lz4_block::<Faster>(src)
+}
+
+/// Internal implementation of the LZ4 block encoder. The candidates are found
+/// with a hash chain on 4 bytes, the lengths are computed with the given
+/// `WhileEqual` implementation. Before writing a match, we check if the next
+/// position gives a longer one (lazy matching).
+pub fn internal_encode_lz4_block<T: WhileEqual>(src: &[u8]) -> Vec<u8> {
+    let mut ret = vec![];
+    if src.len() < MF_LIMIT + 1 {
+        write_sequence(src, 0, 0, &mut ret);
+        return ret;
+    }
+    let mut finder = HashChain::new(src.len());
+    let match_limit = src.len() - MF_LIMIT;
+
+    let mut anchor = 0;
+    let mut index = 0;
+    while index < match_limit {
+        let mut best = finder.find::<T>(src, index);
+        finder.insert(src, index);
+        if best.0 < MIN_MATCH {
+            index += 1;
+            continue;
+        }
+        while index + 1 < match_limit {
+            let next = finder.find::<T>(src, index + 1);
+            if next.0 <= best.0 + 1 {
+                break;
+            }
+            finder.insert(src, index + 1);
+            best = next;
+            index += 1;
+        }
+        write_sequence(&src[anchor..index], best.1, best.0, &mut ret);
+        for i in index + 1..std::cmp::min(index + best.0, match_limit) {
+            finder.insert(src, i);
+        }
+        index += best.0;
+        anchor = index;
+    }
+    write_sequence(&src[anchor..], 0, 0, &mut ret);
+    ret
+}
+
+/// Hash chain on the 4 first bytes of each position.
+struct HashChain {
+    /// Last position of each hash.
+    head: Vec<usize>,
+    /// Previous position with the same hash.
+    chain: Vec<usize>,
+}
+
+impl HashChain {
+    fn new(len: usize) -> Self {
+        HashChain {
+            head: vec![usize::MAX; 1 << HASH_LOG],
+            chain: vec![usize::MAX; len],
+        }
+    }
+
+    fn insert(&mut self, src: &[u8], index: usize) {
+        let h = hash(src, index);
+        self.chain[index] = self.head[h];
+        self.head[h] = index;
+    }
+
+    /// Return the length and the offset of the longest match at `index`.
+    fn find<T: WhileEqual>(&self, src: &[u8], index: usize) -> (usize, usize) {
+        let end_limit = src.len() - LAST_LITERALS;
+        let mut candidate = self.head[hash(src, index)];
+        let mut best = (0, 0);
+        let mut depth = 0;
+        while candidate != usize::MAX && index - candidate <= MAX_DISTANCE && depth < MAX_CHAIN {
+            if src[candidate..candidate + MIN_MATCH] == src[index..index + MIN_MATCH] {
+                let len = T::while_equal(src, candidate, index) as usize;
+                let len = std::cmp::min(len, end_limit - index);
+                if len > best.0 {
+                    best = (len, index - candidate);
+                }
+            }
+            candidate = self.chain[candidate];
+            depth += 1;
+        }
+        best
+    }
+}
+
+/// Decode a LZ4 block. The LZ4 block doesn't contain the size of the decoded
+/// data, the caller has to give a maximum size. Any malformed block returns an
+/// error, the decoder never reads or writes out of its buffers.
+pub fn decode_lz4_block(src: &[u8], max_size: usize) -> Result<Vec<u8>, Lz4Error> {
+    let mut ret: Vec<u8> = Vec::with_capacity(std::cmp::min(max_size, src.len() * 4));
+    let mut it = src.iter();
+
+    // Read the continuation of a length in the token
+    let read_length = |it: &mut std::slice::Iter<u8>| -> Result<usize, Lz4Error> {
+        let mut len = 0usize;
+        loop {
+            let byte = *it.next().ok_or(Lz4Error::Truncated)?;
+            len = len
+                .checked_add(byte as usize)
+                .ok_or(Lz4Error::OutputTooLarge)?;
+            if byte < 255 {
+                return Ok(len);
+            }
+        }
+    };
+
+    while let Some(&token) = it.next() {
+        let mut lit_len = (token >> 4) as usize;
+        if lit_len == 15 {
+            lit_len += read_length(&mut it)?;
+        }
+        let literals = it.as_slice();
+        if literals.len() < lit_len {
+            return Err(Lz4Error::Truncated);
+        }
+        if ret.len() + lit_len > max_size {
+            return Err(Lz4Error::OutputTooLarge);
+        }
+        ret.extend_from_slice(&literals[..lit_len]);
+        it = literals[lit_len..].iter();
+
+        // The last sequence has no match
+        if it.len() == 0 {
+            return Ok(ret);
+        }
+
+        let offset = match (it.next(), it.next()) {
+            (Some(&lo), Some(&hi)) => u16::from_le_bytes([lo, hi]) as usize,
+            _ => return Err(Lz4Error::Truncated),
+        };
+        if offset == 0 || offset > ret.len() {
+            return Err(Lz4Error::InvalidOffset(offset));
+        }
+        let mut match_len = (token & 15) as usize;
+        if match_len == 15 {
+            match_len += read_length(&mut it)?;
+        }
+        match_len += MIN_MATCH;
+        if ret.len() + match_len > max_size {
+            return Err(Lz4Error::OutputTooLarge);
+        }
+        // The match may overlap the bytes it produces.
+        let start = ret.len() - offset;
+        for i in start..start + match_len {
+            ret.push(ret[i]);
+        }
+    }
+    // A block can't be empty or end with a match.
+    Err(Lz4Error::Truncated)
+}
+
+/// The blocks in `rsc/lz4` were produced by the reference `lz4` command line
+/// (v1.9.4) in fast, high compression, and on a file full of overlapping
+/// repetitions.
+#[test]
+fn lz4_reference_blocks() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+    let book1 = &book1[..20000];
+    let records = read_rsc("./rsc/lz4/records");
+
+    for (block, expected) in [
+        ("./rsc/lz4/book1_20k.block", book1),
+        ("./rsc/lz4/book1_20k_hc.block", book1),
+        ("./rsc/lz4/records.block", &records),
+    ] {
+        let block = read_rsc(block);
+        let decoded = decode_lz4_block(&block, expected.len()).unwrap();
+        assert_eq!(decoded, expected);
+
+        // Round trip with our encoder
+        let encoded = encode_lz4_block(expected);
+        assert_eq!(
+            decode_lz4_block(&encoded, expected.len()).unwrap(),
+            expected
+        );
+        // We're not that far from the reference implementation.
+        assert!(encoded.len() < block.len() * 5 / 4);
+    }
+}
+
+#[test]
+fn lz4_block_limits() {
+    // Sous `MF_LIMIT + 1` octets, le bloc n'a qu'une séquence de littéraux.
+    for len in [0, 1, MF_LIMIT] {
+        let src = vec![7; len];
+        let encoded = encode_lz4_block(&src);
+        assert_eq!(encoded, [&[(len as u8) << 4][..], &src].concat());
+        assert_eq!(decode_lz4_block(&encoded, len).unwrap(), src);
+    }
+
+    // Un seul octet répété : un littéral, puis un match à l'offset 1 qui
+    // chevauche sa sortie et s'arrête avant les 5 derniers octets.
+    let src = vec![0; 100_000];
+    let encoded = encode_lz4_block(&src);
+    assert_eq!(encoded[..4], [0x1f, 0, 1, 0]);
+    let ml_code = src.len() - 1 - LAST_LITERALS - MIN_MATCH;
+    assert_eq!(
+        encoded[4..4 + (ml_code - 15) / 255],
+        vec![255; (ml_code - 15) / 255]
+    );
+    assert_eq!(encoded[encoded.len() - 6..], [0x50, 0, 0, 0, 0, 0]);
+    assert_eq!(decode_lz4_block(&encoded, src.len()).unwrap(), src);
+
+    // 300 littéraux, la longueur continue après le token (15 + 255 + 30).
+    let src = (0..150u16)
+        .flat_map(|v| v.to_be_bytes())
+        .collect::<Vec<u8>>();
+    let encoded = encode_lz4_block(&src);
+    assert_eq!(encoded[..3], [0xf0, 255, 30]);
+    assert_eq!(encoded[3..], src);
+    assert_eq!(decode_lz4_block(&encoded, src.len()).unwrap(), src);
+}
+
+#[test]
+fn lz4_invalid_blocks() {
+    let encoded = encode_lz4_block(&[0; 100]);
+    assert_eq!(
+        decode_lz4_block(&encoded, 99),
+        Err(Lz4Error::OutputTooLarge)
+    );
+    assert_eq!(
+        decode_lz4_block(&encoded[..encoded.len() - 1], 100),
+        Err(Lz4Error::Truncated)
+    );
+    assert_eq!(decode_lz4_block(&[], 100), Err(Lz4Error::Truncated));
+    // Un offset nul, ou avant le début du bloc.
+    assert_eq!(
+        decode_lz4_block(&[0x10, b'a', 0, 0], 100),
+        Err(Lz4Error::InvalidOffset(0))
+    );
+    assert_eq!(
+        decode_lz4_block(&[0x10, b'a', 2, 0], 100),
+        Err(Lz4Error::InvalidOffset(2))
+    );
+    // La longueur des littéraux s'arrête au milieu de sa continuation.
+    assert_eq!(
+        decode_lz4_block(&[0xf0, 255], 1000),
+        Err(Lz4Error::Truncated)
+    );
+    // Le bloc se termine par un match au lieu de littéraux.
+    assert_eq!(
+        decode_lz4_block(&[0x10, b'a', 1, 0], 100),
+        Err(Lz4Error::Truncated)
+    );
+}
@@ -0,0 +1,359 @@
+//! Streaming variation of the lempel_ziv encoder with a sliding window.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! The encoders in `lempel_ziv` need the whole source in memory because the
+//! pairs they write contain an absolute index in the source. Here the pairs
+//! contain a distance from the current position, so the encoder and the
+//! decoder only have to remember the last `window_size` bytes. They are kept
+//! in a ring buffer, the input is accepted chunk by chunk and the tokens are
+//! flushed as soon as they are known.
+//!
+//! Tokens format:
+//! - `0b0xxxxxxx`: a literal lower than 128.
+//! - `[0x80, 0x00, literal]`: a literal greater or equal to 128, written as a
+//!   pair of length 0 followed by the literal itself.
+//! - `[0x80 | len >> 8, len, distance >> 8, distance]`: a copy of `len` bytes
+//!   starting `distance` bytes before the current position. The copy may
+//!   overlap the bytes it produces.
+
+/// Minimum length of a repetition, under that length the pair is more
+/// expensive than the literals.
+const MIN_MATCH: usize = 5;
+/// Maximum length of a repetition, the length is written on 15 bits.
+const MAX_MATCH: usize = 32767;
+/// Maximum size of the window, the distance is written on 16 bits.
+pub const MAX_WINDOW_SIZE: usize = u16::MAX as usize;
+/// Number of bits of the hash used to find the previous occurences of 4
+/// bytes.
+const HASH_LOG: u32 = 15;
+/// Maximum number of previous occurences visited to find a repetition.
+const MAX_CHAIN: usize = 64;
+
+const FLAG_BIT: u8 = 1 << 7;
+const FLAG_MASK: u8 = FLAG_BIT - 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LzStreamError {
+    /// A pair references a byte older than the window or than the start of
+    /// the stream.
+    InvalidDistance(usize),
+    /// The stream ends in the middle of a token.
+    Truncated,
+}
+
+#[inline]
+fn hash(bytes: &[u8]) -> usize {
+    let key = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (key.wrapping_mul(2654435761) >> (u32::BITS - HASH_LOG)) as usize
+}
+
+/// Incremental lempel_ziv encoder. Only the last `window_size` bytes of the
+/// source are kept in memory, plus the few bytes of a chunk that could still
+/// start a repetition with the next chunk.
+///
+/// ```
+/// use final_state_rs::lz_stream::*;
+///
+/// let logs = "GET /index.html 200\nGET /index.html 304\nGET /style.css 200\n";
+/// let mut encoder = LzStreamEncoder::new(1024);
+/// let mut encoded = vec![];
+/// for line in logs.split_inclusive('\n') {
+///     encoded.append(&mut encoder.write(line.as_bytes()));
+/// }
+/// encoded.append(&mut encoder.finish());
+/// assert!(encoded.len() < logs.len());
+///
+/// let mut decoder = LzStreamDecoder::new(1024);
+/// let mut decoded = decoder.write(&encoded).unwrap();
+/// decoded.append(&mut decoder.finish().unwrap());
+/// assert_eq!(decoded, logs.as_bytes());
+/// ```
+pub struct LzStreamEncoder {
+    window_size: usize,
+    /// Last `window_size` bytes already encoded. The byte at the absolute
+    /// position `p` is stored in `ring[p % window_size]`.
+    ring: Vec<u8>,
+    /// Absolute position of the first byte in `pending`.
+    position: usize,
+    /// Bytes received but not yet encoded.
+    pending: Vec<u8>,
+    /// Last absolute position + 1 of each hash, 0 if none.
+    head: Vec<usize>,
+    /// Previous absolute position + 1 with the same hash, indexed like the
+    /// ring buffer.
+    chain: Vec<usize>,
+}
+
+impl LzStreamEncoder {
+    /// Create a new encoder, the window size has to be in
+    /// `[1..MAX_WINDOW_SIZE]`.
+    pub fn new(window_size: usize) -> Self {
+        assert!(
+            (1..=MAX_WINDOW_SIZE).contains(&window_size),
+            "The window size has to be in [1..{MAX_WINDOW_SIZE}]"
+        );
+        LzStreamEncoder {
+            window_size,
+            ring: vec![0; window_size],
+            position: 0,
+            pending: vec![],
+            head: vec![0; 1 << HASH_LOG],
+            chain: vec![0; window_size],
+        }
+    }
+
+    /// Encode a new chunk of the source and return the tokens ready to be
+    /// written. The last bytes of the chunk may be kept until the next call
+    /// because they could be the beginning of a repetition.
+    pub fn write(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(chunk);
+        self.encode_pending(false)
+    }
+
+    /// Encode the bytes still pending, the encoder can be used again after
+    /// that call as if the stream was continuing.
+    pub fn finish(&mut self) -> Vec<u8> {
+        self.encode_pending(true)
+    }
+
+    fn encode_pending(&mut self, flush: bool) -> Vec<u8> {
+        let mut ret = vec![];
+        let mut k = 0;
+        while k < self.pending.len() && (flush || self.pending.len() - k >= MIN_MATCH) {
+            let (len, distance) = self.find_repetition(k);
+            if len >= MIN_MATCH {
+                let bits: u32 = (((len as u32) | (1 << 15)) << 16) + distance as u32;
+                ret.extend_from_slice(&bits.to_be_bytes());
+                for i in k..k + len {
+                    self.insert(i);
+                }
+                k += len;
+            } else {
+                let literal = self.pending[k];
+                if literal >= FLAG_BIT {
+                    ret.extend_from_slice(&[FLAG_BIT, 0, literal]);
+                } else {
+                    ret.push(literal);
+                }
+                self.insert(k);
+                k += 1;
+            }
+        }
+        self.pending.drain(..k);
+        self.position += k;
+        ret
+    }
+
+    /// Byte at the given absolute position, either in the ring buffer or in
+    /// the pending bytes.
+    #[inline]
+    fn byte_at(&self, position: usize) -> u8 {
+        if position >= self.position {
+            self.pending[position - self.position]
+        } else {
+            self.ring[position % self.window_size]
+        }
+    }
+
+    /// Search the longest repetition of the bytes starting at `pending[k]`.
+    /// Return the length and the distance of the repetition, the length is 0
+    /// if nothing was found.
+    fn find_repetition(&self, k: usize) -> (usize, usize) {
+        let available = self.pending.len() - k;
+        if available < MIN_MATCH {
+            return (0, 0);
+        }
+        let max_len = std::cmp::min(available, MAX_MATCH);
+        let current = self.position + k;
+        let lowest = current.saturating_sub(self.window_size);
+        let mut best = (0, 0);
+        let mut candidate = self.head[hash(&self.pending[k..])];
+        let mut depth = 0;
+        while candidate > 0 && depth < MAX_CHAIN {
+            let from = candidate - 1;
+            if from < lowest || from >= current {
+                break;
+            }
+            let mut len = 0;
+            while len < max_len && self.byte_at(from + len) == self.pending[k + len] {
+                len += 1;
+            }
+            if len > best.0 {
+                best = (len, current - from);
+                if len == max_len {
+                    break;
+                }
+            }
+            candidate = self.chain[from % self.window_size];
+            depth += 1;
+        }
+        best
+    }
+
+    /// Push `pending[k]` in the ring buffer and register its position in the
+    /// hash chains if we know the 3 following bytes.
+    fn insert(&mut self, k: usize) {
+        let position = self.position + k;
+        let slot = position % self.window_size;
+        self.ring[slot] = self.pending[k];
+        if k + 4 <= self.pending.len() {
+            let h = hash(&self.pending[k..]);
+            self.chain[slot] = self.head[h];
+            self.head[h] = position + 1;
+        } else {
+            // Nothing can be chained from here, remove the stale link.
+            self.chain[slot] = 0;
+        }
+    }
+}
+
+/// Incremental decoder of the tokens produced by `LzStreamEncoder`. The
+/// window size has to be the same than the one used to encode. Tokens may be
+/// split between two chunks.
+pub struct LzStreamDecoder {
+    window_size: usize,
+    ring: Vec<u8>,
+    /// Number of bytes decoded since the beginning of the stream.
+    position: usize,
+    /// Beginning of a token received in a previous chunk.
+    pending: Vec<u8>,
+}
+
+impl LzStreamDecoder {
+    pub fn new(window_size: usize) -> Self {
+        assert!(
+            (1..=MAX_WINDOW_SIZE).contains(&window_size),
+            "The window size has to be in [1..{MAX_WINDOW_SIZE}]"
+        );
+        LzStreamDecoder {
+            window_size,
+            ring: vec![0; window_size],
+            position: 0,
+            pending: vec![],
+        }
+    }
+
+    /// Decode a chunk of tokens and return the decoded bytes.
+    pub fn write(&mut self, chunk: &[u8]) -> Result<Vec<u8>, LzStreamError> {
+        self.pending.extend_from_slice(chunk);
+        let mut ret = vec![];
+        let mut k = 0;
+        while k < self.pending.len() {
+            let symbol = self.pending[k];
+            if symbol < FLAG_BIT {
+                self.push(symbol, &mut ret);
+                k += 1;
+                continue;
+            }
+            if k + 1 >= self.pending.len() {
+                break;
+            }
+            let len = (((symbol & FLAG_MASK) as usize) << 8) + self.pending[k + 1] as usize;
+            if len == 0 {
+                // Literal greater than 127
+                if k + 2 >= self.pending.len() {
+                    break;
+                }
+                self.push(self.pending[k + 2], &mut ret);
+                k += 3;
+                continue;
+            }
+            if k + 3 >= self.pending.len() {
+                break;
+            }
+            let distance = ((self.pending[k + 2] as usize) << 8) + self.pending[k + 3] as usize;
+            if distance == 0 || distance > self.window_size || distance > self.position {
+                return Err(LzStreamError::InvalidDistance(distance));
+            }
+            for _ in 0..len {
+                let byte = self.ring[(self.position - distance) % self.window_size];
+                self.push(byte, &mut ret);
+            }
+            k += 4;
+        }
+        self.pending.drain(..k);
+        Ok(ret)
+    }
+
+    /// Check that the stream doesn't end in the middle of a token.
+    pub fn finish(&mut self) -> Result<Vec<u8>, LzStreamError> {
+        if self.pending.is_empty() {
+            Ok(vec![])
+        } else {
+            Err(LzStreamError::Truncated)
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, byte: u8, ret: &mut Vec<u8>) {
+        self.ring[self.position % self.window_size] = byte;
+        self.position += 1;
+        ret.push(byte);
+    }
+}
+
+#[test]
+fn lz_stream_calgary_book1_chunks() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+    let book1 = &book1[..200_000];
+
+    let mut encoder = LzStreamEncoder::new(4096);
+    let mut encoded = vec![];
+    for chunk in book1.chunks(1000) {
+        encoded.append(&mut encoder.write(chunk));
+    }
+    encoded.append(&mut encoder.finish());
+    assert!(encoded.len() < book1.len());
+
+    // The decoder doesn't care about the way the tokens are splitted.
+    let mut decoder = LzStreamDecoder::new(4096);
+    let mut decoded = vec![];
+    for chunk in encoded.chunks(7) {
+        decoded.append(&mut decoder.write(chunk).unwrap());
+    }
+    decoded.append(&mut decoder.finish().unwrap());
+    assert_eq!(decoded, book1);
+
+    // The result doesn't depend on the size of the chunks given to the
+    // encoder, except for the last bytes of each chunk.
+    let mut encoder = LzStreamEncoder::new(4096);
+    let mut encoded_once = encoder.write(book1);
+    encoded_once.append(&mut encoder.finish());
+    assert!(encoded_once.len() <= encoded.len());
+}
+
+#[test]
+fn lz_stream_binary_and_overlaps() {
+    let mut src: Vec<u8> = (0..=255).collect();
+    src.extend_from_slice(&[0xAA; 3000]);
+    src.extend((0..2000).map(|_| rand::random::<u8>()));
+    src.extend_from_slice(&[0xFF, 0x00].repeat(100));
+
+    let mut encoder = LzStreamEncoder::new(256);
+    let mut encoded = vec![];
+    for chunk in src.chunks(333) {
+        encoded.append(&mut encoder.write(chunk));
+    }
+    encoded.append(&mut encoder.finish());
+
+    let mut decoder = LzStreamDecoder::new(256);
+    let mut decoded = decoder.write(&encoded).unwrap();
+    decoded.append(&mut decoder.finish().unwrap());
+    assert_eq!(decoded, src);
+
+    let mut decoder = LzStreamDecoder::new(256);
+    assert_eq!(
+        decoder.write(&[b'a', 0x80, 5, 0, 2]),
+        Err(LzStreamError::InvalidDistance(2))
+    );
+    let mut decoder = LzStreamDecoder::new(256);
+    decoder.write(&[b'a', 0x80, 5]).unwrap();
+    assert_eq!(decoder.finish(), Err(LzStreamError::Truncated));
+}
@@ -0,0 +1,274 @@
+//! LZW, the dictionary variation of lz78, with variable code width and
+//! dictionary reset.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! Documentation: doc/[language]/lempel_ziv.md
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! Contrary to the encoders of `lempel_ziv`, which reference a previous
+//! sequence with an index and a length, LZW builds a dictionary of sequences
+//! while it reads the source. Each code written in the output is the index of
+//! a sequence in the dictionary, and every code adds a new sequence: the
+//! previous one followed by the first symbol of the next one. The decoder
+//! builds the same dictionary, one code later.
+//!
+//! The codes 0 to 255 are the symbols, 256 clears the dictionary and 257 ends
+//! the stream. The width of the codes starts at 9 bits and grows with the
+//! dictionary until `max_bits`. When the dictionary is full, the encoder
+//! writes a `CLEAR_CODE` and starts again with an empty dictionary, so it can
+//! adapt to a new part of the source.
+//!
+//! The codes are written with `bitstream`, which is a LIFO stream: the
+//! encoder keeps the codes and writes them from the last to the first, so the
+//! decoder reads them in the order they were produced.
+
+use std::collections::HashMap;
+
+use crate::bitstream::{BitDstream, BitEstream};
+
+/// Code that clears the dictionary.
+const CLEAR_CODE: usize = 256;
+/// Code that marks the end of the stream.
+const END_CODE: usize = 257;
+/// First code of a sequence of the dictionary.
+const FIRST_CODE: usize = 258;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LzwError {
+    /// The stream ends before the `END_CODE`.
+    Truncated,
+    /// A code isn't in the dictionary yet.
+    InvalidCode(usize),
+    /// The decoded stream would be greater than the maximum size.
+    TooLarge,
+}
+
+/// Width of the `nth` code written since the last reset. At that time the
+/// dictionary contains `n` sequences, the greatest code that can be written
+/// is `FIRST_CODE + n - 1`.
+#[inline]
+fn code_width(n: usize) -> u8 {
+    std::cmp::max(9, usize::BITS - (FIRST_CODE - 1 + n).leading_zeros()) as u8
+}
+
+/// Encode a source with LZW, the codes are at most `max_bits` wide (between 9
+/// and 16).
+///
+/// ```
+/// use final_state_rs::lzw::*;
+///
+/// let src = "TOBEORNOTTOBEORTOBEORNOT".as_bytes();
+/// let encoded = encode_lzw(src, 12);
+/// assert!(encoded.len() < src.len());
+/// assert_eq!(decode_lzw(&encoded, 12).unwrap(), src);
+/// ```
+pub fn encode_lzw(src: &[u8], max_bits: u8) -> Vec<u8> {
+    assert!((9..=16).contains(&max_bits), "max_bits out of [9; 16]");
+    let max_codes = 1 << max_bits;
+
+    let mut codes: Vec<(usize, u8)> = vec![];
+    let mut dict = HashMap::<(usize, u8), usize>::default();
+    let mut next_code = FIRST_CODE;
+
+    let mut it = src.iter();
+    if let Some(first) = it.next() {
+        // Code de la séquence courante, la plus longue séquence du
+        // dictionnaire qui préfixe la suite de la source.
+        let mut current = *first as usize;
+        for symbol in it {
+            if let Some(code) = dict.get(&(current, *symbol)) {
+                current = *code;
+                continue;
+            }
+            codes.push((current, code_width(next_code - FIRST_CODE)));
+            dict.insert((current, *symbol), next_code);
+            next_code += 1;
+            if next_code == max_codes {
+                codes.push((CLEAR_CODE, code_width(next_code - FIRST_CODE)));
+                dict.clear();
+                next_code = FIRST_CODE;
+            }
+            current = *symbol as usize;
+        }
+        codes.push((current, code_width(next_code - FIRST_CODE)));
+        // Le décodeur ajoute une dernière séquence avant de lire la fin.
+        next_code += 1;
+    }
+    codes.push((END_CODE, code_width(next_code - FIRST_CODE)));
+
+    let mut estream = BitEstream::new();
+    for (code, width) in codes.iter().rev() {
+        estream.write(*code, *width);
+    }
+    estream.into()
+}
+
+/// Decode a stream produced by `encode_lzw` with the same `max_bits`.
+pub fn decode_lzw(src: &[u8], max_bits: u8) -> Result<Vec<u8>, LzwError> {
+    decode_lzw_with_max_size(src, max_bits, usize::MAX)
+}
+
+/// Decode a stream produced by `encode_lzw`, fails with `LzwError::TooLarge`
+/// if the decoded stream is greater than `max_size`. A code of 16 bits can
+/// stand for thousands of bytes, a stream from an untrusted source should be
+/// decoded with a bound.
+///
+/// ```
+/// use final_state_rs::lzw::*;
+///
+/// let encoded = encode_lzw(&[7; 1000], 12);
+/// assert_eq!(decode_lzw_with_max_size(&encoded, 12, 1000).unwrap(), [7; 1000]);
+/// assert_eq!(
+///     decode_lzw_with_max_size(&encoded, 12, 999),
+///     Err(LzwError::TooLarge)
+/// );
+/// ```
+pub fn decode_lzw_with_max_size(
+    src: &[u8],
+    max_bits: u8,
+    max_size: usize,
+) -> Result<Vec<u8>, LzwError> {
+    assert!((9..=16).contains(&max_bits), "max_bits out of [9; 16]");
+    let max_codes = 1 << max_bits;
+    let mut dstream: BitDstream = src.to_vec().try_into().map_err(|_| LzwError::Truncated)?;
+
+    let mut ret = vec![];
+    // Une séquence du dictionnaire a toujours déjà été écrite dans la
+    // sortie, on garde seulement sa position et sa taille.
+    let mut entries: Vec<(usize, usize)> = vec![];
+    let mut previous: Option<(usize, usize)> = None;
+    loop {
+        let width = code_width(entries.len() + previous.is_some() as usize);
+        let code: usize = dstream.read(width).map_err(|_| LzwError::Truncated)?;
+        let current = match code {
+            CLEAR_CODE => {
+                entries.clear();
+                previous = None;
+                continue;
+            }
+            END_CODE => return Ok(ret),
+            symbol if symbol < CLEAR_CODE => {
+                if ret.len() == max_size {
+                    return Err(LzwError::TooLarge);
+                }
+                ret.push(symbol as u8);
+                (ret.len() - 1, 1)
+            }
+            code => {
+                // Le code peut être celui de la séquence que l'on est en
+                // train de construire (la précédente suivie de son premier
+                // symbole), la copie se fait octet par octet.
+                let (start, len) = match (entries.get(code - FIRST_CODE), previous) {
+                    (Some(entry), _) => *entry,
+                    (None, Some((start, len))) if code - FIRST_CODE == entries.len() => {
+                        (start, len + 1)
+                    }
+                    _ => return Err(LzwError::InvalidCode(code)),
+                };
+                if ret.len() + len > max_size {
+                    return Err(LzwError::TooLarge);
+                }
+                let index = ret.len();
+                for i in start..start + len {
+                    ret.push(ret[i]);
+                }
+                (index, len)
+            }
+        };
+        if let Some((start, len)) = previous {
+            if FIRST_CODE + entries.len() < max_codes {
+                entries.push((start, len + 1));
+            }
+        }
+        previous = Some(current);
+    }
+}
+
+#[test]
+fn lzw_calgary_book1() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+
+    // Avec 9 bits, le dictionnaire est vidé très souvent.
+    let mut sizes = vec![];
+    for max_bits in [9, 12, 16] {
+        let encoded = encode_lzw(&book1, max_bits);
+        assert_eq!(decode_lzw(&encoded, max_bits).unwrap(), book1);
+        sizes.push(encoded.len());
+    }
+    assert!(sizes[0] > sizes[1] && sizes[1] > sizes[2]);
+    // `compress` atteint ~43% sur book1.
+    assert!(sizes[2] < book1.len() / 2);
+}
+
+#[test]
+fn lzw_code_of_the_sequence_being_built() {
+    // "aaa" donne le code 97 puis 258, la séquence "aa" que le décodeur
+    // n'a pas encore ajoutée quand il lit son code.
+    let encoded = encode_lzw(b"aaa", 12);
+    let mut dstream = BitDstream::try_from(encoded.clone()).unwrap();
+    assert_eq!(dstream.remaining(), 27);
+    assert_eq!(dstream.read(9), Ok(b'a' as usize));
+    assert_eq!(dstream.read(9), Ok(FIRST_CODE));
+    assert_eq!(dstream.read(9), Ok(END_CODE));
+    assert_eq!(decode_lzw(&encoded, 12).unwrap(), b"aaa");
+    assert_eq!(decode_lzw_with_max_size(&encoded, 12, 3).unwrap(), b"aaa");
+    assert_eq!(
+        decode_lzw_with_max_size(&encoded, 12, 2),
+        Err(LzwError::TooLarge)
+    );
+    assert_eq!(
+        decode_lzw_with_max_size(&encoded, 12, 0),
+        Err(LzwError::TooLarge)
+    );
+
+    for len in 0..100 {
+        let src = vec![b'a'; len];
+        assert_eq!(decode_lzw(&encode_lzw(&src, 9), 9).unwrap(), src);
+    }
+}
+
+#[test]
+fn lzw_dictionary_reset() {
+    // Avec 9 bits, le dictionnaire contient 254 séquences avant d'être vidé.
+    // Presque chaque paire d'une source aléatoire est nouvelle, un code par
+    // octet, le reset arrive vers le 255e octet.
+    let random = (0..2000).map(|_| rand::random::<u8>()).collect::<Vec<u8>>();
+    for len in 250..270 {
+        let src = &random[..len];
+        assert_eq!(decode_lzw(&encode_lzw(src, 9), 9).unwrap(), src);
+    }
+    // Avec n séquences, le plus grand code est `FIRST_CODE + n - 1` : les
+    // codes passent à 10 bits quand 512 peut être écrit.
+    assert_eq!(code_width(511 - FIRST_CODE + 1), 9);
+    assert_eq!(code_width(512 - FIRST_CODE + 1), 10);
+
+    // Un flux fait d'un reset et de la fin ne contient rien.
+    let mut estream = BitEstream::new();
+    estream.write(END_CODE, 9);
+    estream.write(CLEAR_CODE, 9);
+    assert_eq!(decode_lzw(&Vec::from(estream), 12).unwrap(), []);
+}
+
+#[test]
+fn lzw_invalid_streams() {
+    // Un code qui n'est pas encore dans le dictionnaire.
+    let mut estream = BitEstream::new();
+    estream.write(300, 9);
+    assert_eq!(
+        decode_lzw(&Vec::from(estream), 12),
+        Err(LzwError::InvalidCode(300))
+    );
+    // Pas de code de fin.
+    let mut estream = BitEstream::new();
+    estream.write(b'a' as usize, 9);
+    assert_eq!(
+        decode_lzw(&Vec::from(estream), 12),
+        Err(LzwError::Truncated)
+    );
+    assert_eq!(decode_lzw(&[], 12), Err(LzwError::Truncated));
+}
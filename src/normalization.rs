@@ -6,7 +6,7 @@
 //! Author: Adrien Zinger, avec l'inspiration du travail de Jarek Duda,
 //!         Yann Collet, Charles Bloom et bien d'autres.
 
-use std::collections::BinaryHeap;
+use alloc::{boxed::Box, vec, vec::Vec};
 
 #[derive(Debug)]
 pub enum NormError {
@@ -27,7 +27,7 @@ pub fn fast_normalization_1(
     table_log: usize,
 ) -> Result<Vec<usize>, Box<NormError>> {
     let mut norm = vec![0usize; hist.len()];
-    let len = hist.len();
+    let len = hist.iter().sum::<usize>();
 
     const HIGH_NUM: usize = (usize::BITS - 2) as usize;
 
@@ -37,7 +37,10 @@ pub fn fast_normalization_1(
     // On cherche un nombre suffisement grand, mais pas trop pour éviter les
     // difficulté de multiplications.
     let scale: usize = HIGH_NUM - table_log;
-    let step: usize = (1usize << HIGH_NUM) / hist.iter().sum::<usize>();
+    // Un histogramme vide ne se normalise pas.
+    let step: usize = (1usize << HIGH_NUM)
+        .checked_div(len)
+        .ok_or(NormError::NormalizationError)?;
     let mut max = 0;
     let mut max_norm = &mut 0;
     let mut still_to_distribute: isize = 1 << table_log;
@@ -75,7 +78,7 @@ pub fn fast_normalization_1(
     if -still_to_distribute >= (max >> 1) as isize {
         return Err(Box::new(NormError::NormalizationError));
     }
-    *max_norm += still_to_distribute as usize;
+    *max_norm = (*max_norm as isize + still_to_distribute) as usize;
     Ok(norm)
 }
 
@@ -85,12 +88,14 @@ pub fn normalization_with_fast_compensation(
     table_log: usize,
 ) -> Result<Vec<usize>, Box<NormError>> {
     let mut norm = vec![0usize; hist.len()];
-    let len = hist.len();
+    let len = hist.iter().sum::<usize>();
 
     const HIGH_NUM: usize = (usize::BITS - 2) as usize;
 
     let scale: usize = HIGH_NUM - table_log;
-    let step: usize = (1usize << HIGH_NUM) / hist.iter().sum::<usize>();
+    let step: usize = (1usize << HIGH_NUM)
+        .checked_div(len)
+        .ok_or(NormError::NormalizationError)?;
     let mut max = 0;
     let mut max_norm = &mut 0;
     let mut total: usize = 0;
@@ -100,7 +105,7 @@ pub fn normalization_with_fast_compensation(
                 "An rle compression should be more accurate",
             )));
         } else if s > 0 {
-            let proba = std::cmp::max(
+            let proba = core::cmp::max(
                 1,
                 s.checked_mul(step)
                     .ok_or(NormError::MultiplicationOverflow)?
@@ -152,16 +157,19 @@ pub fn normalization_with_compensation_binary_heap(
     table_log: usize,
     max_symbol: usize,
 ) -> Result<Vec<usize>, Box<NormError>> {
-    use std::cmp::max;
+    use alloc::collections::BinaryHeap;
+    use core::cmp::{max, Ordering};
     use NormError::MultiplicationOverflow as Overflow;
 
     let mut normalized = vec![0usize; max_symbol + 1];
-    let len = histogram.len();
+    let len = histogram.iter().sum::<usize>();
 
     const HIGH_NUM: usize = (usize::BITS - 2) as usize;
 
     let scale: usize = HIGH_NUM - table_log;
-    let step: usize = (1usize << HIGH_NUM) / histogram.iter().sum::<usize>();
+    let step: usize = (1usize << HIGH_NUM)
+        .checked_div(len)
+        .ok_or(NormError::NormalizationError)?;
     let mut total: usize = 0;
 
     for (index, &count) in histogram.iter().enumerate().take(max_symbol + 1) {
@@ -189,21 +197,17 @@ pub fn normalization_with_compensation_binary_heap(
     }
 
     impl Ord for SortedProba {
-        fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        fn cmp(&self, other: &Self) -> Ordering {
             match self.change > other.change {
-                true => std::cmp::Ordering::Greater,
-                false => std::cmp::Ordering::Less,
+                true => Ordering::Greater,
+                false => Ordering::Less,
             }
         }
     }
 
     impl PartialOrd for SortedProba {
-        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-            match self.index.partial_cmp(&other.index) {
-                Some(core::cmp::Ordering::Equal) => {}
-                ord => return ord,
-            }
-            self.change.partial_cmp(&other.change)
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
         }
     }
 
@@ -220,8 +224,9 @@ pub fn normalization_with_compensation_binary_heap(
         } else {
             normalized[index] - 1
         };
-        let change =
-            ((normalized[index] as f32) / normalized_plus as f32).log2() * histogram[index] as f32;
+        // `libm` car `f32::log2` a besoin de `std`.
+        let change = libm::log2f(normalized[index] as f32 / normalized_plus as f32)
+            * histogram[index] as f32;
         sorted_probas.push(SortedProba { change, index });
     }
 
@@ -240,7 +245,7 @@ pub fn normalization_with_compensation_binary_heap(
             } else {
                 normalized[proba.index] - 1
             };
-            proba.change = ((normalized[proba.index] as f32) / normalized_plus as f32).log2()
+            proba.change = libm::log2f(normalized[proba.index] as f32 / normalized_plus as f32)
                 * histogram[proba.index] as f32;
             sorted_probas.push(proba);
         }
@@ -269,13 +274,13 @@ pub fn slow_normalization(hist: &[usize], table_log: usize) -> Result<Vec<usize>
                 max_norm = n;
                 max = proba;
             }
-            still_to_distribute -= proba as isize;
+            still_to_distribute -= proba;
         }
     }
-    if -still_to_distribute >= (max >> 1) as isize {
+    if -still_to_distribute >= (max >> 1) {
         return Err(Box::new(NormError::NormalizationError));
     }
-    *max_norm += still_to_distribute as usize;
+    *max_norm = (*max_norm as isize + still_to_distribute) as usize;
     Ok(norm)
 }
 
@@ -284,28 +289,31 @@ pub fn zstd_normalization_1_inplace(
     table_log: usize,
     max_symbol: usize,
 ) -> Result<(), Box<NormError>> {
-    let len = hist.len();
     const HIGH_NUM: usize = (usize::BITS - 2) as usize;
 
     let scale: usize = HIGH_NUM - table_log;
     let total = hist.iter().sum::<usize>();
-    let step: usize = (1usize << HIGH_NUM) / total;
+    let step: usize = (1usize << HIGH_NUM)
+        .checked_div(total)
+        .ok_or(NormError::NormalizationError)?;
     const RTB_TABLE: [usize; 8] = [0, 473195, 504333, 520860, 550000, 700000, 750000, 830000];
     let v_step = 1 << (scale - 20);
     let mut max = 0;
     let mut max_norm = &mut 0;
     let mut still_to_distribute: isize = 1 << table_log;
     let low_threshold = total >> table_log;
-    for s in hist.iter_mut().take(max_symbol) {
-        if *s <= low_threshold {
-            *s = 1;
-            still_to_distribute -= 1;
-        } else if *s == len {
+    for s in hist.iter_mut().take(max_symbol + 1) {
+        if *s == total {
             return Err(Box::new(NormError::RunLengthEncoding(
                 "An rle compression should be more accurate",
             )));
-        } else if *s > 0 {
-            let mut proba = std::cmp::max(1, ((*s) * step) >> scale);
+        } else if *s == 0 {
+            continue;
+        } else if *s <= low_threshold {
+            *s = 1;
+            still_to_distribute -= 1;
+        } else {
+            let mut proba = core::cmp::max(1, ((*s) * step) >> scale);
             if proba < 8 && (*s) * step - (proba << scale) > v_step * RTB_TABLE[proba] {
                 proba += 1;
             }
@@ -318,9 +326,9 @@ pub fn zstd_normalization_1_inplace(
         }
     }
     if -still_to_distribute >= (max >> 1) as isize {
-        panic!("fail to normalize")
+        return Err(Box::new(NormError::NormalizationError));
     }
-    *max_norm += still_to_distribute as usize;
+    *max_norm = (*max_norm as isize + still_to_distribute) as usize;
     Ok(())
 }
 
@@ -1,10 +1,8 @@
-use std::{collections::HashMap, convert::TryInto};
-use tiny_bitstream::{BitDstream, BitEstream, BitReader, BitWriter};
+use alloc::{vec, vec::Vec};
+use core::convert::TryInto;
 
-use crate::normalization::{
-    build_cumulative_function, normalization_with_compensation_binary_heap,
-    normalization_with_fast_compensation,
-};
+use crate::bitstream::{BitDstream, BitEstream};
+use crate::normalization::{build_cumulative_function, normalization_with_fast_compensation};
 
 pub fn compress_state(state: usize, table_log: usize, frequency: usize, cumul: usize) -> usize {
     // The feature `checks` adds some natural checks behind a compilation feature; in some
@@ -29,13 +27,15 @@ pub fn compress_state(state: usize, table_log: usize, frequency: usize, cumul: u
 
 /// Meme chose que encode_u8 mais avec un tbleau de u16 comme source. Generalement
 /// l'histogramme est plus coûteux à réaliser sur cette taille là.
+#[cfg(feature = "std")]
 #[deprecated = "You should cook your own encoding function"]
 pub fn encode(
     hist: &mut [usize],
-    symbol_index: &HashMap<u16, usize>,
+    symbol_index: &std::collections::HashMap<u16, usize>,
     table_log: usize, // R
     src: &[u16],
 ) -> (usize, Vec<u32>, Vec<u8>) {
+    use crate::normalization::normalization_with_compensation_binary_heap;
     let cs = normalization_with_compensation_binary_heap(hist, table_log, 255).unwrap();
 
     let mut state = 0;
@@ -53,14 +53,14 @@ pub fn encode(
         if state >= (fs << d) {
             let bits = state & msk;
             let nb_bits = u64::BITS - bits.leading_zeros();
-            estream.unchecked_write(bits, nb_bits.try_into().unwrap());
+            estream.write(bits, nb_bits.try_into().unwrap());
             nb_bits_table.push(nb_bits);
             state >>= 16;
         };
 
         state = compress_state(state, table_log, fs, *cs.get(index).unwrap());
     });
-    (state, nb_bits_table, estream.try_into().unwrap())
+    (state, nb_bits_table, estream.into())
 }
 
 #[deprecated = "You should cook your own encoding function"]
@@ -114,7 +114,7 @@ pub fn encode_u8(
             // seulement les 16 bit plus grands.
             let bits = state & msk;
             let nb_bits = u64::BITS - bits.leading_zeros();
-            estream.unchecked_write(bits, nb_bits.try_into().unwrap());
+            estream.write(bits, nb_bits.try_into().unwrap());
             nb_bits_table.push(nb_bits.try_into().unwrap());
             state >>= 16;
         };
@@ -122,7 +122,7 @@ pub fn encode_u8(
         state = compress_state(state, table_log, fs, cs[index]);
     });
     //println!("state {state}");
-    (state, nb_bits_table, estream.try_into().unwrap())
+    (state, nb_bits_table, estream.into())
 }
 
 pub fn encode_rans(
@@ -170,7 +170,7 @@ pub fn encode_rans(
             // seulement les 16 bit plus grands.
             let bits = state & msk;
             let nb_bits = u64::BITS - bits.leading_zeros();
-            estream.unchecked_write(bits, nb_bits.try_into().unwrap());
+            estream.write(bits, nb_bits.try_into().unwrap());
             nb_bits_table.push(nb_bits.try_into().unwrap());
             state >>= 16;
         };
@@ -178,7 +178,7 @@ pub fn encode_rans(
         state = compress_state(state, table_log, fs, cs[index]);
     });
     //println!("state {state}");
-    (state, nb_bits_table, estream.try_into().unwrap())
+    (state, nb_bits_table, estream.into())
 }
 
 /// Todo: trouver le symbole par dychotomie. ( et explorer d'autres méthodes plus
@@ -211,7 +211,6 @@ pub fn decode(
     let mask = 2usize.pow(table_log as u32) - 1;
 
     let mut dstream: BitDstream = str.try_into().unwrap();
-    dstream.read(1).unwrap(); // read mark
 
     let cs = build_cumulative_function(normalized_counter);
     let mut ret = vec![];
@@ -230,7 +229,7 @@ pub fn decode(
         );
         if state < 2usize.pow(16) {
             if let Some(nb_bits) = bits.pop() {
-                state = (state << 16) + dstream.read(nb_bits as u8).unwrap() as usize;
+                state = (state << 16) + dstream.read(nb_bits as u8).unwrap();
             }
         }
     }
@@ -238,32 +237,85 @@ pub fn decode(
     ret
 }
 
+/// Decode `len` symbols of a stream of `encode_rans`.
+///
+/// Panic if the table or the stream are invalid, see `try_decode_rans`.
 pub fn decode_rans(
+    state: usize,
+    bits: Vec<u8>,
+    stream: Vec<u8>,
+    normalized_counter: &[usize],
+    table_log: usize,
+    len: usize,
+) -> Vec<u8> {
+    try_decode_rans(state, bits, stream, normalized_counter, table_log, len)
+        .unwrap_or_else(|err| panic!("Cannot decode the rANS stream: {err:?}"))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RansError {
+    /// The normalized histogram or the table log are invalid.
+    InvalidTable,
+    /// The state or the number of bits of a flush are out of bounds.
+    InvalidState,
+    /// The stream ends before the last symbol.
+    Truncated,
+    /// The stream contains more symbols than the maximum size.
+    TooLarge,
+}
+
+/// Check the table log and the normalized histogram of a decoder.
+fn check_table(normalized_counter: &[usize], table_log: usize) -> Result<(), RansError> {
+    if !(1..=16).contains(&table_log)
+        || normalized_counter.iter().sum::<usize>() != 1 << table_log
+        || normalized_counter.iter().skip(256).any(|count| *count > 0)
+    {
+        return Err(RansError::InvalidTable);
+    }
+    Ok(())
+}
+
+/// Same as `decode_rans`, but an invalid input returns an error instead of a
+/// panic. The encoder keeps the state under 2^32 and flushes 16 bits at most,
+/// the table log can't be greater than 16.
+///
+/// ```
+/// use final_state_rs::r_ans::*;
+///
+/// let norm = [3, 5];
+/// let (state, bits, stream) = encode_rans(&norm, 3, &[1, 0, 1, 1]);
+/// let decoded = try_decode_rans(state, bits.clone(), stream.clone(), &norm, 3, 4);
+/// assert_eq!(decoded, Ok(vec![1, 0, 1, 1]));
+/// let invalid = try_decode_rans(state, bits, stream, &norm, 4, 4);
+/// assert_eq!(invalid, Err(RansError::InvalidTable));
+/// ```
+pub fn try_decode_rans(
     mut state: usize,
     mut bits: Vec<u8>,
     stream: Vec<u8>,
     normalized_counter: &[usize],
     table_log: usize,
     len: usize,
-) -> Vec<u8> {
+) -> Result<Vec<u8>, RansError> {
+    check_table(normalized_counter, table_log)?;
+    if state > u32::MAX as usize || bits.iter().any(|nb_bits| *nb_bits > 16) {
+        return Err(RansError::InvalidState);
+    }
     let mask = 2usize.pow(table_log as u32) - 1;
 
-    let mut dstream: BitDstream = stream.try_into().unwrap();
-    dstream.read(1).unwrap(); // read mark
+    let mut dstream: BitDstream = stream.try_into().map_err(|_| RansError::Truncated)?;
 
     let cs = build_cumulative_function(normalized_counter);
     let mut ret = vec![];
     for _ in 0..len {
-        // todo add a security timing to auto kill loop
         let symbol_index = find_s(state & mask, &cs);
-        ret.push(symbol_index.try_into().expect("symbol overflow"));
+        ret.push(symbol_index as u8);
         state = decompress_state(
             state,
-            *normalized_counter
-                .get(symbol_index)
-                .expect("symbol frequency not found"),
+            normalized_counter[symbol_index],
             table_log,
-            *cs.get(symbol_index).expect("symbol cumul not found"),
+            cs[symbol_index],
         );
         if state < 2usize.pow(16) {
             // Si on a un etat < 16, on essaye de lire le stream.
@@ -272,10 +324,99 @@ pub fn decode_rans(
             // ca veut dire qu'on arrive a la fin de la decompression
             // et que l'etat a une valeur attendue.
             if let Some(nb_bits) = bits.pop() {
-                state = (state << 16) + dstream.read(nb_bits as u8).unwrap() as usize;
+                state = (state << 16) + dstream.read(nb_bits).map_err(|_| RansError::Truncated)?;
             }
         }
     }
     ret.reverse();
-    ret
+    Ok(ret)
+}
+
+/// Lower bound of the state of `encode_rans_terminated`, the state stays in
+/// `[RANS_L, 2^32)` and the encoder flushes 16 bits at a time.
+const RANS_L: usize = 1 << 16;
+
+/// Encode like `encode_rans`, but the encoder starts from the state `2^16`
+/// and always flushes 16 bits, so the decoder knows when to read the stream
+/// without the number of bits of each flush. The final state and the number
+/// of symbols are written at the end of the stream, see
+/// `decode_rans_terminated`.
+pub fn encode_rans_terminated(
+    normalized_histogram: &[usize],
+    table_log: usize,
+    src: &[u8],
+) -> Vec<u8> {
+    let cs = build_cumulative_function(normalized_histogram);
+    assert_eq!(normalized_histogram.iter().sum::<usize>(), 1 << table_log);
+    assert!(table_log <= 16, "The table log can't be greater than 16");
+    let d = 32 - table_log;
+    let mut state = RANS_L;
+    let mut estream = BitEstream::new();
+    for symbol in src.iter() {
+        let index = *symbol as usize;
+        let fs = normalized_histogram[index];
+        if state >= (fs << d) {
+            estream.write(state & (RANS_L - 1), 16);
+            state >>= 16;
+        }
+        state = compress_state(state, table_log, fs, cs[index]);
+    }
+    estream.write(state, 32);
+    estream.write_size(src.len());
+    estream.into()
+}
+
+/// Decode the last stream of `encode_rans_terminated` in `dstream`, fails
+/// with `RansError::TooLarge` if it contains more than `max_size` symbols.
+/// Like `t_ans::decode_tans_terminated`, the streams can be concatenated and
+/// `BitDstream::next_stream` continues with the previous one.
+///
+/// ```
+/// use final_state_rs::bitstream::BitDstream;
+/// use final_state_rs::r_ans::*;
+///
+/// let norm = [3, 5];
+/// let mut stream = encode_rans_terminated(&norm, 3, &[1, 0, 1, 1]);
+/// stream.append(&mut encode_rans_terminated(&norm, 3, &[0, 0]));
+///
+/// let mut dstream = BitDstream::try_from(stream).unwrap();
+/// assert_eq!(decode_rans_terminated(&mut dstream, &norm, 3, 100), Ok(vec![0, 0]));
+/// dstream.next_stream().unwrap();
+/// assert_eq!(decode_rans_terminated(&mut dstream, &norm, 3, 100), Ok(vec![1, 0, 1, 1]));
+/// ```
+pub fn decode_rans_terminated(
+    dstream: &mut BitDstream,
+    normalized_counter: &[usize],
+    table_log: usize,
+    max_size: usize,
+) -> Result<Vec<u8>, RansError> {
+    check_table(normalized_counter, table_log)?;
+    let size = dstream.read_size().map_err(|_| RansError::Truncated)?;
+    if size > max_size {
+        return Err(RansError::TooLarge);
+    }
+    let mut state = dstream.read(32).map_err(|_| RansError::Truncated)?;
+    if state < RANS_L {
+        return Err(RansError::InvalidState);
+    }
+    let mask = (1 << table_log) - 1;
+    let cs = build_cumulative_function(normalized_counter);
+    let mut ret = vec![0; size];
+    for byte in ret.iter_mut().rev() {
+        let symbol_index = find_s(state & mask, &cs);
+        *byte = symbol_index as u8;
+        state = decompress_state(
+            state,
+            normalized_counter[symbol_index],
+            table_log,
+            cs[symbol_index],
+        );
+        if state < RANS_L {
+            state = (state << 16) + dstream.read(16).map_err(|_| RansError::Truncated)?;
+        }
+    }
+    if state != RANS_L {
+        return Err(RansError::InvalidState);
+    }
+    Ok(ret)
 }
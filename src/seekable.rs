@@ -0,0 +1,267 @@
+//! Seekable format, a sequence of frames with an index for random access.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! A frame of `frame` has to be decoded from its first block, its filters
+//! work on the whole source. Here the source is cut in chunks of
+//! `frame_size` bytes and each chunk is an independent frame, so a range of
+//! the source only needs the frames that contain it. The offsets of the
+//! frames are in a trailing index, the reader finds it from the end of the
+//! stream.
+//!
+//! Format, integers in little-endian:
+//! - the frames of `encode_frame`, one after the other,
+//! - the index: for each frame, its offset in the stream (u64) and the offset
+//!   of its decoded bytes in the source (u64), then the size of the frames
+//!   and the size of the source (u64 each) to close the last frame,
+//! - u32: the number of frames,
+//! - the magic `FSSK`.
+
+use std::ops::Range;
+
+use crate::frame::{decode_frame_with_max_size, encode_frame, FrameConfig, FrameError};
+
+const MAGIC: [u8; 4] = *b"FSSK";
+/// Size of an entry of the index.
+const ENTRY_SIZE: usize = 16;
+/// Size of the number of frames and of the magic.
+const FOOTER_SIZE: usize = 8;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SeekableError {
+    /// The stream is smaller than its index.
+    Truncated,
+    /// The stream doesn't end with the magic.
+    InvalidMagic,
+    /// The offsets of the index aren't increasing, or don't match the frames.
+    InvalidIndex,
+    /// The range goes after the end of the source.
+    OutOfRange,
+    /// A frame can't be decoded.
+    Frame(FrameError),
+}
+
+/// Compress the source in independent frames of `frame_size` bytes followed
+/// by their index.
+///
+/// ```
+/// use final_state_rs::frame::FrameConfig;
+/// use final_state_rs::seekable::*;
+///
+/// let src = (0..100_000u32).flat_map(|v| (v % 251).to_le_bytes()).collect::<Vec<u8>>();
+/// let encoded = encode_seekable(&src, 32 << 10, &FrameConfig::default());
+/// let reader = SeekableReader::new(&encoded).unwrap();
+/// assert_eq!(reader.len(), src.len());
+/// assert_eq!(reader.read_range(200_000, 10).unwrap(), src[200_000..200_010]);
+/// ```
+pub fn encode_seekable(src: &[u8], frame_size: usize, config: &FrameConfig) -> Vec<u8> {
+    assert!(frame_size > 0, "The size of the frames can't be 0");
+    let mut ret = vec![];
+    let mut index = vec![];
+    for (i, chunk) in src.chunks(frame_size).enumerate() {
+        index.push((ret.len(), i * frame_size));
+        ret.extend_from_slice(&encode_frame(chunk, config));
+    }
+    assert!(index.len() <= u32::MAX as usize, "Too many frames");
+    let nb_frames = index.len();
+    index.push((ret.len(), src.len()));
+    for (offset, decoded_offset) in index {
+        ret.extend_from_slice(&(offset as u64).to_le_bytes());
+        ret.extend_from_slice(&(decoded_offset as u64).to_le_bytes());
+    }
+    ret.extend_from_slice(&(nb_frames as u32).to_le_bytes());
+    ret.extend_from_slice(&MAGIC);
+    ret
+}
+
+/// Reader of a stream of `encode_seekable`, it reads the index once and
+/// decodes only the frames of the requested ranges.
+pub struct SeekableReader<'a> {
+    src: &'a [u8],
+    /// Offset of each frame in the stream and of its decoded bytes in the
+    /// source, the last entry is the end of the frames and of the source.
+    index: Vec<(usize, usize)>,
+}
+
+impl<'a> SeekableReader<'a> {
+    /// Read the index at the end of the stream.
+    pub fn new(src: &'a [u8]) -> Result<Self, SeekableError> {
+        let footer = src
+            .len()
+            .checked_sub(FOOTER_SIZE)
+            .ok_or(SeekableError::Truncated)?;
+        if src[footer + 4..] != MAGIC {
+            return Err(SeekableError::InvalidMagic);
+        }
+        let nb_frames = u32::from_le_bytes(src[footer..footer + 4].try_into().unwrap()) as usize;
+        let index_start = (nb_frames + 1)
+            .checked_mul(ENTRY_SIZE)
+            .and_then(|size| footer.checked_sub(size))
+            .ok_or(SeekableError::Truncated)?;
+        let offset = |bytes: &[u8]| {
+            usize::try_from(u64::from_le_bytes(bytes.try_into().unwrap()))
+                .map_err(|_| SeekableError::InvalidIndex)
+        };
+        let index = src[index_start..footer]
+            .chunks(ENTRY_SIZE)
+            .map(|entry| Ok((offset(&entry[..8])?, offset(&entry[8..])?)))
+            .collect::<Result<Vec<(usize, usize)>, SeekableError>>()?;
+        // Chaque frame contient au moins un octet, et la dernière se termine
+        // au début de l'index.
+        if index[0] != (0, 0)
+            || index[nb_frames].0 != index_start
+            || index
+                .windows(2)
+                .any(|entries| entries[0].0 >= entries[1].0 || entries[0].1 >= entries[1].1)
+        {
+            return Err(SeekableError::InvalidIndex);
+        }
+        Ok(SeekableReader { src, index })
+    }
+
+    /// Size of the source.
+    pub fn len(&self) -> usize {
+        self.index[self.index.len() - 1].1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of independent frames.
+    pub fn nb_frames(&self) -> usize {
+        self.index.len() - 1
+    }
+
+    /// Range of the source decoded by a frame.
+    pub fn frame_range(&self, frame: usize) -> Range<usize> {
+        self.index[frame].1..self.index[frame + 1].1
+    }
+
+    /// Decode one frame.
+    pub fn read_frame(&self, frame: usize) -> Result<Vec<u8>, SeekableError> {
+        let encoded = &self.src[self.index[frame].0..self.index[frame + 1].0];
+        let size = self.frame_range(frame).len();
+        let decoded = decode_frame_with_max_size(encoded, size).map_err(SeekableError::Frame)?;
+        if decoded.len() != size {
+            return Err(SeekableError::InvalidIndex);
+        }
+        Ok(decoded)
+    }
+
+    /// Decode `len` bytes of the source from `start`, only the frames that
+    /// contain them are decoded.
+    ///
+    /// ```
+    /// use final_state_rs::frame::FrameConfig;
+    /// use final_state_rs::seekable::*;
+    ///
+    /// let src = "abracadabra".repeat(1000).into_bytes();
+    /// let encoded = encode_seekable(&src, 1000, &FrameConfig::default());
+    /// let reader = SeekableReader::new(&encoded).unwrap();
+    /// assert_eq!(reader.read_range(995, 10).unwrap(), src[995..1005]);
+    /// assert_eq!(reader.read_range(10_995, 10), Err(SeekableError::OutOfRange));
+    /// ```
+    pub fn read_range(&self, start: usize, len: usize) -> Result<Vec<u8>, SeekableError> {
+        let end = start
+            .checked_add(len)
+            .filter(|end| *end <= self.len())
+            .ok_or(SeekableError::OutOfRange)?;
+        if len == 0 {
+            return Ok(vec![]);
+        }
+        let first = self.index.partition_point(|entry| entry.1 <= start) - 1;
+        let last = self.index.partition_point(|entry| entry.1 < end) - 1;
+        let mut ret = Vec::with_capacity(len);
+        for frame in first..=last {
+            let decoded = self.read_frame(frame)?;
+            let range = self.frame_range(frame);
+            let from = start.max(range.start) - range.start;
+            let to = end.min(range.end) - range.start;
+            ret.extend_from_slice(&decoded[from..to]);
+        }
+        Ok(ret)
+    }
+}
+
+#[test]
+fn seekable_read_range() {
+    use crate::filters::Filter;
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+
+    let config = FrameConfig {
+        filters: vec![Filter::Delta(1)],
+        ..Default::default()
+    };
+    let encoded = encode_seekable(&book1, 100_000, &config);
+    let reader = SeekableReader::new(&encoded).unwrap();
+    assert_eq!(reader.len(), book1.len());
+    assert_eq!(reader.nb_frames(), book1.len().div_ceil(100_000));
+    assert_eq!(reader.read_range(0, book1.len()).unwrap(), book1);
+    for _ in 0..100 {
+        let start = rand::random::<usize>() % book1.len();
+        let len = rand::random::<usize>() % (book1.len() - start).min(250_000);
+        assert_eq!(
+            reader.read_range(start, len).unwrap(),
+            book1[start..start + len]
+        );
+    }
+    assert_eq!(
+        reader.read_range(99_990, 20).unwrap(),
+        book1[99_990..100_010]
+    );
+    assert_eq!(reader.read_range(book1.len(), 0).unwrap(), []);
+    assert_eq!(
+        reader.read_range(book1.len() - 1, 2),
+        Err(SeekableError::OutOfRange)
+    );
+    assert_eq!(
+        reader.read_range(usize::MAX, 2),
+        Err(SeekableError::OutOfRange)
+    );
+
+    // Un range dans la deuxième frame ne lit pas la première.
+    let mut corrupted = encoded.clone();
+    corrupted[0] ^= 0xff;
+    let reader = SeekableReader::new(&corrupted).unwrap();
+    assert_eq!(
+        reader.read_range(150_000, 100).unwrap(),
+        book1[150_000..150_100]
+    );
+    assert_eq!(
+        reader.read_range(50_000, 100),
+        Err(SeekableError::Frame(FrameError::InvalidMagic))
+    );
+
+    let empty = encode_seekable(&[], 1000, &config);
+    let reader = SeekableReader::new(&empty).unwrap();
+    assert!(reader.is_empty());
+    assert_eq!(reader.nb_frames(), 0);
+    assert_eq!(reader.read_range(0, 0).unwrap(), []);
+
+    assert_eq!(
+        SeekableReader::new(b"FSSK").err(),
+        Some(SeekableError::Truncated)
+    );
+    assert_eq!(
+        SeekableReader::new(&encoded[..encoded.len() - 1]).err(),
+        Some(SeekableError::InvalidMagic)
+    );
+    assert_eq!(
+        SeekableReader::new(&encoded[1..]).err(),
+        Some(SeekableError::InvalidIndex)
+    );
+    let mut forged = encoded.clone();
+    let footer = forged.len() - FOOTER_SIZE;
+    forged[footer..footer + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(
+        SeekableReader::new(&forged).err(),
+        Some(SeekableError::Truncated)
+    );
+}
@@ -6,6 +6,8 @@
 //! Author: Adrien Zinger, avec l'inspiration du travail de Jarek Duda,
 //!         Yann Collet, Charles Bloom et bien d'autres.
 
+use alloc::{vec, vec::Vec};
+
 /// Implémentation original dans fse.c par Yann Collet. Décrite par Charles
 /// Bloom. Cette méthode à été mise à jour plus tard.
 ///
@@ -119,6 +121,40 @@ pub fn bit_reverse_spread(sorted_hist: &[usize], table_log: usize) -> Vec<u8> {
     ret
 }
 
+/// Diffusion utilisée par zstd pour les tables FSE de ses blocs. Les symboles
+/// ne sont pas triés et le pas est `(5/8) range + 3`. Un symbole de
+/// probabilité `-1` (moins qu'un état) occupe un seul état, en fin de table,
+/// et les autres positions sautent ces états réservés.
+///
+/// ```
+/// use final_state_rs::spreads::zstd_spread;
+///
+/// let spread = zstd_spread(&[6, 6, 3, -1], 4);
+/// assert_eq!(spread[15], 3);
+/// ```
+pub fn zstd_spread(norm: &[i16], table_log: usize) -> Vec<u8> {
+    let table_size = 1 << table_log;
+    let mask = table_size - 1;
+    let mut ret = vec![0; table_size];
+    let mut high_threshold = table_size - 1;
+    for (s, _) in norm.iter().enumerate().filter(|(_, count)| **count == -1) {
+        ret[high_threshold] = s as u8;
+        high_threshold = high_threshold.wrapping_sub(1);
+    }
+    let step = (table_size >> 1) + (table_size >> 3) + 3;
+    let mut pos = 0;
+    for (s, &count) in norm.iter().enumerate().filter(|(_, count)| **count > 0) {
+        for _ in 0..count {
+            ret[pos] = s as u8;
+            pos = (pos + step) & mask;
+            while pos > high_threshold {
+                pos = (pos + step) & mask;
+            }
+        }
+    }
+    ret
+}
+
 // ****************************************************************************
 // ****************************************************************************
 // ****************************************************************************
@@ -195,3 +231,21 @@ fn bitreverse_spread_test() {
             .collect::<Vec<char>>()
     )
 }
+
+#[test]
+fn zstd_spread_test() {
+    assert_eq!(
+        "AABCABCABCABBAAB",
+        zstd_spread(&[7, 6, 3], 4)
+            .iter()
+            .map(|c| char::from(b'A' + *c))
+            .collect::<String>()
+    );
+    assert_eq!(
+        "AABCABCABCABBABD",
+        zstd_spread(&[6, 6, 3, -1], 4)
+            .iter()
+            .map(|c| char::from(b'A' + *c))
+            .collect::<String>()
+    );
+}
@@ -7,7 +7,9 @@
 //! We are trying to implement an efficient tANS en/de-coder.
 //! Author: Adrien Zinger
 
-use tiny_bitstream::{BitDstream, BitEstream, BitReader, BitWriter};
+use alloc::{vec, vec::Vec};
+
+use crate::bitstream::{BitDstream, BitEstream, ReadBits, WriteBits};
 
 /// Preparation for tANS of the encoding table.
 ///
@@ -88,25 +90,25 @@ pub fn encode_symbol(
     table: &[usize],
     state: usize,
     symbol: usize,
-    stream: &mut BitEstream,
+    stream: &mut impl WriteBits,
 ) -> usize {
     let nb_bits_out = (state + delta_nb_bits[symbol]) >> 16;
-    stream.unchecked_write(state, nb_bits_out as u8);
+    stream.write_bits(state, nb_bits_out as u8);
     table[((state >> nb_bits_out) as i32 + starts[symbol]) as usize]
 }
 
 #[inline] // I want to be sure that will be inlined
 pub fn decode_symbol(
-    dstream: &mut BitDstream,
+    dstream: &mut impl ReadBits,
     nb_bits: &[usize],
     new_states: &[usize],
     state: usize,
     spread: &[u8],
 ) -> (usize, u8) {
-    // Panic if we try to look further than the length of the stream
+    // Panic if we try to look further than the length of the stream.
     let bits = dstream
-        .read(nb_bits[state] as u8)
-        .unwrap_or_else(|_| panic!("Expected to be able to read {} bytes", nb_bits[state]));
+        .read_bits(nb_bits[state] as u8)
+        .unwrap_or_else(|_| panic!("Expected to be able to read {} bits", nb_bits[state]));
     let ret = new_states[state] + bits;
     (ret, spread[state])
 }
@@ -220,27 +222,195 @@ pub fn encode_tans(
             &mut estream,
         )
     });
-    (estream.try_into().unwrap(), *state - (1 << table_log))
+    (estream.into(), *state - (1 << table_log))
 }
 
 /// Decode any source encoded with `encode_tans` if we know the histogram, the
 /// spread table and the table_log used for it. The state should be the latest
 /// state that encode_symbol gave, which is also returned by the `encode_tans`
 /// function.
+///
+/// Panic if the table or the stream are invalid, see `try_decode_tans`.
 pub fn decode_tans(
     src: Vec<u8>,
     histogram: &[usize],
     spread: &[u8],
     table_log: usize,
-    mut state: usize,
+    state: usize,
     dst_buffer: &mut [u8],
 ) {
+    if let Err(err) = try_decode_tans(src, histogram, spread, table_log, state, dst_buffer) {
+        panic!("Cannot decode the tANS stream: {err:?}")
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TansError {
+    /// The histogram, the spread and the table log don't describe a table.
+    InvalidTable,
+    /// The state is greater than the table.
+    InvalidState,
+    /// The stream ends before the last symbol.
+    Truncated,
+    /// The stream contains more symbols than the maximum size.
+    TooLarge,
+}
+
+/// Greatest table log accepted by the decoder, the decoding table takes
+/// `2^table_log` states.
+const MAX_DECODE_TABLE_LOG: usize = 20;
+
+/// Check that the spread contains each symbol as many times as its
+/// normalized count.
+fn check_decode_table(
+    histogram: &[usize],
+    spread: &[u8],
+    table_log: usize,
+) -> Result<(), TansError> {
+    if !(1..=MAX_DECODE_TABLE_LOG).contains(&table_log) || spread.len() != 1 << table_log {
+        return Err(TansError::InvalidTable);
+    }
+    let mut counts = [0; 256];
+    for symbol in spread.iter() {
+        counts[*symbol as usize] += 1;
+    }
+    let expected = histogram.iter().chain(core::iter::repeat(&0));
+    if counts
+        .iter()
+        .zip(expected)
+        .any(|(count, norm)| count != norm)
+        || histogram.iter().skip(256).any(|norm| *norm > 0)
+    {
+        return Err(TansError::InvalidTable);
+    }
+    Ok(())
+}
+
+/// Same as `decode_tans`, but an invalid table or a truncated stream returns
+/// an error instead of a panic. The size of the output is the size of the
+/// destination buffer, the decoder doesn't allocate anything else than the
+/// decoding table.
+///
+/// ```
+/// use final_state_rs::spreads::fse_spread_unsorted;
+/// use final_state_rs::t_ans::*;
+///
+/// let norm = [10, 6];
+/// let spread = fse_spread_unsorted(&norm, 4);
+/// let mut state = 1 << 4;
+/// let (stream, state) = encode_tans(&[0, 1, 1, 0, 0], &norm, &spread, 4, &mut state);
+/// let mut decoded = [0; 5];
+/// try_decode_tans(stream.clone(), &norm, &spread, 4, state, &mut decoded).unwrap();
+/// assert_eq!(decoded, [0, 1, 1, 0, 0]);
+///
+/// let invalid = try_decode_tans(stream, &[10, 5], &spread, 4, state, &mut decoded);
+/// assert_eq!(invalid, Err(TansError::InvalidTable));
+/// ```
+pub fn try_decode_tans(
+    src: Vec<u8>,
+    histogram: &[usize],
+    spread: &[u8],
+    table_log: usize,
+    mut state: usize,
+    dst_buffer: &mut [u8],
+) -> Result<(), TansError> {
+    check_decode_table(histogram, spread, table_log)?;
+    if state >= 1 << table_log {
+        return Err(TansError::InvalidState);
+    }
     let (nb_bits, new_states) = build_decode_table(table_log, spread, histogram);
-    let mut dstream = BitDstream::try_from(src).unwrap();
-    dstream.read(1).unwrap(); // Read mark
-    dst_buffer.iter_mut().rev().for_each(|byte| {
-        let (new_state, symbol) = decode_symbol(&mut dstream, &nb_bits, &new_states, state, spread);
-        *byte = symbol;
-        state = new_state;
-    });
+    let mut dstream = BitDstream::try_from(src).map_err(|_| TansError::Truncated)?;
+    for byte in dst_buffer.iter_mut().rev() {
+        let bits = dstream
+            .read(nb_bits[state] as u8)
+            .map_err(|_| TansError::Truncated)?;
+        *byte = spread[state];
+        state = new_states[state] + bits;
+    }
+    Ok(())
+}
+
+/// Encode with `encode_tans`, then write the final state and the number of
+/// symbols at the end of the stream. The decoder doesn't need anything else
+/// than the table, see `decode_tans_terminated`.
+pub fn encode_tans_terminated(
+    src: &[u8],
+    histogram: &[usize],
+    spread: &[u8],
+    table_log: usize,
+) -> Vec<u8> {
+    let (table, delta_nb_bits, starts) = build_encode_table(histogram, table_log, spread);
+    let mut estream = BitEstream::new();
+    let mut state = 1 << table_log;
+    for symbol in src.iter() {
+        state = encode_symbol(
+            &delta_nb_bits,
+            &starts,
+            &table,
+            state,
+            *symbol as usize,
+            &mut estream,
+        );
+    }
+    estream.write(state - (1 << table_log), table_log as u8);
+    estream.write_size(src.len());
+    estream.into()
+}
+
+/// Decode the last stream of `encode_tans_terminated` in `dstream`, fails
+/// with `TansError::TooLarge` if it contains more than `max_size` symbols.
+/// The decoder ends on the initial state of the encoder, otherwise the
+/// stream is invalid.
+///
+/// The streams can be concatenated: once a stream is decoded, `dstream` is
+/// at the beginning of it and `BitDstream::next_stream` continues with the
+/// previous one.
+///
+/// ```
+/// use final_state_rs::bitstream::BitDstream;
+/// use final_state_rs::spreads::fse_spread_unsorted;
+/// use final_state_rs::t_ans::*;
+///
+/// let norm = [10, 6];
+/// let spread = fse_spread_unsorted(&norm, 4);
+/// let mut stream = encode_tans_terminated(&[0, 1, 1, 0, 0], &norm, &spread, 4);
+/// stream.append(&mut encode_tans_terminated(&[1, 1, 1], &norm, &spread, 4));
+///
+/// let mut dstream = BitDstream::try_from(stream).unwrap();
+/// let last = decode_tans_terminated(&mut dstream, &norm, &spread, 4, 100).unwrap();
+/// assert_eq!(last, [1, 1, 1]);
+/// dstream.next_stream().unwrap();
+/// let first = decode_tans_terminated(&mut dstream, &norm, &spread, 4, 100).unwrap();
+/// assert_eq!(first, [0, 1, 1, 0, 0]);
+/// assert_eq!(dstream.remaining(), 0);
+/// ```
+pub fn decode_tans_terminated(
+    dstream: &mut BitDstream,
+    histogram: &[usize],
+    spread: &[u8],
+    table_log: usize,
+    max_size: usize,
+) -> Result<Vec<u8>, TansError> {
+    check_decode_table(histogram, spread, table_log)?;
+    let size = dstream.read_size().map_err(|_| TansError::Truncated)?;
+    if size > max_size {
+        return Err(TansError::TooLarge);
+    }
+    let mut state = dstream
+        .read(table_log as u8)
+        .map_err(|_| TansError::Truncated)?;
+    let (nb_bits, new_states) = build_decode_table(table_log, spread, histogram);
+    let mut ret = vec![0; size];
+    for byte in ret.iter_mut().rev() {
+        let bits = dstream
+            .read(nb_bits[state] as u8)
+            .map_err(|_| TansError::Truncated)?;
+        *byte = spread[state];
+        state = new_states[state] + bits;
+    }
+    if state != 0 {
+        return Err(TansError::InvalidState);
+    }
+    Ok(ret)
 }
@@ -9,6 +9,17 @@ use crate::{
     t_ans::{decode_tans, encode_tans},
 };
 
+/// Content of a file of `rsc/`, for the tests of the modules that read
+/// several ressources.
+pub(crate) fn read_rsc(path: &str) -> Vec<u8> {
+    let mut ret = vec![];
+    File::open(path)
+        .unwrap_or_else(|_| panic!("Cannot find {path} ressource"))
+        .read_to_end(&mut ret)
+        .unwrap_or_else(|_| panic!("Unexpected fail to read {path} ressource"));
+    ret
+}
+
 const fn get_calgary_extract_histogram_1() -> (([usize; 256], usize), [u8; 50]) {
     const SRC: [u8; 50] = [
         37, 65, 32, 65, 98, 100, 111, 117, 44, 32, 73, 46, 69, 46, 10, 37, 65, 32, 87, 111, 110,
@@ -126,6 +137,75 @@ fn tans_book1_compression() {
     assert_eq!(book1[..], book1_decoded);
 }
 
+#[test]
+fn terminated_streams_concatenation() {
+    use crate::bitstream::BitDstream;
+    use crate::r_ans::{decode_rans_terminated, encode_rans_terminated, RansError};
+    use crate::t_ans::{decode_tans_terminated, encode_tans_terminated, TansError};
+
+    const TABLE_LOG: usize = 11;
+    let mut book1 = vec![];
+    File::open("./rsc/calgary_book1")
+        .expect("Cannot find calgary book1 ressource")
+        .read_to_end(&mut book1)
+        .expect("Unexpected fail to read calgary book1 ressource");
+    let mut hist = [0; 256];
+    let max_symbol = multi_bucket_count_u8(&book1, &mut hist);
+    let hist = normalization_with_compensation_binary_heap(&hist, TABLE_LOG, max_symbol).unwrap();
+    let spread = &fse_spread_unsorted(&hist, TABLE_LOG);
+
+    // Des lignes de book1, plus une vide, les unes après les autres sans
+    // leurs tailles.
+    let mut lines = book1
+        .split_inclusive(|byte| *byte == b'\n')
+        .take(2000)
+        .collect::<Vec<&[u8]>>();
+    lines.push(&[]);
+    let mut tans = vec![];
+    let mut rans = vec![];
+    for line in lines.iter() {
+        tans.extend(encode_tans_terminated(line, &hist, spread, TABLE_LOG));
+        rans.extend(encode_rans_terminated(&hist, TABLE_LOG, line));
+    }
+    let mut tans = BitDstream::try_from(tans).unwrap();
+    let mut rans = BitDstream::try_from(rans).unwrap();
+    for (i, line) in lines.iter().enumerate().rev() {
+        let decoded = decode_tans_terminated(&mut tans, &hist, spread, TABLE_LOG, 1000).unwrap();
+        assert_eq!(decoded, *line);
+        let decoded = decode_rans_terminated(&mut rans, &hist, TABLE_LOG, 1000).unwrap();
+        assert_eq!(decoded, *line);
+        if i > 0 {
+            tans.next_stream().unwrap();
+            rans.next_stream().unwrap();
+        }
+    }
+    assert_eq!(tans.remaining(), 0);
+    assert_eq!(rans.remaining(), 0);
+
+    let stream = encode_tans_terminated(&book1, &hist, spread, TABLE_LOG);
+    let mut dstream = BitDstream::try_from(stream.clone()).unwrap();
+    let decoded = decode_tans_terminated(&mut dstream, &hist, spread, TABLE_LOG, book1.len());
+    assert_eq!(decoded.unwrap(), book1);
+    let mut dstream = BitDstream::try_from(stream.clone()).unwrap();
+    let decoded = decode_tans_terminated(&mut dstream, &hist, spread, TABLE_LOG, 1000);
+    assert_eq!(decoded, Err(TansError::TooLarge));
+    // Sans le début du flux, le décodeur ne finit pas sur l'état initial.
+    let mut dstream = BitDstream::try_from(stream[1..].to_vec()).unwrap();
+    let decoded = decode_tans_terminated(&mut dstream, &hist, spread, TABLE_LOG, book1.len());
+    assert!(decoded.is_err());
+
+    let stream = encode_rans_terminated(&hist, TABLE_LOG, &book1);
+    let mut dstream = BitDstream::try_from(stream.clone()).unwrap();
+    let decoded = decode_rans_terminated(&mut dstream, &hist, TABLE_LOG, book1.len());
+    assert_eq!(decoded.unwrap(), book1);
+    let mut dstream = BitDstream::try_from(stream[1..].to_vec()).unwrap();
+    let decoded = decode_rans_terminated(&mut dstream, &hist, TABLE_LOG, book1.len());
+    assert!(decoded.is_err());
+    let mut dstream = BitDstream::try_from(vec![0x01]).unwrap();
+    let decoded = decode_rans_terminated(&mut dstream, &hist, TABLE_LOG, book1.len());
+    assert_eq!(decoded, Err(RansError::Truncated));
+}
+
 /* ***************************************************************
 ******************************************************************
 ** Some tests of the normalization's methods
@@ -315,3 +395,201 @@ fn correctness_multi_bucket_count() {
     assert_eq!(ret[4], 1, "error at the 4th block");
     assert_eq!(ret[5], 1, "error at the 5th block");
 }
+
+#[test]
+fn test_large_alphabet_counters_consistency() {
+    use crate::count::*;
+
+    let mut book1 = vec![];
+    File::open("./rsc/calgary_book1")
+        .expect("Cannot find calgary book1 ressource")
+        .read_to_end(&mut book1)
+        .expect("Unexpected fail to read calgary book1 ressource");
+    // Les paires d'octets du livre comme symboles de 16 bits.
+    let src_u16 = book1
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect::<Vec<u16>>();
+    let src_u32 = src_u16
+        .iter()
+        .map(|s| *s as u32 + 100_000)
+        .collect::<Vec<u32>>();
+
+    let (hist1, max1) = simple_count_u16(&src_u16, u16::MAX as usize).unwrap();
+    let (hist2, max2) = multi_bucket_count_u16(&src_u16, u16::MAX as usize).unwrap();
+    assert_eq!((&hist1, max1), (&hist2, max2));
+    assert_eq!(hist1.iter().sum::<u32>() as usize, src_u16.len());
+    // Tailles non multiples de 4
+    for len in 1..8 {
+        assert_eq!(
+            simple_count_u16(&src_u16[..len], max1),
+            multi_bucket_count_u16(&src_u16[..len], max1)
+        );
+    }
+
+    let (hist3, max3) = simple_count_u32(&src_u32, 200_000).unwrap();
+    let (hist4, max4) = multi_bucket_count_u32(&src_u32, 200_000).unwrap();
+    assert_eq!((&hist3, max3), (&hist4, max4));
+    assert_eq!(max3, max1 + 100_000);
+    assert_eq!(hist3[100_000..=max3], hist1[..=max1]);
+
+    #[cfg(feature = "rayon")]
+    {
+        let split = std::thread::available_parallelism().unwrap().get();
+        let (hist5, max5) = divide_and_conquer_count_u16(&src_u16, split, max1).unwrap();
+        assert_eq!((&hist1[..=max1], max1), (&hist5[..], max5));
+        let (hist6, max6) = divide_and_conquer_count_u32(&src_u32, split, 200_000).unwrap();
+        assert_eq!((&hist3, max3), (&hist6, max6));
+        assert_eq!(
+            divide_and_conquer_count_u16(&src_u16, split, 1000),
+            Err(CountError::SymbolOutOfBound(src_u16[0] as usize))
+        );
+    }
+
+    assert_eq!(
+        multi_bucket_count_u32(&src_u32, max3 - 1),
+        Err(CountError::SymbolOutOfBound(max3))
+    );
+
+    // L'histogramme se normalise comme ceux des octets.
+    let hist = hist1.iter().map(|c| *c as usize).collect::<Vec<usize>>();
+    let norm = normalization_with_compensation_binary_heap(&hist, 14, max1).unwrap();
+    assert_eq!(norm.iter().sum::<usize>(), 1 << 14);
+    assert!(hist
+        .iter()
+        .zip(norm.iter())
+        .all(|(count, normalized)| (*count > 0) == (*normalized > 0)));
+}
+
+/// Les compteurs à quatre tables de 32 bits et le choix au runtime doivent
+/// donner le même histogramme, quelle que soit la taille modulo 16.
+#[test]
+fn test_parallel_lanes_counters_consistency() {
+    use crate::count::{count_u8, parallel_lanes_count_u8};
+
+    let mut book1 = vec![];
+    File::open("./rsc/calgary_book1")
+        .expect("Cannot find calgary book1 ressource")
+        .read_to_end(&mut book1)
+        .expect("Unexpected fail to read calgary book1 ressource");
+    let runs = (0..100_003).map(|i| (i / 1000) as u8).collect::<Vec<u8>>();
+    for src in [&book1[..], &book1[..1499], &book1[..17], &runs, &[]] {
+        let (expected, expected_max) = simple_count_u8(src);
+        let mut hist = [0; 256];
+        assert_eq!(parallel_lanes_count_u8(src, &mut hist), expected_max);
+        assert_eq!(hist, expected);
+        let mut hist = [0; 256];
+        assert_eq!(count_u8(src, &mut hist), expected_max);
+        assert_eq!(hist, expected);
+        #[cfg(feature = "portable_simd")]
+        {
+            let mut hist = [0; 256];
+            assert_eq!(crate::count::simd_count_u8(src, &mut hist), expected_max);
+            assert_eq!(hist, expected);
+        }
+    }
+}
+
+/// Tous les compteurs retournent le plus grand symbole de la source, même
+/// quand l'histogramme contient déjà des symboles plus grands.
+#[test]
+fn test_counters_max_symbol() {
+    use crate::count::{count_u8, parallel_lanes_count_u8};
+
+    // Assez grand pour passer par `divide_and_conquer_count` avec `rayon`.
+    let large = (0..5 << 20).map(|i| (i % 97) as u8).collect::<Vec<u8>>();
+    type Counter = fn(&[u8], &mut [usize; 256]) -> usize;
+    let counters: &[Counter] = &[
+        simple_count_u8_inplace,
+        parallel_lanes_count_u8,
+        count_u8,
+        #[cfg(feature = "portable_simd")]
+        crate::count::simd_count_u8,
+    ];
+    for src in [&large[..], &large[..100_000], &large[..17], &[]] {
+        let (expected, expected_max) = simple_count_u8(src);
+        for counter in counters {
+            let mut hist = [1; 256];
+            assert_eq!(counter(src, &mut hist), expected_max);
+            assert!(hist.iter().zip(expected.iter()).all(|(h, e)| *h == e + 1));
+        }
+    }
+}
+
+/// L'histogramme incrémental doit donner les mêmes comptes que les compteurs
+/// et être accepté tel quel par toutes les fonctions de normalisation.
+#[test]
+fn test_incremental_histogram() {
+    use crate::{
+        count::{CountError, Histogram},
+        normalization::{
+            fast_normalization_1, normalization_with_fast_compensation, slow_normalization,
+            zstd_normalization_1_inplace,
+        },
+    };
+
+    let mut book1 = vec![];
+    File::open("./rsc/calgary_book1")
+        .expect("Cannot find calgary book1 ressource")
+        .read_to_end(&mut book1)
+        .expect("Unexpected fail to read calgary book1 ressource");
+    let (expected, expected_max) = simple_count_u8(&book1);
+
+    let mut hist = Histogram::new();
+    for block in book1.chunks(10_007) {
+        hist.add(block);
+    }
+    assert_eq!(hist.counts(), &expected[..=expected_max]);
+    assert_eq!(
+        (hist.total(), hist.max_symbol()),
+        (book1.len(), expected_max)
+    );
+    assert_eq!(hist, Histogram::from_source(&book1));
+
+    let (head, tail) = book1.split_at(300_000);
+    let mut merged = Histogram::from_source(head);
+    merged.merge(&Histogram::from_source(tail));
+    assert_eq!(merged, hist);
+    merged.subtract(&Histogram::from_source(tail)).unwrap();
+    assert_eq!(merged, Histogram::from_source(head));
+    let mut small = Histogram::from_source(b"aab");
+    assert_eq!(
+        small.subtract(&Histogram::from_source(b"abb")),
+        Err(CountError::NegativeCount(b'b' as usize))
+    );
+    assert_eq!(small, Histogram::from_source(b"aab"));
+
+    // L'estimation est proche du compte exact pour les symboles fréquents.
+    let estimate = Histogram::sampled(&book1, 8);
+    assert!(estimate.total().abs_diff(book1.len()) < 8);
+    let e = b'e' as usize;
+    assert!(estimate.count(b'e').abs_diff(expected[e]) < expected[e] / 20);
+
+    for hist in [hist, estimate] {
+        let max_symbol = hist.max_symbol();
+        for norm in [
+            fast_normalization_1(hist.counts(), 11).unwrap(),
+            normalization_with_fast_compensation(hist.counts(), 11).unwrap(),
+            normalization_with_compensation_binary_heap(hist.counts(), 11, max_symbol).unwrap(),
+        ] {
+            assert_eq!(norm.iter().sum::<usize>(), 1 << 11);
+        }
+        let mut counts = hist.counts().to_vec();
+        zstd_normalization_1_inplace(&mut counts, 11, max_symbol).unwrap();
+        assert_eq!(counts.iter().sum::<usize>(), 1 << 11);
+    }
+    // La normalisation lente divise des entiers, elle ne fonctionne qu'avec
+    // un total plus petit que la table.
+    let small = Histogram::from_source(b"abracadabra");
+    let norm = slow_normalization(small.counts(), 4).unwrap();
+    assert_eq!(norm.iter().sum::<usize>(), 1 << 4);
+    // Un compte égal à la taille de la tranche n'est pas un cas de RLE.
+    let square = Histogram::from_source(b"\x00\x01\x01");
+    assert_eq!(square.counts(), [1, 2]);
+    for norm in [
+        fast_normalization_1(square.counts(), 5).unwrap(),
+        normalization_with_fast_compensation(square.counts(), 5).unwrap(),
+    ] {
+        assert_eq!(norm.iter().sum::<usize>(), 1 << 5);
+    }
+}
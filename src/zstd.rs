@@ -0,0 +1,861 @@
+//! Zstandard (RFC 8878) frame decoder.
+//!
+//! This file is a part of `final_state_rs`.
+//!
+//! License: MIT or BSD
+//! Author: Adrien Zinger <zinger.ad@gmail.com>
+//!
+//! ---
+//!
+//! A zstd frame is a list of blocks. A compressed block contains a section of
+//! literals, compressed with a Huffman code, and a section of sequences. Each
+//! sequence copies some literals and then a match, like the pairs of
+//! `lempel_ziv`. The literal lengths, the match lengths and the offsets are
+//! coded with three interleaved FSE (tANS) decoders.
+//!
+//! The FSE tables are built with the tools of this crate: `zstd_spread` for
+//! the spread of the symbols, and `t_ans::build_decode_table` for the number
+//! of bits and the next state of each state. The normalized histograms come
+//! from the block, or from the predefined distributions of the format.
+//!
+//! The Huffman and FSE streams are read backward, from the last byte, which
+//! contains a padding mark, to the first one. This file has its own backward
+//! bit reader: contrary to `bitstream`, the Huffman decoder needs to read
+//! some zeros past the beginning of a stream.
+//!
+//! Dictionaries are not supported.
+
+use crate::checksum::xxh64;
+use crate::spreads::zstd_spread;
+use crate::t_ans::build_decode_table;
+
+const MAGIC: u32 = 0xFD2FB528;
+/// Magic of the skippable frames, the 4 lower bits are free.
+const SKIPPABLE_MAGIC: u32 = 0x184D2A50;
+/// Maximum size of a block, compressed or not.
+const MAX_BLOCK_SIZE: usize = 128 << 10;
+/// Maximum number of bits of a Huffman code of the literals.
+const HUFFMAN_MAX_BITS: u32 = 11;
+
+const LL_BASE: [usize; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64,
+    128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+const LL_BITS: [u32; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16,
+];
+const ML_BASE: [usize; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    28, 29, 30, 31, 32, 33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027,
+    2051, 4099, 8195, 16387, 32771, 65539,
+];
+const ML_BITS: [u32; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+/// Greatest offset code, the offset is read on as many bits.
+const MAX_OF_CODE: usize = 31;
+
+/// Predefined distributions of the literal lengths, match lengths and offset
+/// codes, with their accuracy log.
+const LL_DEFAULT_NORM: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+];
+const ML_DEFAULT_NORM: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OF_DEFAULT_NORM: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+const LL_DEFAULT_LOG: usize = 6;
+const ML_DEFAULT_LOG: usize = 6;
+const OF_DEFAULT_LOG: usize = 5;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ZstdError {
+    /// The stream ends before the end of a frame.
+    Truncated,
+    /// The frame doesn't start with a zstd or a skippable magic number.
+    InvalidMagic(u32),
+    /// The frame header uses a reserved bit.
+    InvalidHeader,
+    /// The frame needs the dictionary with this id.
+    DictionaryUnsupported(u32),
+    /// The block type 3 is reserved.
+    ReservedBlockType,
+    /// A block is larger than 128 KiB.
+    InvalidBlockSize,
+    /// The weights of the Huffman code of the literals are invalid.
+    InvalidHuffmanTable,
+    /// The normalized histogram of an FSE table is invalid.
+    InvalidFseTable,
+    /// A block reuses a table that wasn't defined by a previous block.
+    MissingTable,
+    /// A bit stream or a section doesn't match the sizes given by the block.
+    Corrupted,
+    /// An offset that goes before the beginning of the frame.
+    InvalidOffset(usize),
+    /// The checksum of the frame doesn't match the decoded data.
+    ChecksumMismatch,
+    /// The content size of the frame header doesn't match the decoded data.
+    SizeMismatch,
+    /// The decoded stream would be greater than the maximum size.
+    TooLarge,
+}
+
+/// Little-endian integer of `size` bytes at the beginning of `src`.
+fn read_le(src: &[u8], size: usize) -> Result<u64, ZstdError> {
+    let bytes = src.get(..size).ok_or(ZstdError::Truncated)?;
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0, |acc, byte| (acc << 8) | *byte as u64))
+}
+
+/* *************************************************************************
+  Backward bit reader
+*/
+
+/// Read a stream from its end. The highest bit set of the last byte is the
+/// mark of the end, the bits are read from the most significant to the least
+/// significant.
+struct BackwardBitReader<'a> {
+    src: &'a [u8],
+    /// Number of bits not read yet, negative if we have read past the
+    /// beginning of the stream (these bits are zeros).
+    bits: isize,
+}
+
+impl<'a> BackwardBitReader<'a> {
+    fn new(src: &'a [u8]) -> Result<Self, ZstdError> {
+        match src.last() {
+            Some(&last) if last != 0 => Ok(BackwardBitReader {
+                src,
+                bits: (src.len() * 8) as isize - last.leading_zeros() as isize - 1,
+            }),
+            _ => Err(ZstdError::Corrupted),
+        }
+    }
+
+    /// Read at most 56 bits.
+    #[inline]
+    fn read(&mut self, nb_bits: u32) -> usize {
+        let end = self.bits;
+        let start = end - nb_bits as isize;
+        self.bits = start;
+        if end <= 0 || nb_bits == 0 {
+            return 0;
+        }
+        let low = start.max(0) as usize;
+        let byte = low / 8;
+        let mut buf = [0; 8];
+        let available = std::cmp::min(8, self.src.len() - byte);
+        buf[..available].copy_from_slice(&self.src[byte..byte + available]);
+        let value = (u64::from_le_bytes(buf) >> (low % 8)) & ((1 << (end as usize - low)) - 1);
+        (value << (low as isize - start)) as usize
+    }
+}
+
+/* *************************************************************************
+  FSE tables
+*/
+
+struct FseTable {
+    table_log: usize,
+    symbols: Vec<u8>,
+    nb_bits: Vec<usize>,
+    new_state: Vec<usize>,
+}
+
+impl FseTable {
+    /// Build the decoding table of a normalized histogram, where `-1` is the
+    /// probability "less than one state".
+    fn new(norm: &[i16], table_log: usize) -> Self {
+        let symbols = zstd_spread(norm, table_log);
+        let hist = norm
+            .iter()
+            .map(|count| count.unsigned_abs() as usize)
+            .collect::<Vec<usize>>();
+        let (nb_bits, new_state) = build_decode_table(table_log, &symbols, &hist);
+        FseTable {
+            table_log,
+            symbols,
+            nb_bits,
+            new_state,
+        }
+    }
+
+    /// A table with only one symbol, no bit is read from the stream.
+    fn rle(symbol: u8) -> Self {
+        let mut norm = vec![0; symbol as usize + 1];
+        norm[symbol as usize] = 1;
+        FseTable::new(&norm, 0)
+    }
+
+    #[inline]
+    fn init(&self, stream: &mut BackwardBitReader) -> usize {
+        stream.read(self.table_log as u32)
+    }
+
+    #[inline]
+    fn update(&self, state: usize, stream: &mut BackwardBitReader) -> usize {
+        self.new_state[state] + stream.read(self.nb_bits[state] as u32)
+    }
+
+    /// Read the description of a table at the beginning of `src`, return the
+    /// table and the number of bytes used.
+    fn read(src: &[u8], max_symbol: usize, max_log: usize) -> Result<(Self, usize), ZstdError> {
+        // Forward stream, least significant bits first.
+        let peek = |pos: usize, nb_bits: usize| -> i32 {
+            let byte = pos / 8;
+            let mut buf = [0; 4];
+            if byte < src.len() {
+                let available = std::cmp::min(4, src.len() - byte);
+                buf[..available].copy_from_slice(&src[byte..byte + available]);
+            }
+            ((u32::from_le_bytes(buf) >> (pos % 8)) & ((1 << nb_bits) - 1)) as i32
+        };
+        let table_log = peek(0, 4) as usize + 5;
+        if table_log > max_log {
+            return Err(ZstdError::InvalidFseTable);
+        }
+        let mut pos = 4;
+        let mut norm: Vec<i16> = vec![];
+        // Les probabilités restantes (plus un), elles sont écrites sur le
+        // nombre de bits juste nécessaire, et les plus petites valeurs sur un
+        // bit de moins.
+        let mut remaining = (1 << table_log) + 1;
+        let mut threshold = 1 << table_log;
+        let mut nb_bits = table_log + 1;
+        while remaining > 1 {
+            if norm.len() > max_symbol {
+                return Err(ZstdError::InvalidFseTable);
+            }
+            let max = 2 * threshold - 1 - remaining;
+            let mut value = peek(pos, nb_bits - 1);
+            if value < max {
+                pos += nb_bits - 1;
+            } else {
+                value = peek(pos, nb_bits);
+                if value >= threshold {
+                    value -= max;
+                }
+                pos += nb_bits;
+            }
+            let count = value - 1;
+            remaining -= count.abs();
+            norm.push(count as i16);
+            if count == 0 {
+                // Chaque drapeau de 2 bits ajoute des probabilités nulles,
+                // 3 annonce un autre drapeau.
+                loop {
+                    let repeat = peek(pos, 2);
+                    pos += 2;
+                    norm.extend(std::iter::repeat_n(0, repeat as usize));
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+            if remaining < 1 {
+                return Err(ZstdError::InvalidFseTable);
+            }
+            while remaining < threshold {
+                nb_bits -= 1;
+                threshold >>= 1;
+            }
+        }
+        let used = pos.div_ceil(8);
+        if used > src.len() {
+            return Err(ZstdError::Truncated);
+        }
+        if norm.len() > max_symbol + 1 {
+            return Err(ZstdError::InvalidFseTable);
+        }
+        Ok((FseTable::new(&norm, table_log), used))
+    }
+}
+
+/* *************************************************************************
+  Huffman tables of the literals
+*/
+
+struct HuffmanTable {
+    max_bits: u32,
+    /// Symbol and length of the code of each prefix of `max_bits` bits.
+    symbols: Vec<u8>,
+    nb_bits: Vec<u8>,
+}
+
+impl HuffmanTable {
+    /// Read the description of a table at the beginning of `src`, return the
+    /// table and the number of bytes used.
+    fn read(src: &[u8]) -> Result<(Self, usize), ZstdError> {
+        let header = *src.first().ok_or(ZstdError::Truncated)? as usize;
+        let (mut weights, used) = if header < 128 {
+            // Weights compressed with FSE, two states share the stream.
+            let data = src.get(1..1 + header).ok_or(ZstdError::Truncated)?;
+            let (table, table_size) = FseTable::read(data, 255, 6)?;
+            let mut stream = BackwardBitReader::new(&data[table_size..])?;
+            let mut states = [table.init(&mut stream), table.init(&mut stream)];
+            let mut weights = vec![];
+            let mut current = 0;
+            loop {
+                weights.push(table.symbols[states[current]]);
+                states[current] = table.update(states[current], &mut stream);
+                if stream.bits < 0 {
+                    weights.push(table.symbols[states[1 - current]]);
+                    break;
+                }
+                if weights.len() > 255 {
+                    return Err(ZstdError::InvalidHuffmanTable);
+                }
+                current = 1 - current;
+            }
+            (weights, 1 + header)
+        } else {
+            // Weights written directly on 4 bits.
+            let nb_weights = header - 127;
+            let data = src
+                .get(1..1 + nb_weights.div_ceil(2))
+                .ok_or(ZstdError::Truncated)?;
+            let weights = (0..nb_weights)
+                .map(|i| match i % 2 {
+                    0 => data[i / 2] >> 4,
+                    _ => data[i / 2] & 0xf,
+                })
+                .collect::<Vec<u8>>();
+            (weights, 1 + nb_weights.div_ceil(2))
+        };
+        if weights.len() > 255 || weights.iter().any(|w| *w as u32 > HUFFMAN_MAX_BITS) {
+            return Err(ZstdError::InvalidHuffmanTable);
+        }
+
+        // The weight of the last symbol completes the sum to the next power
+        // of two.
+        let sum: u32 = weights
+            .iter()
+            .filter(|w| **w > 0)
+            .map(|w| 1 << (w - 1))
+            .sum();
+        if sum == 0 {
+            return Err(ZstdError::InvalidHuffmanTable);
+        }
+        let max_bits = u32::BITS - sum.leading_zeros();
+        let left = (1 << max_bits) - sum;
+        if max_bits > HUFFMAN_MAX_BITS || !left.is_power_of_two() {
+            return Err(ZstdError::InvalidHuffmanTable);
+        }
+        weights.push((left.trailing_zeros() + 1) as u8);
+
+        let code_bits = weights
+            .iter()
+            .map(|w| match w {
+                0 => 0,
+                w => (max_bits + 1 - *w as u32) as u8,
+            })
+            .collect::<Vec<u8>>();
+        // The longest codes come first, then for each length the symbols in
+        // increasing order.
+        let mut rank_start = vec![0; max_bits as usize + 2];
+        for bits in code_bits.iter().filter(|b| **b > 0) {
+            rank_start[*bits as usize] += 1 << (max_bits - *bits as u32);
+        }
+        let mut start = 0;
+        for bits in (1..=max_bits as usize).rev() {
+            let count = rank_start[bits];
+            rank_start[bits] = start;
+            start += count;
+        }
+        let table_size = 1 << max_bits;
+        let mut symbols = vec![0; table_size];
+        let mut nb_bits = vec![0; table_size];
+        for (symbol, bits) in code_bits.iter().enumerate().filter(|(_, b)| **b > 0) {
+            let start = rank_start[*bits as usize];
+            let len = 1 << (max_bits - *bits as u32);
+            symbols[start..start + len].fill(symbol as u8);
+            nb_bits[start..start + len].fill(*bits);
+            rank_start[*bits as usize] += len;
+        }
+        Ok((
+            HuffmanTable {
+                max_bits,
+                symbols,
+                nb_bits,
+            },
+            used,
+        ))
+    }
+
+    /// Decode `count` literals of a stream.
+    fn decode_stream(&self, src: &[u8], count: usize, ret: &mut Vec<u8>) -> Result<(), ZstdError> {
+        let mut stream = BackwardBitReader::new(src)?;
+        let mask = (1 << self.max_bits) - 1;
+        let mut state = stream.read(self.max_bits);
+        for _ in 0..count {
+            ret.push(self.symbols[state]);
+            let nb_bits = self.nb_bits[state] as u32;
+            state = ((state << nb_bits) | stream.read(nb_bits)) & mask;
+        }
+        // The state has read `max_bits` in advance.
+        if stream.bits != -(self.max_bits as isize) {
+            return Err(ZstdError::Corrupted);
+        }
+        Ok(())
+    }
+}
+
+/* *************************************************************************
+  Frames and blocks
+*/
+
+/// State kept between the blocks of a frame.
+struct FrameDecoder {
+    out: Vec<u8>,
+    /// Last three offsets.
+    reps: [usize; 3],
+    huffman: Option<HuffmanTable>,
+    /// Tables of the literal lengths, offsets and match lengths.
+    tables: [Option<FseTable>; 3],
+}
+
+impl FrameDecoder {
+    fn decode_block(&mut self, src: &[u8]) -> Result<(), ZstdError> {
+        let (literals, used) = self.decode_literals(src)?;
+        self.decode_sequences(&src[used..], &literals)
+    }
+
+    /// Decode the literals section, return the literals and the size of the
+    /// section.
+    fn decode_literals(&mut self, src: &[u8]) -> Result<(Vec<u8>, usize), ZstdError> {
+        let first = *src.first().ok_or(ZstdError::Truncated)? as usize;
+        let size_format = (first >> 2) & 3;
+        match first & 3 {
+            // Raw and RLE literals
+            block_type @ (0 | 1) => {
+                let (regenerated, header) = match size_format {
+                    0 | 2 => (first >> 3, 1),
+                    1 => ((read_le(src, 2)? >> 4) as usize, 2),
+                    _ => ((read_le(src, 3)? >> 4) as usize, 3),
+                };
+                if regenerated > MAX_BLOCK_SIZE {
+                    return Err(ZstdError::Corrupted);
+                }
+                if block_type == 0 {
+                    let literals = src
+                        .get(header..header + regenerated)
+                        .ok_or(ZstdError::Truncated)?;
+                    Ok((literals.to_vec(), header + regenerated))
+                } else {
+                    let byte = *src.get(header).ok_or(ZstdError::Truncated)?;
+                    Ok((vec![byte; regenerated], header + 1))
+                }
+            }
+            // Compressed literals, with a new Huffman table or the previous
+            // one.
+            block_type => {
+                let (header, bits) = match size_format {
+                    0 | 1 => (3, 10),
+                    2 => (4, 14),
+                    _ => (5, 18),
+                };
+                let value = read_le(src, header)? >> 4;
+                let mask = (1 << bits) - 1;
+                let regenerated = (value & mask) as usize;
+                let compressed = ((value >> bits) & mask) as usize;
+                if regenerated > MAX_BLOCK_SIZE {
+                    return Err(ZstdError::Corrupted);
+                }
+                let mut data = src
+                    .get(header..header + compressed)
+                    .ok_or(ZstdError::Truncated)?;
+                if block_type == 2 {
+                    let (table, used) = HuffmanTable::read(data)?;
+                    self.huffman = Some(table);
+                    data = data.get(used..).ok_or(ZstdError::Corrupted)?;
+                }
+                let table = self.huffman.as_ref().ok_or(ZstdError::MissingTable)?;
+                let mut literals = Vec::with_capacity(regenerated);
+                if size_format == 0 {
+                    table.decode_stream(data, regenerated, &mut literals)?;
+                } else {
+                    // Four streams, the sizes of the three first ones are in
+                    // a jump table.
+                    let jump = data.get(..6).ok_or(ZstdError::Corrupted)?;
+                    let mut sizes = [0; 4];
+                    for (i, size) in sizes.iter_mut().take(3).enumerate() {
+                        *size = u16::from_le_bytes([jump[2 * i], jump[2 * i + 1]]) as usize;
+                    }
+                    sizes[3] = (data.len() - 6)
+                        .checked_sub(sizes.iter().sum())
+                        .ok_or(ZstdError::Corrupted)?;
+                    let segment = regenerated.div_ceil(4);
+                    let last = regenerated
+                        .checked_sub(3 * segment)
+                        .ok_or(ZstdError::Corrupted)?;
+                    let mut start = 6;
+                    for (i, size) in sizes.iter().enumerate() {
+                        let count = if i == 3 { last } else { segment };
+                        table.decode_stream(&data[start..start + size], count, &mut literals)?;
+                        start += size;
+                    }
+                }
+                Ok((literals, header + compressed))
+            }
+        }
+    }
+
+    /// Decode the sequences section and execute the sequences.
+    fn decode_sequences(&mut self, src: &[u8], literals: &[u8]) -> Result<(), ZstdError> {
+        let first = *src.first().ok_or(ZstdError::Truncated)? as usize;
+        let (nb_sequences, mut pos) = match first {
+            0..=127 => (first, 1),
+            128..=254 => (((first - 128) << 8) + read_le(&src[1..], 1)? as usize, 2),
+            _ => (read_le(&src[1..], 2)? as usize + 0x7F00, 3),
+        };
+        if nb_sequences == 0 {
+            if pos != src.len() {
+                return Err(ZstdError::Corrupted);
+            }
+            self.out.extend_from_slice(literals);
+            return Ok(());
+        }
+
+        let modes = *src.get(pos).ok_or(ZstdError::Truncated)? as usize;
+        pos += 1;
+        if modes & 3 != 0 {
+            return Err(ZstdError::Corrupted);
+        }
+        let kinds: [(&[i16], usize, usize, usize); 3] = [
+            (&LL_DEFAULT_NORM, LL_DEFAULT_LOG, 35, 9),
+            (&OF_DEFAULT_NORM, OF_DEFAULT_LOG, MAX_OF_CODE, 8),
+            (&ML_DEFAULT_NORM, ML_DEFAULT_LOG, 52, 9),
+        ];
+        for (i, (norm, log, max_symbol, max_log)) in kinds.into_iter().enumerate() {
+            let table = match (modes >> (6 - 2 * i)) & 3 {
+                0 => FseTable::new(norm, log),
+                1 => {
+                    let symbol = *src.get(pos).ok_or(ZstdError::Truncated)?;
+                    pos += 1;
+                    if symbol as usize > max_symbol {
+                        return Err(ZstdError::InvalidFseTable);
+                    }
+                    FseTable::rle(symbol)
+                }
+                2 => {
+                    let (table, used) = FseTable::read(&src[pos..], max_symbol, max_log)?;
+                    pos += used;
+                    table
+                }
+                _ => match self.tables[i].take() {
+                    Some(table) => table,
+                    None => return Err(ZstdError::MissingTable),
+                },
+            };
+            self.tables[i] = Some(table);
+        }
+        let [Some(ll_table), Some(of_table), Some(ml_table)] = &self.tables else {
+            unreachable!()
+        };
+
+        let mut stream = BackwardBitReader::new(src.get(pos..).ok_or(ZstdError::Truncated)?)?;
+        let mut ll_state = ll_table.init(&mut stream);
+        let mut of_state = of_table.init(&mut stream);
+        let mut ml_state = ml_table.init(&mut stream);
+        let mut literal = 0;
+        let block_start = self.out.len();
+        for i in 0..nb_sequences {
+            let ll_code = ll_table.symbols[ll_state] as usize;
+            let of_code = of_table.symbols[of_state] as u32;
+            let ml_code = ml_table.symbols[ml_state] as usize;
+            let offset_value = (1 << of_code) + stream.read(of_code);
+            let match_len = ML_BASE[ml_code] + stream.read(ML_BITS[ml_code]);
+            let literal_len = LL_BASE[ll_code] + stream.read(LL_BITS[ll_code]);
+            if i + 1 < nb_sequences {
+                ll_state = ll_table.update(ll_state, &mut stream);
+                ml_state = ml_table.update(ml_state, &mut stream);
+                of_state = of_table.update(of_state, &mut stream);
+            }
+
+            let copied = literals
+                .get(literal..literal + literal_len)
+                .ok_or(ZstdError::Corrupted)?;
+            self.out.extend_from_slice(copied);
+            literal += literal_len;
+
+            let offset = resolve_offset(&mut self.reps, offset_value, literal_len);
+            if offset == 0 || offset > self.out.len() {
+                return Err(ZstdError::InvalidOffset(offset));
+            }
+            if self.out.len() - block_start + match_len > MAX_BLOCK_SIZE {
+                return Err(ZstdError::Corrupted);
+            }
+            let start = self.out.len() - offset;
+            for i in start..start + match_len {
+                self.out.push(self.out[i]);
+            }
+        }
+        if stream.bits != 0 {
+            return Err(ZstdError::Corrupted);
+        }
+        self.out.extend_from_slice(&literals[literal..]);
+        Ok(())
+    }
+}
+
+/// Offset of a sequence, the values 1 to 3 are the last offsets used. When
+/// there is no literal before the match, the first one is skipped (it would
+/// have extended the previous match) and 3 is the last offset minus one.
+fn resolve_offset(reps: &mut [usize; 3], offset_value: usize, literal_len: usize) -> usize {
+    if offset_value > 3 {
+        let offset = offset_value - 3;
+        *reps = [offset, reps[0], reps[1]];
+        return offset;
+    }
+    let rank = offset_value - 1 + (literal_len == 0) as usize;
+    if rank == 0 {
+        return reps[0];
+    }
+    let offset = if rank == 3 {
+        reps[0].wrapping_sub(1)
+    } else {
+        reps[rank]
+    };
+    if rank > 1 {
+        reps[2] = reps[1];
+    }
+    reps[1] = reps[0];
+    reps[0] = offset;
+    offset
+}
+
+/// Decode a frame after its magic number, return the number of bytes used.
+/// The decoded frame is at most `max_size` bytes.
+fn decode_frame(src: &[u8], ret: &mut Vec<u8>, max_size: usize) -> Result<usize, ZstdError> {
+    let descriptor = *src.first().ok_or(ZstdError::Truncated)? as usize;
+    if descriptor & 0x08 != 0 {
+        return Err(ZstdError::InvalidHeader);
+    }
+    let single_segment = (descriptor >> 5) & 1;
+    let checksum = (descriptor >> 2) & 1 == 1;
+    // The window descriptor isn't needed, the whole frame stays in memory.
+    let mut pos = 2 - single_segment;
+    let dict_size = [0, 1, 2, 4][descriptor & 3];
+    let dict_id = read_le(src.get(pos..).ok_or(ZstdError::Truncated)?, dict_size)? as u32;
+    if dict_id != 0 {
+        return Err(ZstdError::DictionaryUnsupported(dict_id));
+    }
+    pos += dict_size;
+    let size_bytes = [single_segment, 2, 4, 8][descriptor >> 6];
+    let content_size = read_le(src.get(pos..).ok_or(ZstdError::Truncated)?, size_bytes)?;
+    let content_size = match size_bytes {
+        0 => None,
+        2 => Some(content_size + 256),
+        _ => Some(content_size),
+    };
+    if content_size.is_some_and(|size| size > max_size as u64) {
+        return Err(ZstdError::TooLarge);
+    }
+    pos += size_bytes;
+
+    let mut frame = FrameDecoder {
+        out: vec![],
+        reps: [1, 4, 8],
+        huffman: None,
+        tables: [None, None, None],
+    };
+    loop {
+        let header = read_le(src.get(pos..).ok_or(ZstdError::Truncated)?, 3)? as usize;
+        pos += 3;
+        let size = header >> 3;
+        if size > MAX_BLOCK_SIZE {
+            return Err(ZstdError::InvalidBlockSize);
+        }
+        // Un bloc compressé fait au plus 128 KiB décodé, il est vérifié après.
+        if header & 6 != 4 && frame.out.len() + size > max_size {
+            return Err(ZstdError::TooLarge);
+        }
+        match (header >> 1) & 3 {
+            0 => {
+                let block = src.get(pos..pos + size).ok_or(ZstdError::Truncated)?;
+                frame.out.extend_from_slice(block);
+                pos += size;
+            }
+            1 => {
+                let byte = *src.get(pos).ok_or(ZstdError::Truncated)?;
+                frame.out.extend(std::iter::repeat_n(byte, size));
+                pos += 1;
+            }
+            2 => {
+                let block = src.get(pos..pos + size).ok_or(ZstdError::Truncated)?;
+                frame.decode_block(block)?;
+                if frame.out.len() > max_size {
+                    return Err(ZstdError::TooLarge);
+                }
+                pos += size;
+            }
+            _ => return Err(ZstdError::ReservedBlockType),
+        }
+        if header & 1 == 1 {
+            break;
+        }
+    }
+
+    if content_size.is_some_and(|size| size != frame.out.len() as u64) {
+        return Err(ZstdError::SizeMismatch);
+    }
+    if checksum {
+        let expected = read_le(src.get(pos..).ok_or(ZstdError::Truncated)?, 4)? as u32;
+        if expected != xxh64(&frame.out, 0) as u32 {
+            return Err(ZstdError::ChecksumMismatch);
+        }
+        pos += 4;
+    }
+    ret.append(&mut frame.out);
+    Ok(pos)
+}
+
+/// Decode a zstd stream: one or more frames, the skippable frames are
+/// ignored.
+///
+/// ```
+/// use final_state_rs::zstd::*;
+///
+/// // A frame with a single RLE block of 5 'a'.
+/// let src = [
+///     0x28, 0xb5, 0x2f, 0xfd, 0x20, 0x05, 0x2b, 0x00, 0x00, 0x61,
+/// ];
+/// assert_eq!(decode_zstd(&src).unwrap(), b"aaaaa");
+/// ```
+pub fn decode_zstd(src: &[u8]) -> Result<Vec<u8>, ZstdError> {
+    decode_zstd_with_max_size(src, usize::MAX)
+}
+
+/// Decode a zstd stream, fails with `ZstdError::TooLarge` if the decoded
+/// stream is greater than `max_size`. A RLE block of 3 bytes decodes to
+/// 128 KiB, a stream from an untrusted source should be decoded with a bound.
+///
+/// ```
+/// use final_state_rs::zstd::*;
+///
+/// let src = [
+///     0x28, 0xb5, 0x2f, 0xfd, 0x20, 0x05, 0x2b, 0x00, 0x00, 0x61,
+/// ];
+/// assert_eq!(decode_zstd_with_max_size(&src, 5).unwrap(), b"aaaaa");
+/// assert_eq!(decode_zstd_with_max_size(&src, 4), Err(ZstdError::TooLarge));
+/// ```
+pub fn decode_zstd_with_max_size(src: &[u8], max_size: usize) -> Result<Vec<u8>, ZstdError> {
+    let mut ret = vec![];
+    let mut pos = 0;
+    loop {
+        let magic = read_le(src.get(pos..).ok_or(ZstdError::Truncated)?, 4)? as u32;
+        pos += 4;
+        if magic & 0xFFFFFFF0 == SKIPPABLE_MAGIC {
+            let size = read_le(src.get(pos..).ok_or(ZstdError::Truncated)?, 4)? as usize;
+            pos += 4 + size;
+            if pos > src.len() {
+                return Err(ZstdError::Truncated);
+            }
+        } else if magic == MAGIC {
+            let remaining = max_size - ret.len();
+            pos += decode_frame(&src[pos..], &mut ret, remaining)?;
+        } else {
+            return Err(ZstdError::InvalidMagic(magic));
+        }
+        if pos == src.len() {
+            return Ok(ret);
+        }
+    }
+}
+
+#[test]
+fn zstd_reference_files() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+    let records = read_rsc("./rsc/lz4/records");
+    let random = read_rsc("./rsc/deflate/random");
+    let rle = vec![b'z'; 100_000];
+
+    // Generated with the zstd cli, at different levels, with and without
+    // checksum, and from stdin for `rle` (no content size).
+    for (file, expected) in [
+        ("./rsc/zstd/book1_20k_1.zst", &book1[..20000]),
+        ("./rsc/zstd/book1_20k_19.zst", &book1[..20000]),
+        ("./rsc/zstd/book1_3.zst", &book1),
+        ("./rsc/zstd/records.zst", &records),
+        ("./rsc/zstd/random.zst", &random),
+        ("./rsc/zstd/rle.zst", &rle),
+        ("./rsc/zstd/empty.zst", &[]),
+    ] {
+        assert_eq!(decode_zstd(&read_rsc(file)).unwrap(), expected, "{file}");
+    }
+}
+
+#[test]
+fn zstd_frames_and_errors() {
+    use crate::tests::read_rsc;
+    let book1 = read_rsc("./rsc/calgary_book1");
+    let frame = read_rsc("./rsc/zstd/book1_20k_1.zst");
+
+    // Two frames around a skippable frame.
+    let skippable = [0x5a, 0x2a, 0x4d, 0x18, 3, 0, 0, 0, 1, 2, 3];
+    let src = [&frame[..], &skippable, &frame].concat();
+    assert_eq!(
+        decode_zstd(&src).unwrap(),
+        [&book1[..20000], &book1[..20000]].concat()
+    );
+
+    assert_eq!(decode_zstd(&[]), Err(ZstdError::Truncated));
+    assert_eq!(
+        decode_zstd(&frame[..frame.len() - 1]),
+        Err(ZstdError::Truncated)
+    );
+    assert_eq!(
+        decode_zstd(&frame[..frame.len() / 2]),
+        Err(ZstdError::Truncated)
+    );
+    assert_eq!(
+        decode_zstd(&[0x1f, 0x8b, 8, 0]),
+        Err(ZstdError::InvalidMagic(0x00088b1f))
+    );
+    let mut corrupted = frame.clone();
+    let len = corrupted.len();
+    corrupted[len - 1] ^= 1;
+    assert_eq!(decode_zstd(&corrupted), Err(ZstdError::ChecksumMismatch));
+
+    // Single segment frame of 5 bytes, with a reserved block type.
+    let reserved = [0x28, 0xb5, 0x2f, 0xfd, 0x20, 0x05, 0x2f, 0x00, 0x00, 0x61];
+    assert_eq!(decode_zstd(&reserved), Err(ZstdError::ReservedBlockType));
+    // Same frame with a dictionary id.
+    let dict = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x21, 0x07, 0x05, 0x2b, 0x00, 0x00, 0x61,
+    ];
+    assert_eq!(decode_zstd(&dict), Err(ZstdError::DictionaryUnsupported(7)));
+    // A content size that doesn't match.
+    let size = [0x28, 0xb5, 0x2f, 0xfd, 0x20, 0x06, 0x2b, 0x00, 0x00, 0x61];
+    assert_eq!(decode_zstd(&size), Err(ZstdError::SizeMismatch));
+
+    // The maximum size bounds the whole stream, with or without content size.
+    assert_eq!(decode_zstd_with_max_size(&src, 40000).unwrap().len(), 40000);
+    assert_eq!(
+        decode_zstd_with_max_size(&src, 39999),
+        Err(ZstdError::TooLarge)
+    );
+    let rle = read_rsc("./rsc/zstd/rle.zst");
+    assert_eq!(
+        decode_zstd_with_max_size(&rle, 100_000).unwrap(),
+        [b'z'; 100_000]
+    );
+    assert_eq!(
+        decode_zstd_with_max_size(&rle, 99_999),
+        Err(ZstdError::TooLarge)
+    );
+    let book1_3 = read_rsc("./rsc/zstd/book1_3.zst");
+    assert_eq!(
+        decode_zstd_with_max_size(&book1_3, book1.len() - 1),
+        Err(ZstdError::TooLarge)
+    );
+}
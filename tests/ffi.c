@@ -0,0 +1,100 @@
+/* Round trip of a file with the C interface of final_state_rs, built and
+ * run by tests/ffi.rs. */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "final_state.h"
+
+#define TABLE_LOG 11
+#define MAX_LEN 100000
+
+static int failures = 0;
+
+#define CHECK(expr)                                                        \
+    do {                                                                   \
+        if (!(expr)) {                                                     \
+            printf("%s:%d: check failed: %s\n", __FILE__, __LINE__, #expr); \
+            failures++;                                                    \
+        }                                                                  \
+    } while (0)
+
+static void test_tans(const uint8_t *src, size_t len, const uint32_t *counts)
+{
+    uint8_t *dst = malloc(len);
+    uint8_t *decoded = malloc(len);
+    size_t dst_len = 0;
+    uint32_t state = 0;
+    FsTansTable *table = fs_tans_create();
+
+    CHECK(fs_tans_encode(table, src, len, dst, len, &dst_len, &state) == FS_NOT_BUILT);
+    CHECK(fs_tans_build(table, counts, 256, 2) == FS_INVALID_TABLE_LOG);
+    CHECK(fs_tans_build(table, NULL, 256, TABLE_LOG) == FS_NULL_POINTER);
+    CHECK(fs_tans_build(table, counts, 256, TABLE_LOG) == FS_OK);
+
+    CHECK(fs_tans_encode(table, src, len, dst, 16, &dst_len, &state) == FS_DST_TOO_SMALL);
+    CHECK(dst_len > 16 && dst_len < len);
+    CHECK(fs_tans_encode(table, src, len, dst, len, &dst_len, &state) == FS_OK);
+    CHECK(fs_tans_decode(table, dst, dst_len, state, decoded, len) == FS_OK);
+    CHECK(memcmp(src, decoded, len) == 0);
+
+    CHECK(fs_tans_decode(table, dst, 16, state, decoded, len) == FS_TRUNCATED);
+    CHECK(fs_tans_decode(table, dst, dst_len, 1 << TABLE_LOG, decoded, len) == FS_INVALID_STATE);
+    CHECK(fs_tans_decode(table, NULL, dst_len, state, decoded, len) == FS_NULL_POINTER);
+
+    fs_tans_free(table);
+    fs_tans_free(NULL);
+    free(dst);
+    free(decoded);
+}
+
+static void test_rans(const uint8_t *src, size_t len, const uint32_t *counts)
+{
+    size_t capacity = 2 * len + 64;
+    uint8_t *dst = malloc(capacity);
+    uint8_t *decoded = malloc(len);
+    size_t dst_len = 0;
+    uint32_t state = 0;
+    uint32_t rle[2] = {0, 10};
+    uint8_t unknown = 0;
+    FsRansTable *table = fs_rans_create();
+
+    CHECK(fs_rans_build(table, rle, 2, TABLE_LOG) == FS_INVALID_HISTOGRAM);
+    CHECK(fs_rans_build(table, counts, 256, TABLE_LOG) == FS_OK);
+
+    CHECK(fs_rans_encode(table, &unknown, 1, dst, capacity, &dst_len, &state) == FS_INVALID_SYMBOL);
+    CHECK(fs_rans_encode(table, src, len, dst, capacity, &dst_len, &state) == FS_OK);
+    CHECK(fs_rans_decode(table, dst, dst_len, state, decoded, len) == FS_OK);
+    CHECK(memcmp(src, decoded, len) == 0);
+
+    CHECK(fs_rans_decode(table, dst, 3, state, decoded, len) == FS_TRUNCATED);
+    CHECK(fs_rans_decode(table, dst, dst_len / 2, state, decoded, len) == FS_TRUNCATED);
+
+    fs_rans_free(table);
+    free(dst);
+    free(decoded);
+}
+
+int main(int argc, char **argv)
+{
+    static uint8_t src[MAX_LEN];
+    uint32_t counts[256] = {0};
+    size_t len, i;
+    FILE *file;
+
+    if (argc != 2 || !(file = fopen(argv[1], "rb"))) {
+        printf("usage: %s <file>\n", argv[0]);
+        return 1;
+    }
+    len = fread(src, 1, MAX_LEN, file);
+    fclose(file);
+    for (i = 0; i < len; i++)
+        counts[src[i]]++;
+
+    test_tans(src, len, counts);
+    test_rans(src, len, counts);
+    if (failures)
+        printf("%d failures\n", failures);
+    return failures != 0;
+}
@@ -0,0 +1,206 @@
+//! Tests of the C interface: the header `include/final_state.h` is generated
+//! from `src/ffi.rs`, and `tests/ffi.c` is compiled against it and a static
+//! build of the library.
+//!
+//! ```sh
+//! BLESS=1 cargo test --test ffi # rewrite the header
+//! ```
+#![cfg(feature = "std")]
+
+use std::{fs, path::Path, process::Command};
+
+const ROOT: &str = env!("CARGO_MANIFEST_DIR");
+
+/// C type of a Rust type of the interface.
+fn c_type(ty: &str) -> String {
+    let ty = ty.trim();
+    if let Some(ty) = ty.strip_prefix("*const ") {
+        return format!("const {} *", c_type(ty));
+    }
+    if let Some(ty) = ty.strip_prefix("*mut ") {
+        return format!("{} *", c_type(ty));
+    }
+    match ty {
+        "usize" => "size_t",
+        "u8" => "uint8_t",
+        "u32" => "uint32_t",
+        "i32" => "int32_t",
+        "" | "()" => "void",
+        ty => ty,
+    }
+    .to_string()
+}
+
+/// `type name`, without space after the star of a pointer.
+fn c_declaration(ty: &str, name: &str) -> String {
+    let ty = c_type(ty);
+    match ty.ends_with('*') {
+        true => format!("{ty}{name}"),
+        false => format!("{ty} {name}"),
+    }
+}
+
+fn c_comment(doc: &[String], indent: &str) -> String {
+    match doc {
+        [] => String::new(),
+        [line] => format!("{indent}/** {line} */\n"),
+        lines => {
+            let mut comment = format!("{indent}/**\n");
+            for line in lines {
+                match line.is_empty() {
+                    true => comment += &format!("{indent} *\n"),
+                    false => comment += &format!("{indent} * {line}\n"),
+                }
+            }
+            comment + &format!("{indent} */\n")
+        }
+    }
+}
+
+/// `FsError` -> `FS_ERROR`
+fn screaming(name: &str) -> String {
+    let mut screaming = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if i > 0 && c.is_uppercase() {
+            screaming.push('_');
+        }
+        screaming.push(c.to_ascii_uppercase());
+    }
+    screaming
+}
+
+/// Generate the header from the public items of `src/ffi.rs`: the constants,
+/// the enum of the errors, the opaque structures and the functions.
+fn generate_header(source: &str) -> String {
+    let mut header = String::from(
+        "/* Generated from src/ffi.rs by tests/ffi.rs, don't edit it. */\n\n\
+         #ifndef FINAL_STATE_H\n#define FINAL_STATE_H\n\n\
+         #include <stddef.h>\n#include <stdint.h>\n\n\
+         #ifdef __cplusplus\nextern \"C\" {\n#endif\n\n",
+    );
+    let mut doc = vec![];
+    let mut lines = source.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if let Some(line) = line.strip_prefix("///") {
+            doc.push(line.trim().to_string());
+            continue;
+        }
+        if line.starts_with("#[") {
+            continue;
+        }
+        if let Some(constant) = line.strip_prefix("pub const ") {
+            let (name, value) = constant.split_once(':').unwrap();
+            let value = value.split_once('=').unwrap().1.trim_end_matches(';');
+            header += &c_comment(&doc, "");
+            header += &format!("#define {name} {}\n\n", value.trim());
+        } else if let Some(name) = line.strip_prefix("pub enum ") {
+            let name = name.trim_end_matches(" {");
+            header += &c_comment(&doc, "");
+            header += &format!("typedef enum {name} {{\n");
+            let mut variant_doc = vec![];
+            for line in lines.by_ref().map(str::trim) {
+                if line == "}" {
+                    break;
+                }
+                match line.strip_prefix("///") {
+                    Some(doc) => variant_doc.push(doc.trim().to_string()),
+                    None => {
+                        let (variant, value) =
+                            line.trim_end_matches(',').split_once(" = ").unwrap();
+                        header += &c_comment(&variant_doc, "    ");
+                        header += &format!("    FS_{} = {value},\n", screaming(variant));
+                        variant_doc.clear();
+                    }
+                }
+            }
+            header += &format!("}} {name};\n\n");
+        } else if let Some(name) = line.strip_prefix("pub struct ") {
+            let name = name.trim_end_matches(" {");
+            header += &c_comment(&doc, "");
+            header += &format!("typedef struct {name} {name};\n\n");
+        } else if line.starts_with("pub extern \"C\" fn ")
+            || line.starts_with("pub unsafe extern \"C\" fn ")
+        {
+            let mut signature = line.to_string();
+            while !signature.ends_with('{') {
+                signature += lines.next().unwrap().trim();
+            }
+            let signature = signature.split_once(" fn ").unwrap().1;
+            let (name, rest) = signature.split_once('(').unwrap();
+            let (params, ret) = rest.rsplit_once(')').unwrap();
+            let ret = ret.trim_end_matches('{').trim().trim_start_matches("->");
+            let params = params
+                .split(',')
+                .filter(|param| !param.trim().is_empty())
+                .map(|param| {
+                    let (name, ty) = param.split_once(':').unwrap();
+                    c_declaration(ty, name.trim())
+                })
+                .collect::<Vec<_>>();
+            let params = match params.is_empty() {
+                true => "void".to_string(),
+                false => params.join(", "),
+            };
+            header += &c_comment(&doc, "");
+            header += &format!("{}({params});\n\n", c_declaration(ret, name));
+        }
+        doc.clear();
+    }
+    header + "#ifdef __cplusplus\n}\n#endif\n\n#endif /* FINAL_STATE_H */\n"
+}
+
+#[test]
+fn ffi_header_up_to_date() {
+    let source = fs::read_to_string(Path::new(ROOT).join("src/ffi.rs")).unwrap();
+    let header = generate_header(&source);
+    let path = Path::new(ROOT).join("include/final_state.h");
+    if std::env::var_os("BLESS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, &header).unwrap();
+    }
+    let committed = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        committed == header,
+        "include/final_state.h is out of date, run `BLESS=1 cargo test --test ffi`"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn ffi_c_program() {
+    let root = Path::new(ROOT);
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+    let status = Command::new(env!("CARGO"))
+        .args(["rustc", "--release", "--lib", "--crate-type", "staticlib"])
+        .arg("--manifest-path")
+        .arg(root.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .expect("Cannot run cargo");
+    assert!(status.success(), "Cannot build the static library");
+
+    let program = target_dir.join("ffi_test");
+    let status = Command::new(std::env::var("CC").unwrap_or("cc".into()))
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror"])
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg(root.join("tests/ffi.c"))
+        .arg(target_dir.join("release/libfinal_state_rs.a"))
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .status()
+        .expect("Cannot run the C compiler");
+    assert!(status.success(), "Cannot compile tests/ffi.c");
+
+    let output = Command::new(&program)
+        .arg(root.join("rsc/calgary_book1"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "tests/ffi.c failed:\n{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}